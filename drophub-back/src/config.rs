@@ -1,20 +1,268 @@
-use std::{net::SocketAddr, path::Path, time::Duration};
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
 use config as config_lib;
 use dotenv::dotenv;
-use mongodb::options::{Credential, ServerAddress};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct Config {
     pub server: ServerConfig,
-    pub mongodb: MongodbConfig,
+    pub storage: StorageConfig,
+    #[serde(default)]
+    pub limits: LimitsConfig,
+    #[serde(default)]
+    pub ttl: TtlConfig,
+    #[serde(default)]
+    pub relay: RelayConfig,
+    #[serde(default)]
+    pub passphrase: PassphraseConfig,
+    #[serde(default)]
+    pub invite_attempts: InviteAttemptsConfig,
+    #[serde(default)]
+    pub progress: ProgressConfig,
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct ServerConfig {
     pub bind_addr: SocketAddr,
-    pub secret: String,
+    /// HMAC secret of peer tokens. Used only if no token keys are configured.
+    #[serde(default)]
+    pub secret: Option<String>,
+    /// Asymmetric keys of peer tokens. New tokens are signed by the first key with
+    /// a private key, the rest only verify tokens signed before key rotation.
+    #[serde(default)]
+    pub token_keys: Vec<TokenKeyConfig>,
+    /// Takes address of the client from the last entry of `X-Forwarded-For` header.
+    /// Enable only behind a reverse proxy that sets the header.
+    #[serde(default)]
+    pub forwarded_for: bool,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct TokenKeyConfig {
+    /// Key id written to the `kid` header of signed tokens.
+    pub kid: String,
+    pub algorithm: TokenKeyAlgorithm,
+    /// Path to PEM encoded public key.
+    pub public_key: PathBuf,
+    /// Path to PEM encoded private key in PKCS#8 format.
+    #[serde(default)]
+    pub private_key: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenKeyAlgorithm {
+    Es256,
+    EdDsa,
+}
+
+/// Quotas of announced entities.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LimitsConfig {
+    /// Maximum number of entities in a room.
+    pub room_entities: usize,
+    /// Maximum total size of entities in a room, in bytes.
+    pub room_entities_size: usize,
+    /// Maximum number of entities announced by a peer.
+    pub peer_entities: usize,
+    /// Maximum total size of entities announced by a peer, in bytes.
+    pub peer_entities_size: usize,
+    /// Maximum number of peers in a room, also the default room capacity.
+    pub room_capacity: usize,
+    /// Maximum number of outstanding invites of a room, also the default.
+    pub room_invites: usize,
+    /// Maximum size of text entity content stored in the room, in bytes.
+    pub text_size: usize,
+    /// Maximum size of entity thumbnail, in bytes.
+    pub thumbnail_size: usize,
+    /// Maximum number of files in directory entity.
+    pub manifest_entries: usize,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            room_entities: 256,
+            room_entities_size: 64 * 1024 * 1024 * 1024,
+            peer_entities: 64,
+            peer_entities_size: 16 * 1024 * 1024 * 1024,
+            room_capacity: 16,
+            room_invites: 8,
+            text_size: 4 * 1024,
+            thumbnail_size: 16 * 1024,
+            manifest_entries: 1024,
+        }
+    }
+}
+
+/// Lifetimes of stored records and how often expired ones are removed.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TtlConfig {
+    #[serde(with = "humantime_serde")]
+    pub invite: Duration,
+    #[serde(with = "humantime_serde")]
+    pub room: Duration,
+    #[serde(with = "humantime_serde")]
+    pub peer: Duration,
+    #[serde(with = "humantime_serde")]
+    pub entity: Duration,
+    /// Lifetime of issued peer tokens. Peers must refresh tokens before they expire.
+    #[serde(with = "humantime_serde")]
+    pub token: Duration,
+    /// Time a peer can resume its closed subscription. The peer stays in its room meanwhile.
+    #[serde(with = "humantime_serde")]
+    pub session: Duration,
+    #[serde(with = "humantime_serde")]
+    pub sweep_interval: Duration,
+}
+
+impl Default for TtlConfig {
+    fn default() -> Self {
+        Self {
+            invite: Duration::from_secs(60 * 60),
+            room: Duration::from_secs(24 * 60 * 60),
+            peer: Duration::from_secs(24 * 60 * 60),
+            entity: Duration::from_secs(24 * 60 * 60),
+            token: Duration::from_secs(60 * 60),
+            session: Duration::from_secs(30),
+            sweep_interval: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Relaying of transfer frames through the server when peers can't connect directly.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RelayConfig {
+    pub enabled: bool,
+    /// Maximum size of a single frame, in bytes.
+    pub frame_size: usize,
+    /// Maximum number of bytes relayed in a room per second.
+    pub room_bandwidth: usize,
+    /// Maximum total number of bytes relayed in a room.
+    pub room_size: usize,
+    /// Number of frames buffered for a slow receiver before sender gets an error.
+    pub peer_buffer: usize,
+}
+
+impl Default for RelayConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            frame_size: 64 * 1024,
+            room_bandwidth: 1024 * 1024,
+            room_size: 4 * 1024 * 1024 * 1024,
+            peer_buffer: 64,
+        }
+    }
+}
+
+/// Protection of invite passphrases from guessing.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InviteAttemptsConfig {
+    /// Number of failed attempts of a client before backoff. Attempts are accounted per
+    /// peer and per IP address.
+    pub free_attempts: u32,
+    /// Delay after the first failed attempt beyond the free ones, doubled with every next one.
+    #[serde(with = "humantime_serde")]
+    pub backoff: Duration,
+    #[serde(with = "humantime_serde")]
+    pub max_backoff: Duration,
+    /// Number of failed attempts against an existing invite before it is removed, and of
+    /// a client before it is blocked for the maximum backoff.
+    pub invite_lockout: u32,
+}
+
+impl Default for InviteAttemptsConfig {
+    fn default() -> Self {
+        Self {
+            free_attempts: 3,
+            backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(5 * 60),
+            invite_lockout: 5,
+        }
+    }
+}
+
+/// Publishing of transfer progress reported by peers.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProgressConfig {
+    /// Minimum time between published updates of a transfer. More frequent reports are dropped.
+    #[serde(with = "humantime_serde")]
+    pub interval: Duration,
+}
+
+impl Default for ProgressConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_millis(500),
+        }
+    }
+}
+
+/// Format of generated invite passphrases.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "kind")]
+pub enum PassphraseConfig {
+    /// Random characters of the charset.
+    Charset {
+        #[serde(default = "default_passphrase_charset")]
+        charset: String,
+        #[serde(default = "default_passphrase_length")]
+        length: usize,
+        /// Append check character.
+        #[serde(default)]
+        check: bool,
+    },
+    /// Random words of the wordlist joined by `-`.
+    Wordlist {
+        /// Path to the file with a word per line. Lines in EFF format
+        /// (`11111 abacus`) are supported.
+        path: PathBuf,
+        #[serde(default = "default_passphrase_words")]
+        words: usize,
+        /// Append check character.
+        #[serde(default)]
+        check: bool,
+    },
+}
+
+impl Default for PassphraseConfig {
+    fn default() -> Self {
+        Self::Charset {
+            charset: default_passphrase_charset(),
+            length: default_passphrase_length(),
+            check: false,
+        }
+    }
+}
+
+fn default_passphrase_charset() -> String {
+    "23456789abcdefghjkmnpqrstuvwxyz".to_owned()
+}
+
+fn default_passphrase_length() -> usize {
+    6
+}
+
+fn default_passphrase_words() -> usize {
+    4
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "kind")]
+pub enum StorageConfig {
+    Memory,
+    Mongodb(MongodbConfig),
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
@@ -0,0 +1,217 @@
+use std::{
+    hash::Hash,
+    net::IpAddr,
+    time::{Duration, Instant},
+};
+
+use dashmap::DashMap;
+use drophub::{Error, InvitePassphrase, PeerId};
+
+use crate::config::InviteAttemptsConfig;
+
+/// Accounts failed invite redemptions to slow down passphrase guessing. Failures are
+/// accounted per peer and per IP address, so new peers of the same client stay blocked.
+#[derive(Debug)]
+pub struct InviteAttempts {
+    cfg: InviteAttemptsConfig,
+    peers: DashMap<PeerId, Backoff>,
+    addrs: DashMap<IpAddr, Backoff>,
+    invites: DashMap<InvitePassphrase, u32>,
+}
+
+#[derive(Debug)]
+struct Backoff {
+    failures: u32,
+    last_failure: Instant,
+    blocked_until: Instant,
+}
+
+impl InviteAttempts {
+    pub fn new(cfg: InviteAttemptsConfig) -> Self {
+        Self {
+            cfg,
+            peers: DashMap::new(),
+            addrs: DashMap::new(),
+            invites: DashMap::new(),
+        }
+    }
+
+    /// Returns an error if the peer or its address must wait before the next attempt.
+    pub fn check(&self, peer_id: PeerId, addr: IpAddr) -> Result<(), Error> {
+        let blocked_until = [
+            self.peers
+                .get(&peer_id)
+                .map(|backoff| backoff.blocked_until),
+            self.addrs.get(&addr).map(|backoff| backoff.blocked_until),
+        ]
+        .into_iter()
+        .flatten()
+        .max();
+
+        let now = Instant::now();
+        match blocked_until {
+            Some(blocked_until) if blocked_until > now => Err(Error::RateLimited {
+                retry_after: ceil_secs(blocked_until - now),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Accounts failed attempt of the peer from the address. Delay before the next attempt
+    /// doubles with every failure after the free ones, the client is blocked for
+    /// the maximum backoff after the lockout number of failures.
+    pub fn fail_client(&self, peer_id: PeerId, addr: IpAddr) {
+        self.fail(&self.peers, peer_id);
+        self.fail(&self.addrs, addr);
+    }
+
+    fn fail<K: Eq + Hash>(&self, clients: &DashMap<K, Backoff>, key: K) {
+        let now = Instant::now();
+        let mut backoff = clients.entry(key).or_insert_with(|| Backoff {
+            failures: 0,
+            last_failure: now,
+            blocked_until: now,
+        });
+
+        backoff.failures += 1;
+        backoff.last_failure = now;
+        if backoff.failures >= self.cfg.invite_lockout {
+            backoff.blocked_until = now + self.cfg.max_backoff;
+        } else if let Some(exp) = backoff.failures.checked_sub(self.cfg.free_attempts + 1) {
+            let delay = self
+                .cfg
+                .backoff
+                .checked_mul(2u32.saturating_pow(exp))
+                .unwrap_or(self.cfg.max_backoff)
+                .min(self.cfg.max_backoff);
+            backoff.blocked_until = now + delay;
+        }
+    }
+
+    /// Accounts failed attempt against the existing invite. Returns `true` if the invite
+    /// must be locked out.
+    pub fn fail_invite(&self, invite_passphrase: &str) -> bool {
+        let mut failures = self
+            .invites
+            .entry(invite_passphrase.to_owned())
+            .or_insert(0);
+        *failures += 1;
+        if *failures < self.cfg.invite_lockout {
+            return false;
+        }
+
+        drop(failures);
+        self.invites.remove(invite_passphrase);
+        true
+    }
+
+    /// Forgets failures of the peer after successful attempt. Failures of the address are
+    /// kept, otherwise redeeming an own invite would reset the backoff of guessing.
+    pub fn succeed(&self, peer_id: PeerId, invite_passphrase: &str) {
+        self.peers.remove(&peer_id);
+        self.invites.remove(invite_passphrase);
+    }
+
+    /// Forgets clients without failures for the maximum backoff.
+    pub fn remove_stale(&self) {
+        let now = Instant::now();
+        let is_active = |backoff: &Backoff| {
+            backoff.blocked_until > now
+                || now.duration_since(backoff.last_failure) < self.cfg.max_backoff
+        };
+        self.peers.retain(|_, backoff| is_active(backoff));
+        self.addrs.retain(|_, backoff| is_active(backoff));
+    }
+
+    /// Returns invites with accounted failures.
+    pub fn invite_passphrases(&self) -> Vec<InvitePassphrase> {
+        self.invites
+            .iter()
+            .map(|failures| failures.key().clone())
+            .collect()
+    }
+
+    pub fn remove_invite(&self, invite_passphrase: &str) {
+        self.invites.remove(invite_passphrase);
+    }
+}
+
+fn ceil_secs(duration: Duration) -> u64 {
+    duration.as_secs() + u64::from(duration.subsec_nanos() > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+
+    #[test]
+    fn backoff() {
+        let attempts = InviteAttempts::new(InviteAttemptsConfig {
+            free_attempts: 2,
+            backoff: Duration::from_secs(10),
+            max_backoff: Duration::from_secs(30),
+            invite_lockout: 10,
+        });
+        let peer_id = Uuid::new_v4();
+        let addr = IpAddr::from([127, 0, 0, 1]);
+
+        for _ in 0..2 {
+            attempts.fail_client(peer_id, addr);
+            assert!(attempts.check(peer_id, addr).is_ok());
+        }
+
+        let mut retry_afters = Vec::new();
+        for _ in 0..4 {
+            attempts.fail_client(peer_id, addr);
+            match attempts.check(peer_id, addr) {
+                Err(Error::RateLimited { retry_after }) => retry_afters.push(retry_after),
+                other => panic!("unexpected result: {other:?}"),
+            }
+        }
+        assert_eq!(retry_afters, [10, 20, 30, 30]);
+
+        // New peer of the same address stays blocked
+        assert!(attempts.check(Uuid::new_v4(), addr).is_err());
+        attempts.succeed(peer_id, "abc");
+        assert!(attempts.check(peer_id, addr).is_err());
+        assert!(attempts
+            .check(peer_id, IpAddr::from([127, 0, 0, 2]))
+            .is_ok());
+    }
+
+    #[test]
+    fn client_lockout() {
+        let attempts = InviteAttempts::new(InviteAttemptsConfig {
+            free_attempts: 5,
+            backoff: Duration::from_secs(10),
+            max_backoff: Duration::from_secs(60),
+            invite_lockout: 2,
+        });
+        let peer_id = Uuid::new_v4();
+        let addr = IpAddr::from([127, 0, 0, 1]);
+
+        attempts.fail_client(peer_id, addr);
+        assert!(attempts.check(peer_id, addr).is_ok());
+        attempts.fail_client(peer_id, addr);
+        assert!(matches!(
+            attempts.check(peer_id, addr),
+            Err(Error::RateLimited { retry_after: 60 })
+        ));
+    }
+
+    #[test]
+    fn invite_lockout() {
+        let attempts = InviteAttempts::new(InviteAttemptsConfig {
+            invite_lockout: 3,
+            ..Default::default()
+        });
+
+        assert!(!attempts.fail_invite("abc"));
+        assert!(!attempts.fail_invite("abc"));
+        assert!(!attempts.fail_invite("def"));
+        assert!(attempts.fail_invite("abc"));
+        assert_eq!(attempts.invite_passphrases(), ["def"]);
+    }
+}
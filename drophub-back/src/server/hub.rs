@@ -0,0 +1,165 @@
+use std::sync::Arc;
+
+use dashmap::DashMap;
+use drophub::{Error, PeerEvent, PeerId, RelayFrame, RoomId};
+use tokio::sync::{broadcast, mpsc};
+
+/// Capacity of room channel. Slow subscriptions lose oldest events.
+const ROOM_CHANNEL_CAPACITY: usize = 64;
+
+/// Routes events to subscriptions of single peers and of whole rooms.
+#[derive(Debug, Default)]
+pub struct EventHub {
+    peers: DashMap<PeerId, mpsc::UnboundedSender<PeerEvent>>,
+    rooms: DashMap<RoomId, broadcast::Sender<PeerEvent>>,
+    relays: DashMap<PeerId, mpsc::Sender<RelayFrame>>,
+}
+
+impl EventHub {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    /// Registers peer subscription. Peer is unregistered when returned subscription is dropped.
+    pub fn subscribe_peer(self: &Arc<Self>, peer_id: PeerId) -> PeerSubscription {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.peers.insert(peer_id, tx);
+
+        PeerSubscription {
+            hub: Arc::clone(self),
+            peer_id,
+            rx,
+        }
+    }
+
+    /// Sends event to subscription of specified peer.
+    pub fn send_to_peer(&self, peer_id: PeerId, event: PeerEvent) -> Result<(), Error> {
+        self.peers
+            .get(&peer_id)
+            .and_then(|tx| tx.send(event).ok())
+            .ok_or(Error::PeerNotFound { peer_id })
+    }
+
+    /// Registers relay subscription. Subscription buffers at most `capacity` frames.
+    pub fn subscribe_relay(
+        self: &Arc<Self>,
+        peer_id: PeerId,
+        capacity: usize,
+    ) -> RelaySubscription {
+        let (tx, rx) = mpsc::channel(capacity);
+        self.relays.insert(peer_id, tx);
+
+        RelaySubscription {
+            hub: Arc::clone(self),
+            peer_id,
+            rx,
+        }
+    }
+
+    /// Sends frame to relay subscription of specified peer without waiting.
+    pub fn send_relay_frame(&self, peer_id: PeerId, frame: RelayFrame) -> Result<(), Error> {
+        let tx = self
+            .relays
+            .get(&peer_id)
+            .ok_or(Error::PeerNotFound { peer_id })?;
+
+        tx.try_send(frame).map_err(|err| match err {
+            mpsc::error::TrySendError::Full(_) => Error::PeerIsBusy {
+                peer_id,
+                details: Some(serde_json::json! { "Relay buffer is full" }),
+            },
+            mpsc::error::TrySendError::Closed(_) => Error::PeerNotFound { peer_id },
+        })
+    }
+
+    /// Subscribes to room events. Room channel is removed when the last subscription is dropped.
+    pub fn subscribe_room(self: &Arc<Self>, room_id: RoomId) -> RoomSubscription {
+        let rx = self
+            .rooms
+            .entry(room_id)
+            .or_insert_with(|| broadcast::channel(ROOM_CHANNEL_CAPACITY).0)
+            .subscribe();
+
+        RoomSubscription {
+            hub: Arc::clone(self),
+            room_id,
+            rx: Some(rx),
+        }
+    }
+
+    /// Sends event to every subscription of the room.
+    pub fn publish_to_room(&self, room_id: RoomId, event: PeerEvent) {
+        if let Some(tx) = self.rooms.get(&room_id) {
+            // Error means there are no subscribers, nothing to do
+            let _ = tx.send(event);
+        }
+    }
+}
+
+pub struct PeerSubscription {
+    hub: Arc<EventHub>,
+    peer_id: PeerId,
+    rx: mpsc::UnboundedReceiver<PeerEvent>,
+}
+
+impl PeerSubscription {
+    pub async fn recv(&mut self) -> Option<PeerEvent> {
+        self.rx.recv().await
+    }
+}
+
+impl Drop for PeerSubscription {
+    fn drop(&mut self) {
+        self.hub.peers.remove(&self.peer_id);
+    }
+}
+
+pub struct RelaySubscription {
+    hub: Arc<EventHub>,
+    peer_id: PeerId,
+    rx: mpsc::Receiver<RelayFrame>,
+}
+
+impl RelaySubscription {
+    pub async fn recv(&mut self) -> Option<RelayFrame> {
+        self.rx.recv().await
+    }
+}
+
+impl Drop for RelaySubscription {
+    fn drop(&mut self) {
+        self.hub.relays.remove(&self.peer_id);
+    }
+}
+
+pub struct RoomSubscription {
+    hub: Arc<EventHub>,
+    room_id: RoomId,
+    rx: Option<broadcast::Receiver<PeerEvent>>,
+}
+
+impl RoomSubscription {
+    /// Receives next room event. Returns `None` if the room channel is closed.
+    pub async fn recv(&mut self) -> Option<PeerEvent> {
+        let rx = self.rx.as_mut()?;
+        loop {
+            match rx.recv().await {
+                Ok(event) => return Some(event),
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    tracing::warn!(room_id = ?self.room_id, skipped, "Room subscription lagged");
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    }
+}
+
+impl Drop for RoomSubscription {
+    fn drop(&mut self) {
+        // Drop receiver first to make receivers count actual
+        drop(self.rx.take());
+        self.hub
+            .rooms
+            .remove_if(&self.room_id, |_, tx| tx.receiver_count() == 0);
+    }
+}
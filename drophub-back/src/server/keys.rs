@@ -0,0 +1,194 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, bail, Context};
+use drophub::{Error, PeerToken};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header};
+
+use crate::config::{ServerConfig, TokenKeyAlgorithm, TokenKeyConfig};
+
+/// Keys peer tokens are signed and verified with.
+pub struct TokenKeys {
+    signing_header: Header,
+    signing_key: EncodingKey,
+    /// Verification keys by key id. HMAC secret has no id.
+    verifying: HashMap<Option<String>, (Algorithm, DecodingKey)>,
+}
+
+impl TokenKeys {
+    pub fn new(cfg: &ServerConfig) -> anyhow::Result<Self> {
+        if cfg.token_keys.is_empty() {
+            let secret = cfg
+                .secret
+                .as_ref()
+                .ok_or_else(|| anyhow!("Neither token secret nor token keys are configured"))?;
+
+            return Ok(Self {
+                signing_header: Header::new(Algorithm::HS256),
+                signing_key: EncodingKey::from_secret(secret.as_bytes()),
+                verifying: HashMap::from([(
+                    None,
+                    (
+                        Algorithm::HS256,
+                        DecodingKey::from_secret(secret.as_bytes()),
+                    ),
+                )]),
+            });
+        }
+
+        let mut signing = None;
+        let mut verifying = HashMap::new();
+        for key_cfg in &cfg.token_keys {
+            let (algorithm, decoding_key) = load_decoding_key(key_cfg)
+                .with_context(|| format!("Failed to load public key '{}'", key_cfg.kid))?;
+            if verifying
+                .insert(Some(key_cfg.kid.clone()), (algorithm, decoding_key))
+                .is_some()
+            {
+                bail!("Duplicate token key id '{}'", key_cfg.kid);
+            }
+
+            if signing.is_none() && key_cfg.private_key.is_some() {
+                let encoding_key = load_encoding_key(key_cfg)
+                    .with_context(|| format!("Failed to load private key '{}'", key_cfg.kid))?;
+                let mut header = Header::new(algorithm);
+                header.kid = Some(key_cfg.kid.clone());
+                signing = Some((header, encoding_key));
+            }
+        }
+
+        let (signing_header, signing_key) =
+            signing.ok_or_else(|| anyhow!("None of token keys has a private key"))?;
+        tracing::info!(kid = ?signing_header.kid, "Token signing key loaded");
+
+        Ok(Self {
+            signing_header,
+            signing_key,
+            verifying,
+        })
+    }
+
+    pub fn encode(&self, token: &PeerToken) -> Result<String, Error> {
+        token.encode_with_key(&self.signing_header, &self.signing_key)
+    }
+
+    /// Decodes token and verifies it by the key it is signed with.
+    pub fn decode_and_verify(&self, token: &str) -> Result<PeerToken, Error> {
+        let kid = PeerToken::key_id(token)?;
+        let (algorithm, key) = self
+            .verifying
+            .get(&kid)
+            .ok_or_else(|| Error::InvalidToken {
+                details: Some(serde_json::json!({
+                    "message": "Token is signed by unknown key",
+                    "kid": kid,
+                })),
+            })?;
+
+        PeerToken::decode_and_verify_with_key(token, *algorithm, key)
+    }
+}
+
+fn load_decoding_key(cfg: &TokenKeyConfig) -> anyhow::Result<(Algorithm, DecodingKey)> {
+    let pem = std::fs::read(&cfg.public_key)?;
+    let key = match cfg.algorithm {
+        TokenKeyAlgorithm::Es256 => (Algorithm::ES256, DecodingKey::from_ec_pem(&pem)?),
+        TokenKeyAlgorithm::EdDsa => (Algorithm::EdDSA, DecodingKey::from_ed_pem(&pem)?),
+    };
+    Ok(key)
+}
+
+fn load_encoding_key(cfg: &TokenKeyConfig) -> anyhow::Result<EncodingKey> {
+    let Some(path) = &cfg.private_key else {
+        bail!("Private key is not configured");
+    };
+
+    let pem = std::fs::read(path)?;
+    let key = match cfg.algorithm {
+        TokenKeyAlgorithm::Es256 => EncodingKey::from_ec_pem(&pem)?,
+        TokenKeyAlgorithm::EdDsa => EncodingKey::from_ed_pem(&pem)?,
+    };
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::test_utils;
+
+    fn key(kid: &str, algorithm: TokenKeyAlgorithm, private: bool) -> TokenKeyConfig {
+        let name = match algorithm {
+            TokenKeyAlgorithm::Es256 => "es256",
+            TokenKeyAlgorithm::EdDsa => "eddsa",
+        };
+
+        TokenKeyConfig {
+            kid: kid.to_owned(),
+            algorithm,
+            public_key: format!("tests/keys/{name}.pub").into(),
+            private_key: private.then(|| format!("tests/keys/{name}.key").into()),
+        }
+    }
+
+    fn keys(token_keys: Vec<TokenKeyConfig>) -> TokenKeys {
+        let mut cfg = test_utils::test_config().server;
+        cfg.token_keys = token_keys;
+        TokenKeys::new(&cfg).unwrap()
+    }
+
+    #[test]
+    fn rotation() {
+        let token = PeerToken::new(Uuid::new_v4(), None, chrono::Duration::minutes(1));
+
+        let old_keys = keys(vec![key("old", TokenKeyAlgorithm::Es256, true)]);
+        let old_encoded = old_keys.encode(&token).unwrap();
+        assert_eq!(
+            PeerToken::key_id(&old_encoded).unwrap().as_deref(),
+            Some("old")
+        );
+
+        // New key signs, old one still verifies
+        let rotated_keys = keys(vec![
+            key("old", TokenKeyAlgorithm::Es256, false),
+            key("new", TokenKeyAlgorithm::EdDsa, true),
+        ]);
+        assert_eq!(rotated_keys.decode_and_verify(&old_encoded).unwrap(), token);
+        let new_encoded = rotated_keys.encode(&token).unwrap();
+        assert_eq!(
+            PeerToken::key_id(&new_encoded).unwrap().as_deref(),
+            Some("new")
+        );
+
+        // Old key is removed
+        let new_keys = keys(vec![key("new", TokenKeyAlgorithm::EdDsa, true)]);
+        assert_eq!(new_keys.decode_and_verify(&new_encoded).unwrap(), token);
+        assert!(matches!(
+            new_keys.decode_and_verify(&old_encoded),
+            Err(Error::InvalidToken { .. })
+        ));
+
+        // HMAC tokens are rejected once keys are configured
+        let hmac_encoded = token.encode("12345").unwrap();
+        assert!(matches!(
+            new_keys.decode_and_verify(&hmac_encoded),
+            Err(Error::InvalidToken { .. })
+        ));
+    }
+
+    #[test]
+    fn invalid_config() {
+        let mut cfg = test_utils::test_config().server;
+        cfg.secret = None;
+        assert!(TokenKeys::new(&cfg).is_err());
+
+        cfg.token_keys = vec![key("old", TokenKeyAlgorithm::Es256, false)];
+        assert!(TokenKeys::new(&cfg).is_err());
+
+        cfg.token_keys = vec![
+            key("old", TokenKeyAlgorithm::Es256, true),
+            key("old", TokenKeyAlgorithm::EdDsa, false),
+        ];
+        assert!(TokenKeys::new(&cfg).is_err());
+    }
+}
@@ -1,27 +1,110 @@
+mod attempts;
+mod hub;
+mod keys;
+mod passphrase;
+mod progress;
+mod reaper;
+mod relay;
+mod revocation;
 mod rpc;
+mod session;
 mod storage;
 #[cfg(test)]
 mod tests;
 
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 
 use drophub::RpcServer;
-use jsonrpsee::server::{ServerBuilder, ServerHandle};
+use jsonrpsee::server::{
+    serve_with_graceful_shutdown, stop_channel, HttpRequest, Methods, ServerBuilder, ServerHandle,
+    StopHandle,
+};
+use tokio::net::TcpListener;
 
 use self::rpc::Rpc;
 use crate::config::Config;
 
+/// Address of the client, put into extensions of every request of the connection.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct ClientAddr(pub IpAddr);
+
 pub async fn run(cfg: Config) -> anyhow::Result<(SocketAddr, ServerHandle)> {
-    let server = ServerBuilder::default()
-        .ws_only()
-        .build(cfg.server.bind_addr)
-        .await?;
+    let listener = TcpListener::bind(cfg.server.bind_addr).await?;
+    let forwarded_for = cfg.server.forwarded_for;
 
     let rpc = Rpc::new(cfg).await?;
 
-    let addr = server.local_addr()?;
-    let handle = server.start(rpc.into_rpc())?;
+    let addr = listener.local_addr()?;
+    let (stop_handle, handle) = stop_channel();
+    tokio::spawn(serve(
+        listener,
+        rpc.into_rpc().into(),
+        stop_handle,
+        forwarded_for,
+    ));
     tracing::info!(?addr, "Server started");
 
     Ok((addr, handle))
 }
+
+/// Accepts connections until the server is stopped.
+async fn serve(
+    listener: TcpListener,
+    methods: Methods,
+    stop_handle: StopHandle,
+    forwarded_for: bool,
+) {
+    let service_builder = ServerBuilder::default().ws_only().to_service_builder();
+
+    loop {
+        let (socket, remote_addr) = tokio::select! {
+            res = listener.accept() => match res {
+                Ok(conn) => conn,
+                Err(err) => {
+                    tracing::error!(?err, "Failed to accept connection");
+                    continue;
+                }
+            },
+            _ = stop_handle.clone().shutdown() => break,
+        };
+
+        let service = service_builder
+            .clone()
+            .build(methods.clone(), stop_handle.clone());
+        let service = tower::ServiceBuilder::new()
+            .map_request(move |request| with_client_addr(request, remote_addr, forwarded_for))
+            .service(service);
+        tokio::spawn(serve_with_graceful_shutdown(
+            socket,
+            service,
+            stop_handle.clone().shutdown(),
+        ));
+    }
+}
+
+/// Puts address of the client into the request. The reverse proxy is the client, unless
+/// the address appended by the proxy to `X-Forwarded-For` is trusted.
+fn with_client_addr<B>(
+    mut request: HttpRequest<B>,
+    remote_addr: SocketAddr,
+    forwarded_for: bool,
+) -> HttpRequest<B> {
+    let forwarded = forwarded_for
+        .then(|| {
+            request
+                .headers()
+                .get_all("x-forwarded-for")
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .flat_map(|value| value.split(','))
+                .last()?
+                .trim()
+                .parse()
+                .ok()
+        })
+        .flatten();
+
+    let addr = forwarded.unwrap_or(remote_addr.ip());
+    request.extensions_mut().insert(ClientAddr(addr));
+    request
+}
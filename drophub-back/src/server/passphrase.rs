@@ -0,0 +1,197 @@
+use std::collections::HashSet;
+
+use anyhow::{bail, Context};
+use drophub::{passphrase, InvitePassphrase};
+use rand::{seq::SliceRandom, Rng};
+
+use crate::config::PassphraseConfig;
+
+/// Generates invite passphrases in the configured format.
+#[derive(Debug)]
+pub enum PassphraseGenerator {
+    Charset {
+        charset: Vec<char>,
+        length: usize,
+        check: bool,
+    },
+    Wordlist {
+        wordlist: Vec<String>,
+        words: usize,
+        check: bool,
+    },
+}
+
+impl PassphraseGenerator {
+    pub fn new(cfg: &PassphraseConfig) -> anyhow::Result<Self> {
+        let generator = match cfg {
+            PassphraseConfig::Charset {
+                charset,
+                length,
+                check,
+            } => {
+                let charset: Vec<char> = charset.chars().collect();
+                if charset.is_empty() || *length == 0 {
+                    bail!("Passphrase charset and length must not be empty");
+                }
+                if charset.contains(&passphrase::SEPARATOR) {
+                    bail!(
+                        "Passphrase charset must not contain '{}'",
+                        passphrase::SEPARATOR
+                    );
+                }
+                if charset.iter().any(|ch| ch.is_uppercase()) {
+                    bail!("Passphrase charset must be lowercase, typed passphrases are lowercased");
+                }
+                if charset.iter().collect::<HashSet<_>>().len() != charset.len() {
+                    bail!("Passphrase charset contains duplicates");
+                }
+
+                Self::Charset {
+                    charset,
+                    length: *length,
+                    check: *check,
+                }
+            }
+            PassphraseConfig::Wordlist { path, words, check } => {
+                let text = std::fs::read_to_string(path)
+                    .with_context(|| format!("Failed to read wordlist {}", path.display()))?;
+                let wordlist = parse_wordlist(&text)?;
+                if *words == 0 {
+                    bail!("Number of passphrase words must not be zero");
+                }
+
+                Self::Wordlist {
+                    wordlist,
+                    words: *words,
+                    check: *check,
+                }
+            }
+        };
+
+        tracing::info!(
+            entropy_bits = generator.entropy_bits(),
+            "Invite passphrase generator initialized"
+        );
+        Ok(generator)
+    }
+
+    pub fn generate(&self) -> InvitePassphrase {
+        let mut rng = rand::thread_rng();
+        let (body, check) = match self {
+            Self::Charset {
+                charset,
+                length,
+                check,
+            } => {
+                let body: String = (0..*length)
+                    .map(|_| charset[rng.gen_range(0..charset.len())])
+                    .collect();
+                (body, *check)
+            }
+            Self::Wordlist {
+                wordlist,
+                words,
+                check,
+            } => {
+                let body = (0..*words)
+                    .map(|_| {
+                        wordlist
+                            .choose(&mut rng)
+                            .expect("wordlist is not empty")
+                            .as_str()
+                    })
+                    .collect::<Vec<_>>()
+                    .join(&passphrase::SEPARATOR.to_string());
+                (body, *check)
+            }
+        };
+
+        if check {
+            passphrase::with_check_char(&body)
+        } else {
+            body
+        }
+    }
+
+    /// Returns number of random bits in generated passphrases.
+    pub fn entropy_bits(&self) -> f64 {
+        match self {
+            Self::Charset {
+                charset, length, ..
+            } => *length as f64 * (charset.len() as f64).log2(),
+            Self::Wordlist {
+                wordlist, words, ..
+            } => *words as f64 * (wordlist.len() as f64).log2(),
+        }
+    }
+}
+
+/// Parses word per line. Leading dice numbers of EFF wordlists are skipped.
+fn parse_wordlist(text: &str) -> anyhow::Result<Vec<String>> {
+    let mut seen = HashSet::new();
+    let mut wordlist = Vec::new();
+    for line in text.lines() {
+        let Some(word) = line.split_whitespace().last() else {
+            continue;
+        };
+
+        let word = passphrase::normalize(word);
+        // Single character segment would be taken for check character
+        if word.chars().count() < 2 || !word.chars().all(|ch| ch.is_ascii_alphanumeric()) {
+            bail!("Invalid wordlist word '{word}'");
+        }
+        if seen.insert(word.clone()) {
+            wordlist.push(word);
+        }
+    }
+
+    if wordlist.is_empty() {
+        bail!("Wordlist is empty");
+    }
+    Ok(wordlist)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generate() {
+        let generator = PassphraseGenerator::new(&PassphraseConfig::Charset {
+            charset: "abc".to_owned(),
+            length: 8,
+            check: true,
+        })
+        .unwrap();
+        let passphrase = generator.generate();
+        assert_eq!(passphrase.len(), 10);
+        assert!(passphrase::verify(&passphrase));
+
+        let generator = PassphraseGenerator::Wordlist {
+            wordlist: parse_wordlist("11111\tcorrect\n11112\thorse\n\n11113\tbattery\n").unwrap(),
+            words: 3,
+            check: false,
+        };
+        let passphrase = generator.generate();
+        assert_eq!(passphrase.split(passphrase::SEPARATOR).count(), 3);
+        assert!(passphrase
+            .split(passphrase::SEPARATOR)
+            .all(|word| ["correct", "horse", "battery"].contains(&word)));
+    }
+
+    #[test]
+    fn invalid_config() {
+        for charset in ["", "ab-", "abca", "aBc"] {
+            let cfg = PassphraseConfig::Charset {
+                charset: charset.to_owned(),
+                length: 6,
+                check: false,
+            };
+            assert!(PassphraseGenerator::new(&cfg).is_err(), "{charset}");
+        }
+
+        assert!(parse_wordlist("").is_err());
+        assert!(parse_wordlist("correct\na\n").is_err());
+        assert!(parse_wordlist("correct\nhorse-battery\n").is_err());
+    }
+}
@@ -0,0 +1,200 @@
+use std::time::Instant;
+
+use dashmap::{mapref::entry::Entry, DashMap};
+use drophub::{EntityId, PeerId, TransferProgress};
+
+use crate::config::ProgressConfig;
+
+/// Weight of the latest measured rate in the smoothed throughput.
+const SMOOTHING: f64 = 0.3;
+
+/// Throttles progress reports of transfers and estimates their throughput.
+#[derive(Debug)]
+pub struct ProgressTracker {
+    cfg: ProgressConfig,
+    transfers: DashMap<(EntityId, PeerId), Sample>,
+}
+
+/// Last published progress of a transfer.
+#[derive(Debug)]
+struct Sample {
+    transferred: usize,
+    published_at: Instant,
+    /// Bytes per second, unknown until the second published report.
+    throughput: Option<f64>,
+}
+
+impl ProgressTracker {
+    pub fn new(cfg: ProgressConfig) -> Self {
+        Self {
+            cfg,
+            transfers: DashMap::new(),
+        }
+    }
+
+    /// Accounts progress of the transfer to the peer. Returns progress to publish, or `None`
+    /// if the previous one is published less than the interval ago. Completion is published
+    /// anyway.
+    pub fn report(
+        &self,
+        entity_id: EntityId,
+        peer_id: PeerId,
+        transferred: usize,
+        size: usize,
+    ) -> Option<TransferProgress> {
+        self.report_at(Instant::now(), entity_id, peer_id, transferred, size)
+    }
+
+    fn report_at(
+        &self,
+        now: Instant,
+        entity_id: EntityId,
+        peer_id: PeerId,
+        transferred: usize,
+        size: usize,
+    ) -> Option<TransferProgress> {
+        let mut sample = match self.transfers.entry((entity_id, peer_id)) {
+            Entry::Occupied(entry) => entry.into_ref(),
+            Entry::Vacant(entry) => {
+                entry.insert(Sample {
+                    transferred,
+                    published_at: now,
+                    throughput: None,
+                });
+                return Some(progress(transferred, size, None));
+            }
+        };
+
+        let elapsed = now.saturating_duration_since(sample.published_at);
+        if elapsed < self.cfg.interval && transferred < size {
+            return None;
+        }
+
+        // Transfer may continue from an earlier offset after resume, it isn't measured then
+        if let Some(delta) = transferred
+            .checked_sub(sample.transferred)
+            .filter(|_| !elapsed.is_zero())
+        {
+            let rate = delta as f64 / elapsed.as_secs_f64();
+            sample.throughput = Some(match sample.throughput {
+                Some(throughput) => throughput + SMOOTHING * (rate - throughput),
+                None => rate,
+            });
+        }
+        sample.transferred = transferred;
+        sample.published_at = now;
+
+        Some(progress(transferred, size, sample.throughput))
+    }
+
+    /// Forgets progress of the transfer, e.g. when it is finished.
+    pub fn remove(&self, entity_id: EntityId, peer_id: PeerId) {
+        self.transfers.remove(&(entity_id, peer_id));
+    }
+
+    /// Returns transfers with accounted progress.
+    pub fn transfers(&self) -> Vec<(EntityId, PeerId)> {
+        self.transfers.iter().map(|sample| *sample.key()).collect()
+    }
+}
+
+fn progress(transferred: usize, size: usize, throughput: Option<f64>) -> TransferProgress {
+    let remaining = size.saturating_sub(transferred);
+    let eta = if remaining == 0 {
+        Some(0)
+    } else {
+        throughput
+            .filter(|throughput| *throughput > 0.0)
+            .map(|throughput| (remaining as f64 / throughput).ceil() as u64)
+    };
+
+    TransferProgress {
+        transferred,
+        size,
+        throughput: throughput.map_or(0, |throughput| throughput.round() as u64),
+        eta,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use uuid::Uuid;
+
+    use super::*;
+
+    #[test]
+    fn throttle() {
+        let tracker = ProgressTracker::new(ProgressConfig {
+            interval: Duration::from_secs(1),
+        });
+        let (entity_id, peer_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let start = Instant::now();
+
+        let progress = tracker
+            .report_at(start, entity_id, peer_id, 0, 1000)
+            .unwrap();
+        assert_eq!((progress.throughput, progress.eta), (0, None));
+        assert_eq!(
+            tracker.report_at(
+                start + Duration::from_millis(500),
+                entity_id,
+                peer_id,
+                50,
+                1000
+            ),
+            None
+        );
+
+        let progress = tracker
+            .report_at(
+                start + Duration::from_secs(1),
+                entity_id,
+                peer_id,
+                100,
+                1000,
+            )
+            .unwrap();
+        assert_eq!(progress.transferred, 100);
+        assert_eq!((progress.throughput, progress.eta), (100, Some(9)));
+
+        // Completion isn't throttled
+        let progress = tracker
+            .report_at(
+                start + Duration::from_millis(1100),
+                entity_id,
+                peer_id,
+                1000,
+                1000,
+            )
+            .unwrap();
+        assert_eq!(progress.eta, Some(0));
+
+        tracker.remove(entity_id, peer_id);
+        assert!(tracker.transfers().is_empty());
+    }
+
+    #[test]
+    fn throughput() {
+        let tracker = ProgressTracker::new(ProgressConfig {
+            interval: Duration::from_secs(1),
+        });
+        let (entity_id, peer_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        tracker.report_at(at(0), entity_id, peer_id, 0, 10000);
+        tracker.report_at(at(1), entity_id, peer_id, 1000, 10000);
+        let progress = tracker
+            .report_at(at(2), entity_id, peer_id, 3000, 10000)
+            .unwrap();
+        assert_eq!(progress.throughput, 1300);
+
+        // Resumed from an earlier offset, throughput is kept
+        let progress = tracker
+            .report_at(at(3), entity_id, peer_id, 2000, 10000)
+            .unwrap();
+        assert_eq!((progress.throughput, progress.eta), (1300, Some(7)));
+    }
+}
@@ -0,0 +1,156 @@
+use std::{collections::HashSet, sync::Arc, time::Duration};
+
+use chrono::{DateTime, Utc};
+use drophub::{DisconnectReason, Error, PeerEvent};
+use tokio::{task::JoinHandle, time::MissedTickBehavior};
+
+use super::{
+    attempts::InviteAttempts,
+    hub::EventHub,
+    progress::ProgressTracker,
+    relay::RelayLimiter,
+    revocation::RevocationList,
+    rpc::{close_room, disconnect_peer, publish_room_update, transfer_state},
+    storage::{PeerState, Storage},
+};
+use crate::config::TtlConfig;
+
+/// Spawns background task that periodically removes expired records.
+pub fn spawn(
+    storage: Arc<dyn Storage>,
+    hub: Arc<EventHub>,
+    relay: Arc<RelayLimiter>,
+    attempts: Arc<InviteAttempts>,
+    progress: Arc<ProgressTracker>,
+    tokens: Arc<RevocationList>,
+    ttl: TtlConfig,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(ttl.sweep_interval);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            interval.tick().await;
+            if let Err(err) = sweep(&*storage, &hub, &ttl).await {
+                tracing::error!(?err, "Failed to remove expired records");
+            }
+            if let Err(err) = sweep_relay(&*storage, &relay).await {
+                tracing::error!(?err, "Failed to remove relay usage of removed rooms");
+            }
+            if let Err(err) = sweep_attempts(&*storage, &attempts).await {
+                tracing::error!(?err, "Failed to remove failed attempts of removed invites");
+            }
+            if let Err(err) = sweep_progress(&*storage, &progress).await {
+                tracing::error!(?err, "Failed to remove progress of finished transfers");
+            }
+            tokens.remove_expired(Utc::now());
+        }
+    })
+}
+
+async fn sweep(storage: &dyn Storage, hub: &EventHub, ttl: &TtlConfig) -> Result<(), Error> {
+    let now = Utc::now();
+
+    if let Some(time) = expiry_threshold(now, ttl.invite) {
+        for invite in storage.invites_created_before(time).await? {
+            storage.remove_invite(&invite.passphrase).await?;
+            tracing::info!(invite_passphrase = ?invite.passphrase, "Invite expired");
+        }
+    }
+
+    if let Some(time) = expiry_threshold(now, ttl.room) {
+        for room in storage.rooms_created_before(time).await? {
+            tracing::info!(room_id = ?room.id, "Room expired");
+            close_room(storage, hub, room.id, DisconnectReason::RoomExpired).await?;
+        }
+    }
+
+    if let Some(time) = expiry_threshold(now, ttl.peer) {
+        for peer in storage.peers_created_before(time).await? {
+            let room_id = match peer.state {
+                PeerState::Disconnected => None,
+                PeerState::Connecting { room_id, .. } => Some(room_id),
+                PeerState::Connected { room_id, .. } => Some(room_id),
+            };
+
+            tracing::info!(peer_id = ?peer.id, "Peer expired");
+            // Subscription may be already closed
+            let _ = hub.send_to_peer(
+                peer.id,
+                PeerEvent::Disconnect {
+                    room_id,
+                    reason: DisconnectReason::PeerExpired,
+                },
+            );
+            disconnect_peer(storage, hub, peer.id).await?;
+        }
+    }
+
+    if let Some(time) = expiry_threshold(now, ttl.entity) {
+        let mut updated_rooms = HashSet::new();
+        for entity in storage.entities_created_before(time).await? {
+            tracing::info!(entity_id = ?entity.id, "Entity expired");
+            storage
+                .remove_room_entity(entity.room_id, entity.id)
+                .await?;
+            storage.remove_entity(entity.id).await?;
+            updated_rooms.insert(entity.room_id);
+        }
+
+        for room_id in updated_rooms {
+            match publish_room_update(storage, hub, room_id).await {
+                Ok(()) | Err(Error::RoomNotFound { .. }) => {}
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Removes relay usage of rooms that don't exist anymore.
+async fn sweep_relay(storage: &dyn Storage, relay: &RelayLimiter) -> Result<(), Error> {
+    for room_id in relay.room_ids() {
+        if storage.get_room(room_id).await?.is_none() {
+            relay.remove_room(room_id);
+        }
+    }
+
+    Ok(())
+}
+
+/// Removes failed attempts of peers not blocked anymore and of invites that don't exist.
+async fn sweep_attempts(storage: &dyn Storage, attempts: &InviteAttempts) -> Result<(), Error> {
+    attempts.remove_stale();
+    for invite_passphrase in attempts.invite_passphrases() {
+        if storage.get_invite(&invite_passphrase).await?.is_none() {
+            attempts.remove_invite(&invite_passphrase);
+        }
+    }
+
+    Ok(())
+}
+
+/// Removes progress of finished transfers and of transfers that don't exist anymore,
+/// e.g. the entity is removed or the receiver left the room.
+async fn sweep_progress(storage: &dyn Storage, progress: &ProgressTracker) -> Result<(), Error> {
+    for (entity_id, peer_id) in progress.transfers() {
+        let entity = storage.get_entity(entity_id).await?;
+        let finished = match entity.and_then(|entity| transfer_state(&entity, peer_id)) {
+            Some(state) => state.is_finished(),
+            None => true,
+        };
+        if finished {
+            progress.remove(entity_id, peer_id);
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns time before which records with specified TTL are expired.
+fn expiry_threshold(now: DateTime<Utc>, ttl: Duration) -> Option<DateTime<Utc>> {
+    chrono::Duration::from_std(ttl)
+        .ok()
+        .and_then(|ttl| now.checked_sub_signed(ttl))
+}
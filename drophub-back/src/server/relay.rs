@@ -0,0 +1,81 @@
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+use drophub::{Error, RoomId};
+
+use crate::config::RelayConfig;
+
+/// Period of bandwidth limit.
+const BANDWIDTH_WINDOW: Duration = Duration::from_secs(1);
+
+/// Accounts bytes relayed in every room and enforces relay limits.
+#[derive(Debug)]
+pub struct RelayLimiter {
+    cfg: RelayConfig,
+    rooms: DashMap<RoomId, RoomUsage>,
+}
+
+#[derive(Debug)]
+struct RoomUsage {
+    total: usize,
+    window_start: Instant,
+    window_size: usize,
+}
+
+impl RelayLimiter {
+    pub fn new(cfg: RelayConfig) -> Self {
+        Self {
+            cfg,
+            rooms: DashMap::new(),
+        }
+    }
+
+    /// Accounts frame relayed in the room. Frame must be rejected if an error is returned.
+    pub fn consume(&self, room_id: RoomId, frame_size: usize) -> Result<(), Error> {
+        if !self.cfg.enabled {
+            return Err(Error::RelayDisabled);
+        }
+        if frame_size > self.cfg.frame_size {
+            return Err(Error::RelayFrameSizeLimitExceeded {
+                limit: self.cfg.frame_size,
+            });
+        }
+
+        let now = Instant::now();
+        let mut usage = self.rooms.entry(room_id).or_insert_with(|| RoomUsage {
+            total: 0,
+            window_start: now,
+            window_size: 0,
+        });
+
+        if now.duration_since(usage.window_start) >= BANDWIDTH_WINDOW {
+            usage.window_start = now;
+            usage.window_size = 0;
+        }
+        if usage.window_size + frame_size > self.cfg.room_bandwidth {
+            return Err(Error::RoomRelayBandwidthLimitExceeded {
+                room_id,
+                limit: self.cfg.room_bandwidth,
+            });
+        }
+        if usage.total + frame_size > self.cfg.room_size {
+            return Err(Error::RoomRelaySizeLimitExceeded {
+                room_id,
+                limit: self.cfg.room_size,
+            });
+        }
+
+        usage.window_size += frame_size;
+        usage.total += frame_size;
+        Ok(())
+    }
+
+    /// Returns rooms with accounted usage.
+    pub fn room_ids(&self) -> Vec<RoomId> {
+        self.rooms.iter().map(|usage| *usage.key()).collect()
+    }
+
+    pub fn remove_room(&self, room_id: RoomId) {
+        self.rooms.remove(&room_id);
+    }
+}
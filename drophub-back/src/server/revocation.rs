@@ -0,0 +1,86 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use drophub::{PeerId, PeerToken, RoomId};
+use uuid::Uuid;
+
+/// Tokens revoked before their expiration. Expired tokens are rejected by the signature
+/// validation, so they are forgotten once expired.
+#[derive(Debug, Default)]
+pub struct RevocationList {
+    /// Revoked token ids with their expiration time.
+    revoked: DashMap<Uuid, DateTime<Utc>>,
+    /// Not expired tokens issued to every peer.
+    issued: DashMap<PeerId, HashMap<Uuid, IssuedToken>>,
+}
+
+#[derive(Debug)]
+struct IssuedToken {
+    room_id: Option<RoomId>,
+    exp: DateTime<Utc>,
+}
+
+impl RevocationList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Remembers issued token, so it can be revoked with other tokens of the peer.
+    pub fn issue(&self, token: &PeerToken) {
+        self.issued.entry(token.peer_id).or_default().insert(
+            token.jti,
+            IssuedToken {
+                room_id: token.room_id,
+                exp: token.exp,
+            },
+        );
+    }
+
+    pub fn revoke(&self, token: &PeerToken) {
+        if let Some(mut issued) = self.issued.get_mut(&token.peer_id) {
+            issued.remove(&token.jti);
+        }
+        self.revoked.insert(token.jti, token.exp);
+    }
+
+    /// Revokes every token issued to the peer.
+    pub fn revoke_peer(&self, peer_id: PeerId) {
+        let Some((_, issued)) = self.issued.remove(&peer_id) else {
+            return;
+        };
+
+        for (jti, issued) in issued {
+            self.revoked.insert(jti, issued.exp);
+        }
+    }
+
+    /// Revokes tokens issued to the peer and scoped to the room.
+    pub fn revoke_room_peer(&self, peer_id: PeerId, room_id: RoomId) {
+        let Some(mut issued) = self.issued.get_mut(&peer_id) else {
+            return;
+        };
+
+        issued.retain(|jti, issued| {
+            if issued.room_id != Some(room_id) {
+                return true;
+            }
+
+            self.revoked.insert(*jti, issued.exp);
+            false
+        });
+    }
+
+    pub fn is_revoked(&self, token: &PeerToken) -> bool {
+        self.revoked.contains_key(&token.jti)
+    }
+
+    /// Forgets tokens expired before the specified time.
+    pub fn remove_expired(&self, now: DateTime<Utc>) {
+        self.revoked.retain(|_, exp| *exp > now);
+        self.issued.retain(|_, issued| {
+            issued.retain(|_, issued| issued.exp > now);
+            !issued.is_empty()
+        });
+    }
+}
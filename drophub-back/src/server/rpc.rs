@@ -1,181 +1,1595 @@
-use std::pin::pin;
+use std::{collections::HashSet, net::IpAddr, pin::pin, sync::Arc, time::Duration};
 
-use chrono::{Duration, Utc};
+use chrono::{DateTime, Utc};
 use drophub::{
-    AnnouncedEntity, Entity, EntityId, Error, InvitePassphrase, PeerEvent, PeerId, PeerToken,
-    PeerTokenEncoded, Room, RoomId, RpcServer,
+    digest::Digest, passphrase, AnnouncedEntity, Capability, ClientHello, DisconnectReason,
+    EntityId, EntityKind, Error, Invite, InvitePassphrase, PeerEvent, PeerId, PeerToken,
+    PeerTokenEncoded, RelayData, RelayFrame, Room, RoomId, RoomOptions, RpcServer, SignalPayload,
+    TransferState, Transport, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION,
 };
-use futures::FutureExt;
 use jsonrpsee::{
     core::{async_trait, SubscriptionResult},
-    PendingSubscriptionSink,
+    Extensions, PendingSubscriptionSink, SubscriptionSink,
 };
-use mongodb::options::ClientOptions;
-use rand::Rng;
-use scopeguard::defer;
-use tracing::{instrument, Instrument};
+use scopeguard::ScopeGuard;
+use tokio::task::JoinHandle;
+use tracing::instrument;
 use uuid::Uuid;
 
-use super::storage;
-use crate::config::Config;
+use super::{
+    attempts::InviteAttempts,
+    hub::EventHub,
+    keys::TokenKeys,
+    passphrase::PassphraseGenerator,
+    progress::ProgressTracker,
+    reaper,
+    relay::RelayLimiter,
+    revocation::RevocationList,
+    session::{PeerSession, SessionRegistry},
+    storage::{self, Storage},
+    ClientAddr,
+};
+use crate::config::{Config, TtlConfig};
+
+/// Attempts to generate passphrase not used by another invite.
+const MAX_PASSPHRASE_ATTEMPTS: usize = 16;
+
+/// How far in the future modification time of announced entity may be.
+const MAX_CLOCK_SKEW: Duration = Duration::from_secs(5 * 60);
 
 pub struct Rpc {
-    mongodb_client: mongodb::Client,
+    storage: Arc<dyn Storage>,
+    hub: Arc<EventHub>,
+    relay: Arc<RelayLimiter>,
+    attempts: Arc<InviteAttempts>,
+    progress: Arc<ProgressTracker>,
+    keys: TokenKeys,
+    passphrases: PassphraseGenerator,
+    tokens: Arc<RevocationList>,
+    sessions: Arc<SessionRegistry>,
+    reaper: JoinHandle<()>,
     cfg: Config,
 }
 
 impl Rpc {
     pub async fn new(cfg: Config) -> anyhow::Result<Self> {
-        let mut client_options = ClientOptions::parse(&cfg.mongodb.uri).await?;
-        client_options.app_name = Some(env!("CARGO_PKG_NAME").to_owned());
-
-        let mongodb_client = mongodb::Client::with_options(client_options)?;
+        let keys = TokenKeys::new(&cfg.server)?;
+        let passphrases = PassphraseGenerator::new(&cfg.passphrase)?;
+        let storage = storage::new(&cfg.storage, &cfg.ttl).await?;
+        let hub = EventHub::new();
+        let relay = Arc::new(RelayLimiter::new(cfg.relay.clone()));
+        let attempts = Arc::new(InviteAttempts::new(cfg.invite_attempts.clone()));
+        let progress = Arc::new(ProgressTracker::new(cfg.progress.clone()));
+        let tokens = Arc::new(RevocationList::new());
+        let sessions = SessionRegistry::new(
+            storage.clone(),
+            hub.clone(),
+            tokens.clone(),
+            cfg.ttl.session,
+        );
+        let reaper = reaper::spawn(
+            storage.clone(),
+            hub.clone(),
+            relay.clone(),
+            attempts.clone(),
+            progress.clone(),
+            tokens.clone(),
+            cfg.ttl.clone(),
+        );
 
         Ok(Self {
-            mongodb_client,
+            storage,
+            hub,
+            relay,
+            attempts,
+            progress,
+            keys,
+            passphrases,
+            tokens,
+            sessions,
+            reaper,
             cfg,
         })
     }
+
+    /// Sends `Init` event and forwards events of the session until the subscription is closed.
+    /// Closed session is detached, so the peer can resume it.
+    async fn run_session(
+        &self,
+        sink: SubscriptionSink,
+        session: PeerSession,
+        token: PeerTokenEncoded,
+        capabilities: Vec<Capability>,
+    ) -> SubscriptionResult {
+        let mut subscribe_closed = pin!(sink.closed());
+        let mut takeover = self.sessions.attach(session.peer_id);
+        let mut session = scopeguard::guard(session, |session| self.sessions.detach(session));
+        let peer_id = session.peer_id;
+        let progress = capabilities.contains(&Capability::Progress);
+
+        sink.send(
+            PeerEvent::Init {
+                token,
+                invite_passphrase: session.invite_passphrase.clone(),
+                version: PROTOCOL_VERSION,
+                capabilities,
+            }
+            .try_into()?,
+        )
+        .await?;
+
+        loop {
+            let PeerSession {
+                peer_sub, room_sub, ..
+            } = &mut *session;
+            tokio::select! {
+                Some(event) = peer_sub.recv() => {
+                    if matches!(event, PeerEvent::Invite { .. }) {
+                        // Peer must be connected before it receives the room token,
+                        // otherwise its first requests may be rejected
+                        let room_id = connect_peer(&*self.storage, peer_id).await?;
+                        *room_sub = Some(self.hub.subscribe_room(room_id));
+                        sink.send(event.try_into()?).await?;
+                        publish_room_update(&*self.storage, &self.hub, room_id).await?;
+                    } else if let PeerEvent::Disconnect { reason, .. } = event {
+                        *room_sub = None;
+                        sink.send(event.try_into()?).await?;
+                        if reason == DisconnectReason::PeerExpired {
+                            tracing::info!("Peer expired, closing subscription");
+                            self.sessions.close(ScopeGuard::into_inner(session));
+                            return Ok(());
+                        }
+                    } else {
+                        sink.send(event.try_into()?).await?;
+                    }
+                }
+                Some(event) = async { room_sub.as_mut()?.recv().await }, if room_sub.is_some() => {
+                    if progress || !matches!(event, PeerEvent::TransferProgress { .. }) {
+                        sink.send(event.try_into()?).await?;
+                    }
+                }
+                Some(reply) = takeover.requested() => {
+                    tracing::info!("Session taken over by another subscription");
+                    if let Err(session) = reply.send(ScopeGuard::into_inner(session)) {
+                        self.sessions.detach(session);
+                    }
+                    return Ok(())
+                }
+                _ = &mut subscribe_closed => {
+                    tracing::info!("Subscription closed");
+                    return Ok(())
+                }
+            }
+        }
+    }
+
+    /// Checks that the client protocol version is supported. Returns capabilities
+    /// requested by the client and supported by the server.
+    fn negotiate(&self, hello: Option<ClientHello>) -> Result<Vec<Capability>, Error> {
+        let version = hello.as_ref().map(|hello| hello.version);
+        let Some(hello) = hello
+            .filter(|hello| (MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&hello.version))
+        else {
+            return Err(Error::UnsupportedProtocolVersion {
+                version,
+                min_version: MIN_PROTOCOL_VERSION,
+                max_version: PROTOCOL_VERSION,
+            });
+        };
+
+        Ok(hello
+            .capabilities
+            .into_iter()
+            .filter(|capability| match capability {
+                Capability::Relay => self.cfg.relay.enabled,
+                // Keys are exchanged with signals, the server only forwards them
+                Capability::Encryption => true,
+                Capability::Progress => true,
+            })
+            .collect())
+    }
+
+    /// Issues token of the peer scoped to the room, if any.
+    fn issue_token(
+        &self,
+        peer_id: PeerId,
+        room_id: Option<RoomId>,
+    ) -> Result<PeerTokenEncoded, Error> {
+        let ttl = chrono::Duration::from_std(self.cfg.ttl.token)
+            .map_err(|err| Error::Other(err.into()))?;
+        let token = PeerToken::new(peer_id, room_id, ttl);
+        let encoded = self.keys.encode(&token)?;
+        self.tokens.issue(&token);
+        Ok(encoded)
+    }
+
+    /// Verifies signature and validity period of the token and that it isn't revoked.
+    fn verify_token(&self, token: &str) -> Result<PeerToken, Error> {
+        let token = self.keys.decode_and_verify(token)?;
+        if self.tokens.is_revoked(&token) {
+            return Err(Error::InvalidToken {
+                details: Some(serde_json::json! { "Token is revoked" }),
+            });
+        }
+
+        Ok(token)
+    }
+
+    /// Checks that another peer is connected to the room of the sender.
+    async fn verify_room_peer(
+        &self,
+        peer_id: PeerId,
+        room_id: RoomId,
+        to_peer_id: PeerId,
+    ) -> Result<(), Error> {
+        if peer_id == to_peer_id {
+            return Err(Error::SamePeer {
+                peer_id,
+                details: Some(serde_json::json! { "Peer can't send to itself" }),
+            });
+        }
+
+        let room = self
+            .storage
+            .get_room(room_id)
+            .await?
+            .ok_or(Error::RoomNotFound { room_id })?;
+        if !room.peers.contains(&to_peer_id) {
+            return Err(Error::PeerNotFound {
+                peer_id: to_peer_id,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Returns entity listed in the room.
+    async fn get_room_entity(
+        &self,
+        room_id: RoomId,
+        entity_id: EntityId,
+    ) -> Result<storage::Entity, Error> {
+        let room = self
+            .storage
+            .get_room(room_id)
+            .await?
+            .ok_or(Error::RoomNotFound { room_id })?;
+        if !room.entities.contains(&entity_id) {
+            return Err(Error::EntityNotFound { room_id, entity_id });
+        }
+
+        self.storage
+            .get_entity(entity_id)
+            .await?
+            .ok_or(Error::EntityNotFound { room_id, entity_id })
+    }
+
+    /// Checks that the transfer to the peer is accepted and the reporter is either its owner
+    /// or the receiver. Returns the entity.
+    async fn verify_transferring(
+        &self,
+        room_id: RoomId,
+        reporter_id: PeerId,
+        entity_id: EntityId,
+        peer_id: PeerId,
+    ) -> Result<storage::Entity, Error> {
+        let entity = self.get_room_entity(room_id, entity_id).await?;
+        if reporter_id != entity.owner_id && reporter_id != peer_id {
+            return Err(Error::PermissionDenied {
+                room_id: Some(room_id),
+                peer_id: reporter_id,
+                details: Some(serde_json::json!({
+                    "message": "Only the owner and the receiver can report the transfer",
+                    "entity_id": entity_id,
+                    "owner_id": entity.owner_id,
+                })),
+            });
+        }
+        match transfer_state(&entity, peer_id) {
+            Some(TransferState::Transferring) => Ok(entity),
+            Some(state) => Err(Error::InvalidTransferState {
+                entity_id,
+                peer_id,
+                state,
+            }),
+            None => Err(Error::TransferNotFound { entity_id, peer_id }),
+        }
+    }
+
+    /// Verifies token of the peer connected to a room. Returns peer and room ids.
+    async fn verify_room_token(&self, token: &str) -> Result<(PeerId, RoomId), Error> {
+        let token = self.verify_token(token)?;
+        self.verify_connected(&token).await
+    }
+
+    /// Connects the guest to the room of the invite, or creates a new room with the inviter.
+    async fn redeem_invite(&self, guest_id: PeerId, invite_passphrase: &str) -> Result<(), Error> {
+        if !passphrase::verify(invite_passphrase) {
+            return Err(Error::InviteNotFound {
+                invite_passphrase: invite_passphrase.to_owned(),
+            });
+        }
+
+        let invite = match self.storage.get_invite(invite_passphrase).await? {
+            Some(invite) if !invite.is_expired(self.cfg.ttl.invite) => invite,
+            Some(_) => {
+                self.storage.remove_invite(invite_passphrase).await?;
+                return Err(Error::InviteNotFound {
+                    invite_passphrase: invite_passphrase.to_owned(),
+                });
+            }
+            None => {
+                return Err(Error::InviteNotFound {
+                    invite_passphrase: invite_passphrase.to_owned(),
+                })
+            }
+        };
+
+        let host_id = invite.peer_id;
+        if host_id == guest_id {
+            return Err(Error::SamePeer {
+                peer_id: guest_id,
+                details: Some(serde_json::json! { "Peer cannot use its own invite" }),
+            });
+        }
+
+        let guest = self
+            .storage
+            .get_peer(guest_id)
+            .await?
+            .ok_or(Error::PeerNotFound { peer_id: guest_id })?;
+        match guest.state {
+            storage::PeerState::Disconnected => {}
+            storage::PeerState::Connecting { .. } => {
+                return Err(Error::PeerIsBusy {
+                    peer_id: guest_id,
+                    details: Some(serde_json::json! { "Peer is connecting to another room" }),
+                })
+            }
+            storage::PeerState::Connected { room_id, .. } => {
+                return Err(Error::PeerAlreadyConnected {
+                    peer_id: guest_id,
+                    room_id,
+                })
+            }
+        }
+
+        let (room_id, new_room) = if let Some(room_id) = invite.room_id {
+            // Invites created for the room are single-use
+            self.join_room(room_id, guest_id).await?;
+            self.storage.remove_invite(invite_passphrase).await?;
+            (room_id, false)
+        } else {
+            let host = self
+                .storage
+                .get_peer(host_id)
+                .await?
+                .ok_or(Error::PeerNotFound { peer_id: host_id })?;
+            match host.state {
+                storage::PeerState::Disconnected => {
+                    let room = storage::Room {
+                        id: Uuid::new_v4(),
+                        create_at: Utc::now(),
+                        host_id,
+                        locked: false,
+                        options: RoomOptions {
+                            capacity: self.cfg.limits.room_capacity,
+                            max_invites: self.cfg.limits.room_invites,
+                            invite_ttl: None,
+                        },
+                        peers: HashSet::from([host_id, guest_id]),
+                        entities: HashSet::new(),
+                    };
+                    let room_id = room.id;
+                    self.storage.add_room(room).await?;
+                    (room_id, true)
+                }
+                storage::PeerState::Connecting { .. } => {
+                    return Err(Error::PeerIsBusy {
+                        peer_id: host_id,
+                        details: Some(serde_json::json! { "Peer is connecting to another room" }),
+                    })
+                }
+                storage::PeerState::Connected { room_id, .. } => {
+                    self.join_room(room_id, guest_id).await?;
+                    (room_id, false)
+                }
+            }
+        };
+
+        // Host already has room-scoped token if it is connected to the room
+        let peers_to_connect = if new_room {
+            vec![host_id, guest_id]
+        } else {
+            vec![guest_id]
+        };
+
+        for peer_id in peers_to_connect {
+            self.storage
+                .set_peer_state(
+                    peer_id,
+                    storage::PeerState::Connecting {
+                        connecting_at: Utc::now(),
+                        room_id,
+                    },
+                )
+                .await?;
+
+            let token = self.issue_token(peer_id, Some(room_id))?;
+            self.hub
+                .send_to_peer(peer_id, PeerEvent::Invite { token })?;
+        }
+
+        tracing::info!(?room_id, ?host_id, ?guest_id, "Peer invited to room");
+        Ok(())
+    }
+
+    /// Accounts failed redemption. Existing invite is removed after too many failed
+    /// attempts against it.
+    async fn fail_invite_attempt(
+        &self,
+        guest_id: PeerId,
+        guest_addr: IpAddr,
+        invite_passphrase: &str,
+        err: &Error,
+    ) -> Result<(), Error> {
+        match err {
+            Error::Other(_) | Error::MongodbError { .. } => {}
+            Error::InviteNotFound { .. } => self.attempts.fail_client(guest_id, guest_addr),
+            _ => {
+                self.attempts.fail_client(guest_id, guest_addr);
+                if self.attempts.fail_invite(invite_passphrase) {
+                    self.storage.remove_invite(invite_passphrase).await?;
+                    tracing::warn!(?invite_passphrase, "Invite locked out");
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks that only text entities have content and it fits the limit.
+    fn verify_entity_content(&self, entity: &AnnouncedEntity) -> Result<(), Error> {
+        match (&entity.kind, &entity.content) {
+            (EntityKind::Text, Some(content)) => {
+                if content.len() > self.cfg.limits.text_size {
+                    return Err(Error::TextSizeLimitExceeded {
+                        limit: self.cfg.limits.text_size,
+                    });
+                }
+                if content.len() != entity.size {
+                    return Err(Error::InvalidEntity {
+                        details: Some(serde_json::json! { "Text size doesn't match its content" }),
+                    });
+                }
+                Ok(())
+            }
+            (EntityKind::Text, None) => Err(Error::InvalidEntity {
+                details: Some(serde_json::json! { "Text entity must have content" }),
+            }),
+            (EntityKind::File | EntityKind::Directory, Some(_)) => Err(Error::InvalidEntity {
+                details: Some(serde_json::json! { "Only text entity can have content" }),
+            }),
+            (EntityKind::File | EntityKind::Directory, None) => Ok(()),
+        }
+    }
+
+    /// Checks that only directory entities have manifest, it is valid and fits the limit.
+    fn verify_entity_manifest(&self, entity: &AnnouncedEntity) -> Result<(), Error> {
+        match (&entity.kind, &entity.manifest) {
+            (EntityKind::Directory, Some(manifest)) => {
+                if manifest.entries.len() > self.cfg.limits.manifest_entries {
+                    return Err(Error::ManifestEntriesLimitExceeded {
+                        limit: self.cfg.limits.manifest_entries,
+                    });
+                }
+                manifest.validate().map_err(|err| Error::InvalidEntity {
+                    details: Some(serde_json::json! { err.to_string() }),
+                })?;
+                if manifest.size() != entity.size {
+                    return Err(Error::InvalidEntity {
+                        details: Some(
+                            serde_json::json! { "Directory size doesn't match its manifest" },
+                        ),
+                    });
+                }
+                Ok(())
+            }
+            (EntityKind::Directory, None) => Err(Error::InvalidEntity {
+                details: Some(serde_json::json! { "Directory entity must have manifest" }),
+            }),
+            (EntityKind::File | EntityKind::Text, Some(_)) => Err(Error::InvalidEntity {
+                details: Some(serde_json::json! { "Only directory entity can have manifest" }),
+            }),
+            (EntityKind::File | EntityKind::Text, None) => Ok(()),
+        }
+    }
+
+    /// Checks that optional metadata of the entity is well-formed.
+    fn verify_entity_meta(&self, entity: &AnnouncedEntity) -> Result<(), Error> {
+        let invalid = |details: &str| Error::InvalidEntity {
+            details: Some(serde_json::json! { details }),
+        };
+        let meta = &entity.meta;
+
+        if let Some(mime_type) = &meta.mime_type {
+            if !is_mime_type(mime_type) {
+                return Err(invalid("Invalid MIME type"));
+            }
+        }
+        if let Some(digest) = &meta.digest {
+            if !digest.is_valid() {
+                return Err(invalid("Invalid digest"));
+            }
+            // Content of text is known, so its digest is checked right away
+            if let Some(content) = &entity.content {
+                if *digest != Digest::sha256(content.as_bytes()) {
+                    return Err(invalid("Digest doesn't match text content"));
+                }
+            }
+        }
+        if let Some(modified_at) = meta.modified_at {
+            let max_skew = chrono::Duration::from_std(MAX_CLOCK_SKEW)
+                .expect("clock skew is within chrono range");
+            if modified_at > Utc::now() + max_skew {
+                return Err(invalid("Modification time is in the future"));
+            }
+        }
+        if let Some(thumbnail) = &meta.thumbnail {
+            if !is_mime_type(&thumbnail.mime_type) || !thumbnail.mime_type.starts_with("image/") {
+                return Err(invalid("Thumbnail must be an image"));
+            }
+            if thumbnail.data.0.is_empty() {
+                return Err(invalid("Thumbnail is empty"));
+            }
+            if thumbnail.data.0.len() > self.cfg.limits.thumbnail_size {
+                return Err(Error::ThumbnailSizeLimitExceeded {
+                    limit: self.cfg.limits.thumbnail_size,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds peer to the existing room if it is not locked and not full.
+    async fn join_room(&self, room_id: RoomId, peer_id: PeerId) -> Result<(), Error> {
+        let room = self
+            .storage
+            .get_room(room_id)
+            .await?
+            .ok_or(Error::RoomNotFound { room_id })?;
+        if room.locked {
+            return Err(Error::RoomLocked { room_id });
+        }
+        if room.peers.len() >= room.options.capacity {
+            return Err(Error::RoomCapacityLimitExceeded {
+                room_id,
+                limit: room.options.capacity,
+            });
+        }
+
+        self.storage
+            .add_room_peer(room_id, peer_id)
+            .await?
+            .ok_or(Error::RoomNotFound { room_id })?;
+        Ok(())
+    }
+
+    /// Returns not expired invites created for the room.
+    async fn room_invites(&self, room_id: RoomId) -> Result<Vec<storage::Invite>, Error> {
+        let mut invites = Vec::new();
+        for invite in self.storage.room_invites(room_id).await? {
+            if invite.is_expired(self.cfg.ttl.invite) {
+                self.storage.remove_invite(&invite.passphrase).await?;
+            } else {
+                invites.push(invite);
+            }
+        }
+
+        Ok(invites)
+    }
+
+    /// Verifies token of the room host. Returns host and room ids.
+    async fn verify_host_token(&self, token: &str) -> Result<(PeerId, RoomId), Error> {
+        let (peer_id, room_id) = self.verify_room_token(token).await?;
+        let room = self
+            .storage
+            .get_room(room_id)
+            .await?
+            .ok_or(Error::RoomNotFound { room_id })?;
+        if room.host_id != peer_id {
+            return Err(Error::PermissionDenied {
+                room_id: Some(room_id),
+                peer_id,
+                details: Some(serde_json::json! { "Only the host can moderate the room" }),
+            });
+        }
+
+        Ok((peer_id, room_id))
+    }
+
+    /// Checks that the peer is connected to the room its token is scoped to.
+    async fn verify_connected(&self, token: &PeerToken) -> Result<(PeerId, RoomId), Error> {
+        let peer_id = token.peer_id;
+        let Some(room_id) = token.room_id else {
+            return Err(Error::PermissionDenied {
+                room_id: None,
+                peer_id,
+                details: Some(serde_json::json! { "Token is not bound to any room" }),
+            });
+        };
+
+        let peer = self
+            .storage
+            .get_peer(peer_id)
+            .await?
+            .ok_or(Error::PeerNotFound { peer_id })?;
+        match peer.state {
+            storage::PeerState::Connected {
+                room_id: connected_room_id,
+                ..
+            } if connected_room_id == room_id => Ok((peer_id, room_id)),
+            _ => Err(Error::PermissionDenied {
+                room_id: Some(room_id),
+                peer_id,
+                details: Some(serde_json::json! { "Peer is not connected to the room" }),
+            }),
+        }
+    }
+}
+
+impl Drop for Rpc {
+    fn drop(&mut self) {
+        self.reaper.abort();
+    }
+}
+
+/// Number and total size of entities.
+#[derive(Debug, Default)]
+struct EntitiesUsage {
+    count: usize,
+    size: usize,
+}
+
+impl EntitiesUsage {
+    fn add(&mut self, size: usize) {
+        self.count += 1;
+        self.size = self.size.saturating_add(size);
+    }
 }
 
 #[async_trait]
 impl RpcServer for Rpc {
     #[instrument(skip(self))]
-    fn invite(
+    async fn refresh_token(&self, token: PeerTokenEncoded) -> Result<PeerTokenEncoded, Error> {
+        let token = self.verify_token(&token)?;
+        let peer_id = token.peer_id;
+        match token.room_id {
+            Some(_) => {
+                self.verify_connected(&token).await?;
+            }
+            None => {
+                self.storage
+                    .get_peer(peer_id)
+                    .await?
+                    .ok_or(Error::PeerNotFound { peer_id })?;
+            }
+        }
+
+        let refreshed = self.issue_token(peer_id, token.room_id)?;
+        self.tokens.revoke(&token);
+
+        tracing::info!(?peer_id, room_id = ?token.room_id, "Token refreshed");
+        Ok(refreshed)
+    }
+
+    #[instrument(skip(self))]
+    async fn invite(
         &self,
+        ext: &Extensions,
         token: PeerTokenEncoded,
         invite_passphrase: InvitePassphrase,
     ) -> Result<(), Error> {
-        todo!()
+        let token = self.verify_token(&token)?;
+        let guest_id = token.peer_id;
+        if let Some(room_id) = token.room_id {
+            return Err(Error::PeerAlreadyConnected {
+                peer_id: guest_id,
+                room_id,
+            });
+        }
+
+        let ClientAddr(guest_addr) = *ext
+            .get::<ClientAddr>()
+            .ok_or_else(|| Error::Other(anyhow::anyhow!("Client address is unknown")))?;
+        self.attempts.check(guest_id, guest_addr)?;
+
+        let invite_passphrase = passphrase::normalize(&invite_passphrase);
+        match self.redeem_invite(guest_id, &invite_passphrase).await {
+            Ok(()) => {
+                self.attempts.succeed(guest_id, &invite_passphrase);
+                Ok(())
+            }
+            Err(err) => {
+                self.fail_invite_attempt(guest_id, guest_addr, &invite_passphrase, &err)
+                    .await?;
+                Err(err)
+            }
+        }
     }
 
-    fn announce_entity(
+    #[instrument(skip(self))]
+    async fn announce_entity(
         &self,
         token: PeerTokenEncoded,
         entity: AnnouncedEntity,
     ) -> Result<EntityId, Error> {
-        todo!()
+        let (peer_id, room_id) = self.verify_room_token(&token).await?;
+        self.verify_entity_content(&entity)?;
+        self.verify_entity_manifest(&entity)?;
+        self.verify_entity_meta(&entity)?;
+        let room = self
+            .storage
+            .get_room(room_id)
+            .await?
+            .ok_or(Error::RoomNotFound { room_id })?;
+
+        let mut room_usage = EntitiesUsage::default();
+        let mut peer_usage = EntitiesUsage::default();
+        for entity_id in room.entities {
+            if let Some(entity) = self.storage.get_entity(entity_id).await? {
+                room_usage.add(entity.size);
+                if entity.owner_id == peer_id {
+                    peer_usage.add(entity.size);
+                }
+            }
+        }
+        room_usage.add(entity.size);
+        peer_usage.add(entity.size);
+
+        let limits = &self.cfg.limits;
+        if room_usage.count > limits.room_entities {
+            return Err(Error::RoomEntitiesLimitExceeded {
+                room_id,
+                limit: limits.room_entities,
+            });
+        }
+        if room_usage.size > limits.room_entities_size {
+            return Err(Error::RoomEntitiesSizeLimitExceeded {
+                room_id,
+                limit: limits.room_entities_size,
+            });
+        }
+        if peer_usage.count > limits.peer_entities {
+            return Err(Error::PeerEntitiesLimitExceeded {
+                peer_id,
+                limit: limits.peer_entities,
+            });
+        }
+        if peer_usage.size > limits.peer_entities_size {
+            return Err(Error::PeerEntitiesSizeLimitExceeded {
+                peer_id,
+                limit: limits.peer_entities_size,
+            });
+        }
+
+        let entity_id = Uuid::new_v4();
+        self.storage
+            .add_entity(storage::Entity {
+                id: entity_id,
+                create_at: Utc::now(),
+                room_id,
+                kind: entity.kind,
+                name: entity.name,
+                size: entity.size,
+                owner_id: peer_id,
+                content: entity.content,
+                manifest: entity.manifest,
+                meta: entity.meta,
+                transfers: Vec::new(),
+            })
+            .await?;
+        self.storage
+            .add_room_entity(room_id, entity_id)
+            .await?
+            .ok_or(Error::RoomNotFound { room_id })?;
+
+        tracing::info!(?room_id, ?peer_id, ?entity_id, "Entity announced");
+        publish_room_update(&*self.storage, &self.hub, room_id).await?;
+        Ok(entity_id)
     }
 
-    fn remove_entity(&self, token: PeerTokenEncoded, entity_id: EntityId) -> Result<(), Error> {
-        todo!()
+    #[instrument(skip(self))]
+    async fn remove_entity(
+        &self,
+        token: PeerTokenEncoded,
+        entity_id: EntityId,
+    ) -> Result<(), Error> {
+        let (peer_id, room_id) = self.verify_room_token(&token).await?;
+        let entity = self.get_room_entity(room_id, entity_id).await?;
+        if entity.owner_id != peer_id {
+            return Err(Error::PermissionDenied {
+                room_id: Some(room_id),
+                peer_id,
+                details: Some(serde_json::json!({
+                    "message": "Only the owner can remove the entity",
+                    "entity_id": entity_id,
+                    "owner_id": entity.owner_id,
+                })),
+            });
+        }
+
+        self.storage.remove_room_entity(room_id, entity_id).await?;
+        self.storage.remove_entity(entity_id).await?;
+
+        tracing::info!(?room_id, ?peer_id, ?entity_id, "Entity removed");
+        publish_room_update(&*self.storage, &self.hub, room_id).await
     }
 
-    fn get_room_state(&self, token: PeerTokenEncoded) -> Result<Room, Error> {
-        todo!()
+    #[instrument(skip(self))]
+    async fn request_entity(
+        &self,
+        token: PeerTokenEncoded,
+        entity_id: EntityId,
+    ) -> Result<(), Error> {
+        let (peer_id, room_id) = self.verify_room_token(&token).await?;
+        let entity = self.get_room_entity(room_id, entity_id).await?;
+        if entity.owner_id == peer_id {
+            return Err(Error::SamePeer {
+                peer_id,
+                details: Some(serde_json::json! { "Peer can't request its own entity" }),
+            });
+        }
+        if entity.kind == EntityKind::Text {
+            return Err(Error::InvalidEntity {
+                details: Some(serde_json::json! { "Text is delivered with the room" }),
+            });
+        }
+        // Pending request may be repeated, e.g. after the owner reconnects
+        if let Some(state @ TransferState::Transferring) = transfer_state(&entity, peer_id) {
+            return Err(Error::InvalidTransferState {
+                entity_id,
+                peer_id,
+                state,
+            });
+        }
+
+        self.storage
+            .set_entity_transfer(entity_id, peer_id, TransferState::Pending)
+            .await?
+            .ok_or(Error::EntityNotFound { room_id, entity_id })?;
+        self.hub.send_to_peer(
+            entity.owner_id,
+            PeerEvent::EntityRequested {
+                by: peer_id,
+                entity_id,
+            },
+        )?;
+
+        tracing::info!(?room_id, ?peer_id, ?entity_id, "Entity requested");
+        publish_room_update(&*self.storage, &self.hub, room_id).await
     }
 
-    async fn sub_peer_events(
+    #[instrument(skip(self))]
+    async fn answer_entity_request(
         &self,
-        subscription_sink: PendingSubscriptionSink,
-    ) -> SubscriptionResult {
-        let sink = subscription_sink.accept().await?;
-        let mut subscribe_closed = pin!(sink.closed());
+        token: PeerTokenEncoded,
+        entity_id: EntityId,
+        peer_id: PeerId,
+        accept: bool,
+    ) -> Result<(), Error> {
+        let (owner_id, room_id) = self.verify_room_token(&token).await?;
+        let entity = self.get_room_entity(room_id, entity_id).await?;
+        if entity.owner_id != owner_id {
+            return Err(Error::PermissionDenied {
+                room_id: Some(room_id),
+                peer_id: owner_id,
+                details: Some(serde_json::json!({
+                    "message": "Only the owner can answer entity requests",
+                    "entity_id": entity_id,
+                    "owner_id": entity.owner_id,
+                })),
+            });
+        }
+        match transfer_state(&entity, peer_id) {
+            Some(TransferState::Pending) => {}
+            Some(state) => {
+                return Err(Error::InvalidTransferState {
+                    entity_id,
+                    peer_id,
+                    state,
+                })
+            }
+            None => return Err(Error::TransferNotFound { entity_id, peer_id }),
+        }
 
-        let peer_id = Uuid::new_v4();
-        let init_token = PeerToken {
-            peer_id,
-            room_id: None,
-            exp: None,
+        let state = if accept {
+            TransferState::Transferring
+        } else {
+            TransferState::Declined
         };
-        let init_token = init_token.encode(&self.cfg.server.secret)?;
-        let invite_passphrase = create_invite(&self.mongodb_client, peer_id).await?;
+        self.storage
+            .set_entity_transfer(entity_id, peer_id, state)
+            .await?
+            .ok_or(Error::EntityNotFound { room_id, entity_id })?;
+        // Progress of the previous transfer isn't relevant anymore
+        self.progress.remove(entity_id, peer_id);
+
+        tracing::info!(
+            ?room_id,
+            ?peer_id,
+            ?entity_id,
+            accept,
+            "Entity request answered"
+        );
+        publish_room_update(&*self.storage, &self.hub, room_id).await
+    }
 
-        defer! {
-            let mongodb_client = self.mongodb_client.clone();
-            tokio::spawn(async move {
-                let _ = storage::remove_invite(&mongodb_client, &invite_passphrase).await;
+    #[instrument(skip(self))]
+    async fn report_transfer(
+        &self,
+        token: PeerTokenEncoded,
+        entity_id: EntityId,
+        peer_id: PeerId,
+        state: TransferState,
+    ) -> Result<(), Error> {
+        let (reporter_id, room_id) = self.verify_room_token(&token).await?;
+        // Declining is the owner's answer, not an outcome of the transfer
+        if !state.is_finished() || state == TransferState::Declined {
+            return Err(Error::InvalidTransferState {
+                entity_id,
+                peer_id,
+                state,
             });
         }
 
-        sink.send(
-            PeerEvent::Init {
-                token: init_token,
-                invite_passphrase,
+        self.verify_transferring(room_id, reporter_id, entity_id, peer_id)
+            .await?;
+
+        self.storage
+            .set_entity_transfer(entity_id, peer_id, state)
+            .await?
+            .ok_or(Error::EntityNotFound { room_id, entity_id })?;
+        self.progress.remove(entity_id, peer_id);
+
+        tracing::info!(?room_id, ?peer_id, ?entity_id, ?state, "Transfer reported");
+        publish_room_update(&*self.storage, &self.hub, room_id).await
+    }
+
+    #[instrument(skip(self))]
+    async fn report_progress(
+        &self,
+        token: PeerTokenEncoded,
+        entity_id: EntityId,
+        peer_id: PeerId,
+        transferred: usize,
+    ) -> Result<(), Error> {
+        let (reporter_id, room_id) = self.verify_room_token(&token).await?;
+        let entity = self
+            .verify_transferring(room_id, reporter_id, entity_id, peer_id)
+            .await?;
+        if transferred > entity.size {
+            return Err(Error::InvalidTransferProgress {
+                transferred,
+                size: entity.size,
+            });
+        }
+
+        if let Some(progress) = self
+            .progress
+            .report(entity_id, peer_id, transferred, entity.size)
+        {
+            self.hub.publish_to_room(
+                room_id,
+                PeerEvent::TransferProgress {
+                    entity_id,
+                    peer_id,
+                    progress,
+                },
+            );
+        }
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    async fn get_room_state(&self, token: PeerTokenEncoded) -> Result<Room, Error> {
+        let (_, room_id) = self.verify_room_token(&token).await?;
+        storage::load_room(&*self.storage, room_id).await
+    }
+
+    #[instrument(skip(self, payload))]
+    async fn send_signal(
+        &self,
+        token: PeerTokenEncoded,
+        to_peer_id: PeerId,
+        payload: SignalPayload,
+    ) -> Result<(), Error> {
+        let (peer_id, room_id) = self.verify_room_token(&token).await?;
+        self.verify_room_peer(peer_id, room_id, to_peer_id).await?;
+
+        self.hub.send_to_peer(
+            to_peer_id,
+            PeerEvent::Signal {
+                from_peer_id: peer_id,
+                payload,
+            },
+        )
+    }
+
+    #[instrument(skip(self))]
+    async fn request_relay(
+        &self,
+        token: PeerTokenEncoded,
+        to_peer_id: PeerId,
+    ) -> Result<(), Error> {
+        if !self.cfg.relay.enabled {
+            return Err(Error::RelayDisabled);
+        }
+
+        let (peer_id, room_id) = self.verify_room_token(&token).await?;
+        self.verify_room_peer(peer_id, room_id, to_peer_id).await?;
+
+        self.hub.send_to_peer(
+            to_peer_id,
+            PeerEvent::Transport {
+                peer_id,
+                transport: Transport::Relay,
+            },
+        )?;
+        self.hub.send_to_peer(
+            peer_id,
+            PeerEvent::Transport {
+                peer_id: to_peer_id,
+                transport: Transport::Relay,
+            },
+        )?;
+
+        tracing::info!(?room_id, ?peer_id, ?to_peer_id, "Relay requested");
+        Ok(())
+    }
+
+    #[instrument(skip(self, data))]
+    async fn relay_frame(
+        &self,
+        token: PeerTokenEncoded,
+        to_peer_id: PeerId,
+        data: RelayData,
+    ) -> Result<(), Error> {
+        let (peer_id, room_id) = self.verify_room_token(&token).await?;
+        self.verify_room_peer(peer_id, room_id, to_peer_id).await?;
+        self.relay.consume(room_id, data.0.len())?;
+
+        self.hub.send_relay_frame(
+            to_peer_id,
+            RelayFrame {
+                from_peer_id: peer_id,
+                data,
+            },
+        )
+    }
+
+    #[instrument(skip(self))]
+    async fn kick_peer(&self, token: PeerTokenEncoded, peer_id: PeerId) -> Result<(), Error> {
+        let (host_id, room_id) = self.verify_host_token(&token).await?;
+        self.verify_room_peer(host_id, room_id, peer_id).await?;
+
+        self.tokens.revoke_room_peer(peer_id, room_id);
+        self.storage
+            .set_peer_state(peer_id, storage::PeerState::Disconnected)
+            .await?;
+        // Subscription may be already closed
+        let _ = self.hub.send_to_peer(
+            peer_id,
+            PeerEvent::Disconnect {
+                room_id: Some(room_id),
+                reason: DisconnectReason::Kicked,
+            },
+        );
+
+        tracing::info!(?room_id, ?host_id, ?peer_id, "Peer kicked");
+        leave_room(&*self.storage, &self.hub, peer_id, room_id).await
+    }
+
+    #[instrument(skip(self))]
+    async fn close_room(&self, token: PeerTokenEncoded) -> Result<(), Error> {
+        let (host_id, room_id) = self.verify_host_token(&token).await?;
+        if let Some(room) = self.storage.get_room(room_id).await? {
+            for peer_id in room.peers {
+                self.tokens.revoke_room_peer(peer_id, room_id);
             }
-            .try_into()?,
+        }
+
+        tracing::info!(?room_id, ?host_id, "Room closed by host");
+        close_room(
+            &*self.storage,
+            &self.hub,
+            room_id,
+            DisconnectReason::RoomClosed,
+        )
+        .await
+    }
+
+    #[instrument(skip(self))]
+    async fn lock_room(&self, token: PeerTokenEncoded, locked: bool) -> Result<(), Error> {
+        let (host_id, room_id) = self.verify_host_token(&token).await?;
+        self.storage
+            .set_room_locked(room_id, locked)
+            .await?
+            .ok_or(Error::RoomNotFound { room_id })?;
+
+        tracing::info!(?room_id, ?host_id, locked, "Room lock changed");
+        publish_room_update(&*self.storage, &self.hub, room_id).await
+    }
+
+    #[instrument(skip(self))]
+    async fn set_room_options(
+        &self,
+        token: PeerTokenEncoded,
+        options: RoomOptions,
+    ) -> Result<(), Error> {
+        let (host_id, room_id) = self.verify_host_token(&token).await?;
+        let room = self
+            .storage
+            .get_room(room_id)
+            .await?
+            .ok_or(Error::RoomNotFound { room_id })?;
+
+        let limits = &self.cfg.limits;
+        if options.capacity > limits.room_capacity {
+            return Err(Error::RoomCapacityLimitExceeded {
+                room_id,
+                limit: limits.room_capacity,
+            });
+        }
+        if options.max_invites > limits.room_invites {
+            return Err(Error::RoomInvitesLimitExceeded {
+                room_id,
+                limit: limits.room_invites,
+            });
+        }
+        if options.capacity < room.peers.len().max(2) {
+            return Err(Error::InvalidRoomOptions {
+                room_id,
+                details: Some(serde_json::json!({
+                    "message": "Capacity is less than the number of peers in the room",
+                    "peers": room.peers.len(),
+                })),
+            });
+        }
+        if let Some(invite_ttl) = options.invite_ttl {
+            if invite_ttl == 0 || invite_ttl > self.cfg.ttl.invite.as_secs() {
+                return Err(Error::InvalidRoomOptions {
+                    room_id,
+                    details: Some(serde_json::json!({
+                        "message": "Invite TTL must be positive and not exceed the server one",
+                        "max_invite_ttl": self.cfg.ttl.invite.as_secs(),
+                    })),
+                });
+            }
+        }
+
+        self.storage
+            .set_room_options(room_id, options.clone())
+            .await?
+            .ok_or(Error::RoomNotFound { room_id })?;
+
+        tracing::info!(?room_id, ?host_id, ?options, "Room options changed");
+        publish_room_update(&*self.storage, &self.hub, room_id).await
+    }
+
+    #[instrument(skip(self))]
+    async fn create_invite(&self, token: PeerTokenEncoded) -> Result<Invite, Error> {
+        let (host_id, room_id) = self.verify_host_token(&token).await?;
+        let room = self
+            .storage
+            .get_room(room_id)
+            .await?
+            .ok_or(Error::RoomNotFound { room_id })?;
+        if room.locked {
+            return Err(Error::RoomLocked { room_id });
+        }
+
+        let invites = self.room_invites(room_id).await?;
+        if invites.len() >= room.options.max_invites {
+            return Err(Error::RoomInvitesLimitExceeded {
+                room_id,
+                limit: room.options.max_invites,
+            });
+        }
+        if room.peers.len() + invites.len() >= room.options.capacity {
+            return Err(Error::RoomCapacityLimitExceeded {
+                room_id,
+                limit: room.options.capacity,
+            });
+        }
+
+        let invite = create_invite(
+            &*self.storage,
+            &self.passphrases,
+            &self.cfg.ttl,
+            host_id,
+            Some(room_id),
+            room.options.invite_ttl.map(Duration::from_secs),
         )
         .await?;
 
+        tracing::info!(?room_id, ?host_id, "Room invite created");
+        Ok(invite.into_public(self.cfg.ttl.invite))
+    }
+
+    #[instrument(skip(self))]
+    async fn list_invites(&self, token: PeerTokenEncoded) -> Result<Vec<Invite>, Error> {
+        let (_, room_id) = self.verify_room_token(&token).await?;
+        Ok(self
+            .room_invites(room_id)
+            .await?
+            .into_iter()
+            .map(|invite| invite.into_public(self.cfg.ttl.invite))
+            .collect())
+    }
+
+    #[instrument(skip(self))]
+    async fn revoke_invite(
+        &self,
+        token: PeerTokenEncoded,
+        invite_passphrase: InvitePassphrase,
+    ) -> Result<(), Error> {
+        let (host_id, room_id) = self.verify_host_token(&token).await?;
+        match self.storage.get_invite(&invite_passphrase).await? {
+            Some(invite) if invite.room_id == Some(room_id) => {
+                self.storage.remove_invite(&invite_passphrase).await?;
+            }
+            _ => return Err(Error::InviteNotFound { invite_passphrase }),
+        }
+
+        tracing::info!(?room_id, ?host_id, "Room invite revoked");
+        Ok(())
+    }
+
+    async fn sub_relay_frames(
+        &self,
+        subscription_sink: PendingSubscriptionSink,
+        token: PeerTokenEncoded,
+    ) -> SubscriptionResult {
+        let peer_id = match self.verify_room_token(&token).await {
+            Ok((peer_id, _)) if self.cfg.relay.enabled => peer_id,
+            Ok(_) => {
+                subscription_sink.reject(Error::RelayDisabled).await;
+                return Ok(());
+            }
+            Err(err) => {
+                subscription_sink.reject(err).await;
+                return Ok(());
+            }
+        };
+
+        let sink = subscription_sink.accept().await?;
+        let mut subscribe_closed = pin!(sink.closed());
+        let mut relay_sub = self
+            .hub
+            .subscribe_relay(peer_id, self.cfg.relay.peer_buffer);
+
         loop {
             tokio::select! {
-
+                Some(frame) = relay_sub.recv() => {
+                    sink.send(frame.try_into()?).await?;
+                }
                 _ = &mut subscribe_closed => {
-                    tracing::info!("Subscription closed");
+                    tracing::info!(?peer_id, "Relay subscription closed");
                     return Ok(())
                 }
             }
         }
     }
+
+    async fn sub_peer_events(
+        &self,
+        subscription_sink: PendingSubscriptionSink,
+        hello: Option<ClientHello>,
+    ) -> SubscriptionResult {
+        let capabilities = match self.negotiate(hello) {
+            Ok(capabilities) => capabilities,
+            Err(err) => {
+                subscription_sink.reject(err).await;
+                return Ok(());
+            }
+        };
+
+        let sink = subscription_sink.accept().await?;
+
+        let peer_id = Uuid::new_v4();
+        self.storage
+            .add_peer(storage::Peer {
+                id: peer_id,
+                create_at: Utc::now(),
+                state: storage::PeerState::Disconnected,
+            })
+            .await?;
+
+        let peer_sub = self.hub.subscribe_peer(peer_id);
+        let token = self.issue_token(peer_id, None)?;
+        let invite_passphrase = create_invite(
+            &*self.storage,
+            &self.passphrases,
+            &self.cfg.ttl,
+            peer_id,
+            None,
+            None,
+        )
+        .await?
+        .passphrase;
+
+        let session = PeerSession {
+            peer_id,
+            invite_passphrase,
+            peer_sub,
+            room_sub: None,
+        };
+        self.run_session(sink, session, token, capabilities).await
+    }
+
+    async fn resume_session(
+        &self,
+        subscription_sink: PendingSubscriptionSink,
+        hello: Option<ClientHello>,
+        token: PeerTokenEncoded,
+    ) -> SubscriptionResult {
+        let verified = self
+            .negotiate(hello)
+            .and_then(|capabilities| Ok((capabilities, self.verify_token(&token)?)));
+        let (capabilities, token) = match verified {
+            Ok(verified) => verified,
+            Err(err) => {
+                subscription_sink.reject(err).await;
+                return Ok(());
+            }
+        };
+
+        let peer_id = token.peer_id;
+        let Some(session) = self.sessions.resume(peer_id).await else {
+            subscription_sink
+                .reject(Error::SessionNotFound { peer_id })
+                .await;
+            return Ok(());
+        };
+
+        // Session is detached again if the subscription can't be accepted
+        let session = scopeguard::guard(session, |session| self.sessions.detach(session));
+        let sink = subscription_sink.accept().await?;
+        let session = ScopeGuard::into_inner(session);
+
+        self.tokens.revoke(&token);
+        let refreshed = self.issue_token(peer_id, token.room_id)?;
+        tracing::info!(?peer_id, "Session resumed");
+        self.run_session(sink, session, refreshed, capabilities)
+            .await
+    }
 }
 
-async fn create_invite(
-    mongodb_client: &mongodb::Client,
-    peer_id: PeerId,
-) -> Result<InvitePassphrase, Error> {
-    let mut session = mongodb_client.start_session(None).await?;
-
-    let invite_passphrase = session
-        .with_transaction(
-            (),
-            |session, _ctx| {
-                async move {
-                    let client = session.client();
-                    // While not found free unique alias
-                    loop {
-                        let invite_passphrase = generate_invite_passphrase();
-
-                        match storage::get_invite(&client, &invite_passphrase).await? {
-                            None => {
-                                storage::add_invite(
-                                    &client,
-                                    storage::Invite {
-                                        passphrase: invite_passphrase.clone(),
-                                        peer_id,
-                                        create_at: Utc::now(),
-                                    },
-                                )
-                                .await?;
-                            }
-                            Some(invite) if invite.is_expired() => {
-                                storage::remove_invite(&client, &invite_passphrase).await?;
-                                storage::add_invite(
-                                    &client,
-                                    storage::Invite {
-                                        passphrase: invite_passphrase.clone(),
-                                        peer_id,
-                                        create_at: Utc::now(),
-                                    },
-                                )
-                                .await?;
-                            }
-                            _ => continue,
-                        }
+/// Completes peer connection to the room it is connecting to. Returns the room id.
+async fn connect_peer(storage: &dyn Storage, peer_id: PeerId) -> Result<RoomId, Error> {
+    let peer = storage
+        .get_peer(peer_id)
+        .await?
+        .ok_or(Error::PeerNotFound { peer_id })?;
 
-                        return Ok(invite_passphrase);
-                    }
-                }
-                .boxed()
+    let storage::PeerState::Connecting { room_id, .. } = peer.state else {
+        return Err(Error::PeerIsBusy {
+            peer_id,
+            details: Some(serde_json::json! { "Peer is not connecting to any room" }),
+        });
+    };
+
+    storage
+        .set_peer_state(
+            peer_id,
+            storage::PeerState::Connected {
+                connected_at: Utc::now(),
+                room_id,
             },
-            None,
         )
         .await?;
 
-    Ok(invite_passphrase)
+    tracing::info!(?room_id, ?peer_id, "Peer connected to room");
+    Ok(room_id)
 }
 
-fn generate_invite_passphrase() -> String {
-    const LEN: usize = 6;
-    const CHARSET: [char; 31] = [
-        '2', '3', '4', '5', '6', '7', '8', '9', 'a', 'b', 'c', 'd', 'e', 'f', 'g', 'h', 'j', 'k',
-        'm', 'n', 'p', 'q', 'r', 's', 't', 'u', 'v', 'w', 'x', 'y', 'z',
-    ];
+/// Removes peer and leaves its room. The room is removed when the last peer leaves.
+pub(super) async fn disconnect_peer(
+    storage: &dyn Storage,
+    hub: &EventHub,
+    peer_id: PeerId,
+) -> Result<(), Error> {
+    let Some(peer) = storage.remove_peer(peer_id).await? else {
+        return Ok(());
+    };
 
-    let mut rng = rand::thread_rng();
-    (0..LEN)
-        .map(|_| {
-            let idx = rng.gen_range(0..CHARSET.len());
-            CHARSET[idx]
-        })
-        .collect()
+    let room_id: RoomId = match peer.state {
+        storage::PeerState::Disconnected => return Ok(()),
+        storage::PeerState::Connecting { room_id, .. } => room_id,
+        storage::PeerState::Connected { room_id, .. } => room_id,
+    };
+
+    tracing::info!(?room_id, ?peer_id, "Peer disconnected from room");
+    leave_room(storage, hub, peer_id, room_id).await
+}
+
+/// Removes peer and its entities from the room. The room is removed when the last peer
+/// leaves, host role is passed to the longest connected peer when the host leaves.
+async fn leave_room(
+    storage: &dyn Storage,
+    hub: &EventHub,
+    peer_id: PeerId,
+    room_id: RoomId,
+) -> Result<(), Error> {
+    // Entities of the peer are not available anymore
+    if let Some(room) = storage.get_room(room_id).await? {
+        for entity_id in room.entities {
+            match storage.get_entity(entity_id).await? {
+                Some(entity) if entity.owner_id == peer_id => {
+                    storage.remove_room_entity(room_id, entity_id).await?;
+                    storage.remove_entity(entity_id).await?;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    match storage.remove_room_peer(room_id, peer_id).await? {
+        Some(room) if room.peers.is_empty() => {
+            storage.remove_room(room_id).await?;
+            remove_room_invites(storage, room_id).await
+        }
+        Some(room) => {
+            if room.host_id == peer_id {
+                if let Some(host_id) = longest_connected_peer(storage, &room).await? {
+                    storage.set_room_host(room_id, host_id).await?;
+                    tracing::info!(?room_id, ?host_id, "Host role passed");
+                }
+            }
+            publish_room_update(storage, hub, room_id).await
+        }
+        None => Ok(()),
+    }
+}
+
+async fn longest_connected_peer(
+    storage: &dyn Storage,
+    room: &storage::Room,
+) -> Result<Option<PeerId>, Error> {
+    let mut longest: Option<(PeerId, DateTime<Utc>)> = None;
+    for peer_id in &room.peers {
+        let Some(peer) = storage.get_peer(*peer_id).await? else {
+            continue;
+        };
+        let connected_at = match peer.state {
+            storage::PeerState::Connected { connected_at, .. } => connected_at,
+            // Peer that is still connecting has no room token yet
+            storage::PeerState::Connecting { .. } | storage::PeerState::Disconnected => continue,
+        };
+
+        if longest.map_or(true, |(_, longest_at)| connected_at < longest_at) {
+            longest = Some((*peer_id, connected_at));
+        }
+    }
+
+    Ok(longest.map(|(peer_id, _)| peer_id))
+}
+
+/// Disconnects every peer from the room and removes the room with its entities.
+pub(super) async fn close_room(
+    storage: &dyn Storage,
+    hub: &EventHub,
+    room_id: RoomId,
+    reason: DisconnectReason,
+) -> Result<(), Error> {
+    let Some(room) = storage.remove_room(room_id).await? else {
+        return Ok(());
+    };
+    remove_room_invites(storage, room_id).await?;
+
+    for entity_id in room.entities {
+        storage.remove_entity(entity_id).await?;
+    }
+
+    for peer_id in room.peers {
+        storage
+            .set_peer_state(peer_id, storage::PeerState::Disconnected)
+            .await?;
+        // Subscription may be already closed
+        let _ = hub.send_to_peer(
+            peer_id,
+            PeerEvent::Disconnect {
+                room_id: Some(room_id),
+                reason,
+            },
+        );
+    }
+
+    tracing::info!(?room_id, ?reason, "Room closed");
+    Ok(())
+}
+
+async fn remove_room_invites(storage: &dyn Storage, room_id: RoomId) -> Result<(), Error> {
+    for invite in storage.room_invites(room_id).await? {
+        storage.remove_invite(&invite.passphrase).await?;
+    }
+
+    Ok(())
+}
+
+/// Sends actual room state to every peer in the room.
+pub(super) async fn publish_room_update(
+    storage: &dyn Storage,
+    hub: &EventHub,
+    room_id: RoomId,
+) -> Result<(), Error> {
+    let room = storage::load_room(storage, room_id).await?;
+    hub.publish_to_room(room_id, PeerEvent::UpdateRoom { room });
+    Ok(())
+}
+
+/// Creates invite of the peer, or of the room if specified. Invite TTL overrides
+/// the configured one.
+async fn create_invite(
+    storage: &dyn Storage,
+    passphrases: &PassphraseGenerator,
+    ttl: &TtlConfig,
+    peer_id: PeerId,
+    room_id: Option<RoomId>,
+    invite_ttl: Option<Duration>,
+) -> Result<storage::Invite, Error> {
+    for _ in 0..MAX_PASSPHRASE_ATTEMPTS {
+        let invite_passphrase = passphrases.generate();
+
+        match storage.get_invite(&invite_passphrase).await? {
+            None => {}
+            Some(invite) if invite.is_expired(ttl.invite) => {
+                storage.remove_invite(&invite_passphrase).await?;
+            }
+            _ => continue,
+        }
+
+        let invite = storage::Invite {
+            passphrase: invite_passphrase,
+            create_at: Utc::now(),
+            peer_id,
+            room_id,
+            ttl: invite_ttl,
+        };
+        // Another invite may take the passphrase meanwhile
+        match storage.add_invite(invite.clone()).await {
+            Ok(()) => return Ok(invite),
+            Err(Error::InviteAlreadyExists { .. }) => continue,
+            Err(err) => return Err(err),
+        }
+    }
+
+    Err(Error::Other(anyhow::anyhow!(
+        "Failed to generate unique invite passphrase in {MAX_PASSPHRASE_ATTEMPTS} attempts"
+    )))
+}
+
+/// Returns state of the entity transfer to the peer, if the peer requested the entity.
+pub(super) fn transfer_state(entity: &storage::Entity, peer_id: PeerId) -> Option<TransferState> {
+    entity
+        .transfers
+        .iter()
+        .find(|transfer| transfer.peer_id == peer_id)
+        .map(|transfer| transfer.state)
+}
+
+/// Checks `type/subtype` form of MIME type, parameters aren't allowed.
+fn is_mime_type(mime_type: &str) -> bool {
+    const MAX_LEN: usize = 255;
+    let is_token = |s: &str| {
+        !s.is_empty()
+            && s.bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b"!#$&-^_.+".contains(&b))
+    };
+
+    match mime_type.split_once('/') {
+        Some((ty, subtype)) => mime_type.len() <= MAX_LEN && is_token(ty) && is_token(subtype),
+        None => false,
+    }
 }
@@ -0,0 +1,155 @@
+use std::{sync::Arc, time::Duration};
+
+use dashmap::DashMap;
+use drophub::{InvitePassphrase, PeerId};
+use tokio::sync::oneshot;
+use uuid::Uuid;
+
+use super::{
+    hub::{EventHub, PeerSubscription, RoomSubscription},
+    revocation::RevocationList,
+    rpc::disconnect_peer,
+    storage::Storage,
+};
+
+/// Event subscriptions of a connected peer.
+pub struct PeerSession {
+    pub peer_id: PeerId,
+    pub invite_passphrase: InvitePassphrase,
+    pub peer_sub: PeerSubscription,
+    pub room_sub: Option<RoomSubscription>,
+}
+
+/// Sessions of peers whose subscription is closed. Detached session keeps receiving events,
+/// so they are replayed when the peer resumes the session within the grace period.
+pub struct SessionRegistry {
+    storage: Arc<dyn Storage>,
+    hub: Arc<EventHub>,
+    tokens: Arc<RevocationList>,
+    grace_period: Duration,
+    detached: DashMap<PeerId, DetachedSession>,
+    /// Requests to hand over sessions with open subscriptions. Server may not notice
+    /// a dropped connection until the peer resumes the session from a new one.
+    attached: DashMap<PeerId, (Uuid, oneshot::Sender<oneshot::Sender<PeerSession>>)>,
+}
+
+struct DetachedSession {
+    /// Distinguishes detachments of the same peer.
+    id: Uuid,
+    session: PeerSession,
+}
+
+impl SessionRegistry {
+    pub fn new(
+        storage: Arc<dyn Storage>,
+        hub: Arc<EventHub>,
+        tokens: Arc<RevocationList>,
+        grace_period: Duration,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            storage,
+            hub,
+            tokens,
+            grace_period,
+            detached: DashMap::new(),
+            attached: DashMap::new(),
+        })
+    }
+
+    /// Registers session with open subscription, so it can be taken over by a new one.
+    pub fn attach(self: &Arc<Self>, peer_id: PeerId) -> Takeover {
+        let id = Uuid::new_v4();
+        let (tx, rx) = oneshot::channel();
+        self.attached.insert(peer_id, (id, tx));
+
+        Takeover {
+            registry: Arc::clone(self),
+            peer_id,
+            id,
+            rx: Some(rx),
+        }
+    }
+
+    /// Keeps the session for the grace period. The session is closed if it isn't resumed.
+    pub fn detach(self: &Arc<Self>, session: PeerSession) {
+        let peer_id = session.peer_id;
+        let id = Uuid::new_v4();
+        self.detached
+            .insert(peer_id, DetachedSession { id, session });
+        tracing::info!(?peer_id, "Session detached");
+
+        let registry = Arc::clone(self);
+        tokio::spawn(async move {
+            tokio::time::sleep(registry.grace_period).await;
+            if let Some((_, detached)) = registry
+                .detached
+                .remove_if(&peer_id, |_, detached| detached.id == id)
+            {
+                tracing::info!(?peer_id, "Detached session expired");
+                registry.close(detached.session);
+            }
+        });
+    }
+
+    /// Takes detached session of the peer, if the grace period isn't over, or takes over
+    /// session with open subscription.
+    pub async fn resume(&self, peer_id: PeerId) -> Option<PeerSession> {
+        if let Some((_, detached)) = self.detached.remove(&peer_id) {
+            return Some(detached.session);
+        }
+
+        let (_, (_, takeover)) = self.attached.remove(&peer_id)?;
+        let (tx, rx) = oneshot::channel();
+        if takeover.send(tx).is_ok() {
+            if let Ok(session) = rx.await {
+                return Some(session);
+            }
+        }
+
+        // Subscription is closed meanwhile
+        self.detached
+            .remove(&peer_id)
+            .map(|(_, detached)| detached.session)
+    }
+
+    /// Revokes tokens of the peer, removes its invite and disconnects it from the room.
+    pub fn close(&self, session: PeerSession) {
+        let PeerSession {
+            peer_id,
+            invite_passphrase,
+            ..
+        } = session;
+        let storage = self.storage.clone();
+        let hub = self.hub.clone();
+        self.tokens.revoke_peer(peer_id);
+        tokio::spawn(async move {
+            let _ = storage.remove_invite(&invite_passphrase).await;
+            let _ = disconnect_peer(&*storage, &hub, peer_id).await;
+        });
+    }
+}
+
+/// Receives request to hand over the session. Registration is removed when dropped.
+pub struct Takeover {
+    registry: Arc<SessionRegistry>,
+    peer_id: PeerId,
+    id: Uuid,
+    rx: Option<oneshot::Receiver<oneshot::Sender<PeerSession>>>,
+}
+
+impl Takeover {
+    /// Waits for takeover request. Returns channel the session must be sent to.
+    pub async fn requested(&mut self) -> Option<oneshot::Sender<PeerSession>> {
+        let reply = self.rx.as_mut()?.await.ok();
+        self.rx = None;
+        reply
+    }
+}
+
+impl Drop for Takeover {
+    fn drop(&mut self) {
+        self.registry
+            .attached
+            .remove_if(&self.peer_id, |_, (id, _)| *id == self.id);
+    }
+}
@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+
+use drophub::{EntityId, Error, PeerId, RoomId};
+
+use crate::server::storage::{
+    models::{Entity, Peer, PeerState, Room},
+    Storage,
+};
+
+impl From<Entity> for drophub::Entity {
+    fn from(f: Entity) -> Self {
+        drophub::Entity {
+            kind: f.kind,
+            name: f.name,
+            size: f.size,
+            owner_id: f.owner_id,
+            content: f.content,
+            manifest: f.manifest,
+            meta: f.meta,
+            transfers: f
+                .transfers
+                .into_iter()
+                .map(|transfer| (transfer.peer_id, transfer.state))
+                .collect(),
+        }
+    }
+}
+
+/// Joins stored room with its peers and entities into the public room shape.
+///
+/// Peers that are not connected to the room, transfers to peers that left it and records
+/// that are not listed in the room are skipped.
+fn room_with_peers(room: Room, peers: Vec<Peer>, entities: Vec<Entity>) -> drophub::Room {
+    let entities: HashMap<EntityId, drophub::Entity> = entities
+        .into_iter()
+        .filter(|entity| room.entities.contains(&entity.id))
+        .map(|entity| {
+            let entity_id = entity.id;
+            let mut entity = drophub::Entity::from(entity);
+            entity
+                .transfers
+                .retain(|peer_id, _| room.peers.contains(peer_id));
+            (entity_id, entity)
+        })
+        .collect();
+
+    let peers: HashMap<PeerId, drophub::Peer> = peers
+        .into_iter()
+        .filter(|peer| room.peers.contains(&peer.id))
+        .filter_map(|peer| {
+            let PeerState::Connected {
+                connected_at,
+                room_id,
+            } = peer.state
+            else {
+                return None;
+            };
+            if room_id != room.id {
+                return None;
+            }
+
+            let peer_entities = entities
+                .iter()
+                .filter(|(_, entity)| entity.owner_id == peer.id)
+                .map(|(entity_id, _)| *entity_id)
+                .collect();
+
+            Some((
+                peer.id,
+                drophub::Peer {
+                    connected_ts: connected_at,
+                    entities: peer_entities,
+                },
+            ))
+        })
+        .collect();
+
+    drophub::Room {
+        id: room.id,
+        host: room.host_id,
+        locked: room.locked,
+        options: room.options,
+        entities,
+        peers,
+    }
+}
+
+/// Loads room with its peers and entities from storage and joins them into the public room shape.
+pub async fn load_room(storage: &dyn Storage, room_id: RoomId) -> Result<drophub::Room, Error> {
+    let room = storage
+        .get_room(room_id)
+        .await?
+        .ok_or(Error::RoomNotFound { room_id })?;
+
+    let mut peers = Vec::with_capacity(room.peers.len());
+    for peer_id in &room.peers {
+        if let Some(peer) = storage.get_peer(*peer_id).await? {
+            peers.push(peer);
+        }
+    }
+
+    let mut entities = Vec::with_capacity(room.entities.len());
+    for entity_id in &room.entities {
+        if let Some(entity) = storage.get_entity(*entity_id).await? {
+            entities.push(entity);
+        }
+    }
+
+    Ok(room_with_peers(room, peers, entities))
+}
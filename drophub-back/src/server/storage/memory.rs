@@ -0,0 +1,254 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use dashmap::{mapref::entry::Entry, DashMap};
+use drophub::{EntityId, Error, InvitePassphrase, PeerId, RoomId, RoomOptions, TransferState};
+use tracing::instrument;
+
+use crate::server::storage::{
+    models::{Entity, EntityTransfer, Invite, Peer, PeerState, Room},
+    Storage,
+};
+
+/// Storage that keeps everything in process memory. Data is lost on restart.
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    invites: DashMap<InvitePassphrase, Invite>,
+    peers: DashMap<PeerId, Peer>,
+    rooms: DashMap<RoomId, Room>,
+    entities: DashMap<EntityId, Entity>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn update_room<F>(&self, room_id: RoomId, f: F) -> Option<Room>
+    where
+        F: FnOnce(&mut Room),
+    {
+        self.rooms.get_mut(&room_id).map(|mut room| {
+            f(&mut room);
+            room.clone()
+        })
+    }
+}
+
+#[async_trait]
+impl Storage for MemoryStorage {
+    #[instrument(skip(self))]
+    async fn add_invite(&self, invite: Invite) -> Result<(), Error> {
+        match self.invites.entry(invite.passphrase.clone()) {
+            Entry::Vacant(entry) => {
+                entry.insert(invite);
+                Ok(())
+            }
+            Entry::Occupied(entry) => Err(Error::InviteAlreadyExists {
+                invite_passphrase: entry.key().clone(),
+            }),
+        }
+    }
+
+    #[instrument(skip(self))]
+    async fn remove_invite(&self, invite_passphrase: &str) -> Result<Option<Invite>, Error> {
+        Ok(self
+            .invites
+            .remove(invite_passphrase)
+            .map(|(_, invite)| invite))
+    }
+
+    #[instrument(skip(self))]
+    async fn get_invite(&self, invite_passphrase: &str) -> Result<Option<Invite>, Error> {
+        Ok(self
+            .invites
+            .get(invite_passphrase)
+            .map(|invite| invite.clone()))
+    }
+
+    #[instrument(skip(self))]
+    async fn invites_created_before(&self, time: DateTime<Utc>) -> Result<Vec<Invite>, Error> {
+        Ok(collect_created_before(&self.invites, time, |invite| {
+            invite.create_at
+        }))
+    }
+
+    #[instrument(skip(self))]
+    async fn room_invites(&self, room_id: RoomId) -> Result<Vec<Invite>, Error> {
+        Ok(self
+            .invites
+            .iter()
+            .filter(|invite| invite.room_id == Some(room_id))
+            .map(|invite| invite.value().clone())
+            .collect())
+    }
+
+    #[instrument(skip(self))]
+    async fn add_peer(&self, peer: Peer) -> Result<(), Error> {
+        self.peers.insert(peer.id, peer);
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    async fn remove_peer(&self, peer_id: PeerId) -> Result<Option<Peer>, Error> {
+        Ok(self.peers.remove(&peer_id).map(|(_, peer)| peer))
+    }
+
+    #[instrument(skip(self))]
+    async fn get_peer(&self, peer_id: PeerId) -> Result<Option<Peer>, Error> {
+        Ok(self.peers.get(&peer_id).map(|peer| peer.clone()))
+    }
+
+    #[instrument(skip(self))]
+    async fn set_peer_state(&self, peer_id: PeerId, state: PeerState) -> Result<(), Error> {
+        if let Some(mut peer) = self.peers.get_mut(&peer_id) {
+            peer.state = state;
+        }
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    async fn peers_created_before(&self, time: DateTime<Utc>) -> Result<Vec<Peer>, Error> {
+        Ok(collect_created_before(&self.peers, time, |peer| {
+            peer.create_at
+        }))
+    }
+
+    #[instrument(skip(self))]
+    async fn add_room(&self, room: Room) -> Result<(), Error> {
+        self.rooms.insert(room.id, room);
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    async fn remove_room(&self, room_id: RoomId) -> Result<Option<Room>, Error> {
+        Ok(self.rooms.remove(&room_id).map(|(_, room)| room))
+    }
+
+    #[instrument(skip(self))]
+    async fn get_room(&self, room_id: RoomId) -> Result<Option<Room>, Error> {
+        Ok(self.rooms.get(&room_id).map(|room| room.clone()))
+    }
+
+    #[instrument(skip(self))]
+    async fn rooms_created_before(&self, time: DateTime<Utc>) -> Result<Vec<Room>, Error> {
+        Ok(collect_created_before(&self.rooms, time, |room| {
+            room.create_at
+        }))
+    }
+
+    #[instrument(skip(self))]
+    async fn add_room_peer(&self, room_id: RoomId, peer_id: PeerId) -> Result<Option<Room>, Error> {
+        Ok(self.update_room(room_id, |room| {
+            room.peers.insert(peer_id);
+        }))
+    }
+
+    #[instrument(skip(self))]
+    async fn remove_room_peer(
+        &self,
+        room_id: RoomId,
+        peer_id: PeerId,
+    ) -> Result<Option<Room>, Error> {
+        Ok(self.update_room(room_id, |room| {
+            room.peers.remove(&peer_id);
+        }))
+    }
+
+    #[instrument(skip(self))]
+    async fn set_room_host(&self, room_id: RoomId, peer_id: PeerId) -> Result<Option<Room>, Error> {
+        Ok(self.update_room(room_id, |room| {
+            room.host_id = peer_id;
+        }))
+    }
+
+    #[instrument(skip(self))]
+    async fn set_room_locked(&self, room_id: RoomId, locked: bool) -> Result<Option<Room>, Error> {
+        Ok(self.update_room(room_id, |room| {
+            room.locked = locked;
+        }))
+    }
+
+    #[instrument(skip(self))]
+    async fn set_room_options(
+        &self,
+        room_id: RoomId,
+        options: RoomOptions,
+    ) -> Result<Option<Room>, Error> {
+        Ok(self.update_room(room_id, |room| {
+            room.options = options;
+        }))
+    }
+
+    #[instrument(skip(self))]
+    async fn add_room_entity(
+        &self,
+        room_id: RoomId,
+        entity_id: EntityId,
+    ) -> Result<Option<Room>, Error> {
+        Ok(self.update_room(room_id, |room| {
+            room.entities.insert(entity_id);
+        }))
+    }
+
+    #[instrument(skip(self))]
+    async fn remove_room_entity(
+        &self,
+        room_id: RoomId,
+        entity_id: EntityId,
+    ) -> Result<Option<Room>, Error> {
+        Ok(self.update_room(room_id, |room| {
+            room.entities.remove(&entity_id);
+        }))
+    }
+
+    #[instrument(skip(self))]
+    async fn add_entity(&self, entity: Entity) -> Result<(), Error> {
+        self.entities.insert(entity.id, entity);
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    async fn remove_entity(&self, entity_id: EntityId) -> Result<Option<Entity>, Error> {
+        Ok(self.entities.remove(&entity_id).map(|(_, entity)| entity))
+    }
+
+    #[instrument(skip(self))]
+    async fn get_entity(&self, entity_id: EntityId) -> Result<Option<Entity>, Error> {
+        Ok(self.entities.get(&entity_id).map(|entity| entity.clone()))
+    }
+
+    #[instrument(skip(self))]
+    async fn set_entity_transfer(
+        &self,
+        entity_id: EntityId,
+        peer_id: PeerId,
+        state: TransferState,
+    ) -> Result<Option<Entity>, Error> {
+        Ok(self.entities.get_mut(&entity_id).map(|mut entity| {
+            entity
+                .transfers
+                .retain(|transfer| transfer.peer_id != peer_id);
+            entity.transfers.push(EntityTransfer { peer_id, state });
+            entity.clone()
+        }))
+    }
+
+    #[instrument(skip(self))]
+    async fn entities_created_before(&self, time: DateTime<Utc>) -> Result<Vec<Entity>, Error> {
+        Ok(collect_created_before(&self.entities, time, |entity| {
+            entity.create_at
+        }))
+    }
+}
+
+fn collect_created_before<K, V, F>(map: &DashMap<K, V>, time: DateTime<Utc>, create_at: F) -> Vec<V>
+where
+    K: Eq + std::hash::Hash,
+    V: Clone,
+    F: Fn(&V) -> DateTime<Utc>,
+{
+    map.iter()
+        .filter(|item| create_at(item.value()) < time)
+        .map(|item| item.value().clone())
+        .collect()
+}
@@ -1,7 +1,94 @@
-pub mod invites;
+pub mod convert;
+pub mod memory;
 pub mod models;
-pub mod peers;
+pub mod mongo;
 
-pub use self::{invites::*, models::*, peers::*};
+use std::sync::Arc;
 
-const DB_NAME: &str = "drophub";
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use drophub::{EntityId, Error, PeerId, RoomId, RoomOptions, TransferState};
+
+pub use self::{convert::load_room, memory::MemoryStorage, models::*, mongo::MongodbStorage};
+use crate::config::{StorageConfig, TtlConfig};
+
+/// Persistence backend of invites, peers, rooms and entities.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// Prepares backend for records with specified lifetimes.
+    async fn init_ttl(&self, _ttl: &TtlConfig) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Adds invite unless its passphrase is taken by another one.
+    async fn add_invite(&self, invite: Invite) -> Result<(), Error>;
+    async fn remove_invite(&self, invite_passphrase: &str) -> Result<Option<Invite>, Error>;
+    async fn get_invite(&self, invite_passphrase: &str) -> Result<Option<Invite>, Error>;
+    async fn invites_created_before(&self, time: DateTime<Utc>) -> Result<Vec<Invite>, Error>;
+    /// Returns invites created for the room.
+    async fn room_invites(&self, room_id: RoomId) -> Result<Vec<Invite>, Error>;
+
+    async fn add_peer(&self, peer: Peer) -> Result<(), Error>;
+    async fn remove_peer(&self, peer_id: PeerId) -> Result<Option<Peer>, Error>;
+    async fn get_peer(&self, peer_id: PeerId) -> Result<Option<Peer>, Error>;
+    async fn set_peer_state(&self, peer_id: PeerId, state: PeerState) -> Result<(), Error>;
+    async fn peers_created_before(&self, time: DateTime<Utc>) -> Result<Vec<Peer>, Error>;
+
+    async fn add_room(&self, room: Room) -> Result<(), Error>;
+    async fn remove_room(&self, room_id: RoomId) -> Result<Option<Room>, Error>;
+    async fn get_room(&self, room_id: RoomId) -> Result<Option<Room>, Error>;
+    async fn rooms_created_before(&self, time: DateTime<Utc>) -> Result<Vec<Room>, Error>;
+    /// Adds peer to room and returns updated room.
+    async fn add_room_peer(&self, room_id: RoomId, peer_id: PeerId) -> Result<Option<Room>, Error>;
+    /// Removes peer from room and returns updated room.
+    async fn remove_room_peer(
+        &self,
+        room_id: RoomId,
+        peer_id: PeerId,
+    ) -> Result<Option<Room>, Error>;
+    /// Sets host of room and returns updated room.
+    async fn set_room_host(&self, room_id: RoomId, peer_id: PeerId) -> Result<Option<Room>, Error>;
+    /// Locks or unlocks room and returns updated room.
+    async fn set_room_locked(&self, room_id: RoomId, locked: bool) -> Result<Option<Room>, Error>;
+    /// Sets options of room and returns updated room.
+    async fn set_room_options(
+        &self,
+        room_id: RoomId,
+        options: RoomOptions,
+    ) -> Result<Option<Room>, Error>;
+    /// Adds entity to room and returns updated room.
+    async fn add_room_entity(
+        &self,
+        room_id: RoomId,
+        entity_id: EntityId,
+    ) -> Result<Option<Room>, Error>;
+    /// Removes entity from room and returns updated room.
+    async fn remove_room_entity(
+        &self,
+        room_id: RoomId,
+        entity_id: EntityId,
+    ) -> Result<Option<Room>, Error>;
+
+    async fn add_entity(&self, entity: Entity) -> Result<(), Error>;
+    async fn remove_entity(&self, entity_id: EntityId) -> Result<Option<Entity>, Error>;
+    async fn get_entity(&self, entity_id: EntityId) -> Result<Option<Entity>, Error>;
+    /// Sets state of entity transfer to the peer and returns updated entity.
+    async fn set_entity_transfer(
+        &self,
+        entity_id: EntityId,
+        peer_id: PeerId,
+        state: TransferState,
+    ) -> Result<Option<Entity>, Error>;
+    async fn entities_created_before(&self, time: DateTime<Utc>) -> Result<Vec<Entity>, Error>;
+}
+
+/// Creates storage backend selected in config.
+pub async fn new(cfg: &StorageConfig, ttl: &TtlConfig) -> anyhow::Result<Arc<dyn Storage>> {
+    let storage: Arc<dyn Storage> = match cfg {
+        StorageConfig::Memory => Arc::new(MemoryStorage::new()),
+        StorageConfig::Mongodb(cfg) => Arc::new(MongodbStorage::new(cfg).await?),
+    };
+    storage.init_ttl(ttl).await?;
+
+    Ok(storage)
+}
@@ -1,16 +1,17 @@
-use std::collections::HashSet;
+use std::{collections::HashSet, time::Duration};
 
-use chrono::{DateTime, Duration, Utc};
-use drophub::{EntityId, EntityKind, InvitePassphrase, PeerId, RoomId};
-
-pub const INVITE_TTL: Duration = Duration::hours(1);
-pub const ROOM_TTL: Duration = Duration::hours(24);
-pub const PEER_TTL: Duration = Duration::hours(24);
-pub const ENTITY_TTL: Duration = Duration::hours(24);
+use bson::serde_helpers::{chrono_datetime_as_bson_datetime, uuid_1_as_binary};
+use chrono::{DateTime, Utc};
+use drophub::{
+    manifest::Manifest, EntityId, EntityKind, EntityMeta, InvitePassphrase, PeerId, RoomId,
+    RoomOptions, TransferState,
+};
 
 #[derive(Debug, Clone, Eq, PartialEq, serde::Serialize, serde::Deserialize)]
 pub struct Peer {
+    #[serde(with = "uuid_1_as_binary")]
     pub id: PeerId,
+    #[serde(with = "chrono_datetime_as_bson_datetime")]
     pub create_at: DateTime<Utc>,
     pub state: PeerState,
 }
@@ -21,41 +22,143 @@ pub enum PeerState {
     Disconnected,
     Connecting {
         connecting_at: DateTime<Utc>,
+        #[serde(with = "uuid_1_as_binary")]
         room_id: RoomId,
     },
     Connected {
         connected_at: DateTime<Utc>,
+        #[serde(with = "uuid_1_as_binary")]
         room_id: RoomId,
     },
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, serde::Serialize, serde::Deserialize)]
 pub struct Room {
+    #[serde(with = "uuid_1_as_binary")]
     pub id: RoomId,
+    #[serde(with = "chrono_datetime_as_bson_datetime")]
     pub create_at: DateTime<Utc>,
+    #[serde(with = "uuid_1_as_binary")]
+    pub host_id: PeerId,
+    #[serde(default)]
+    pub locked: bool,
+    pub options: RoomOptions,
+    #[serde(with = "uuid_set_as_binary")]
     pub peers: HashSet<PeerId>,
+    #[serde(with = "uuid_set_as_binary")]
     pub entities: HashSet<EntityId>,
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, serde::Serialize, serde::Deserialize)]
 pub struct Entity {
+    #[serde(with = "uuid_1_as_binary")]
     pub id: EntityId,
+    #[serde(with = "chrono_datetime_as_bson_datetime")]
     pub create_at: DateTime<Utc>,
+    #[serde(with = "uuid_1_as_binary")]
+    pub room_id: RoomId,
     pub kind: EntityKind,
     pub name: String,
     pub size: usize,
+    #[serde(with = "uuid_1_as_binary")]
     pub owner_id: PeerId,
+    /// Content of text entity.
+    #[serde(default)]
+    pub content: Option<String>,
+    /// Files of directory entity.
+    #[serde(default)]
+    pub manifest: Option<Manifest>,
+    #[serde(default)]
+    pub meta: EntityMeta,
+    /// Transfers to the peers that requested the entity.
+    #[serde(default)]
+    pub transfers: Vec<EntityTransfer>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct EntityTransfer {
+    #[serde(with = "uuid_1_as_binary")]
+    pub peer_id: PeerId,
+    pub state: TransferState,
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, serde::Serialize, serde::Deserialize)]
 pub struct Invite {
     pub passphrase: InvitePassphrase,
+    #[serde(with = "chrono_datetime_as_bson_datetime")]
     pub create_at: DateTime<Utc>,
+    #[serde(with = "uuid_1_as_binary")]
     pub peer_id: PeerId,
+    /// Room the invite is created for. Invite of the peer if not set.
+    #[serde(default, with = "uuid_option_as_binary")]
+    pub room_id: Option<RoomId>,
+    /// Overrides the configured invite TTL.
+    #[serde(default, with = "humantime_serde")]
+    pub ttl: Option<Duration>,
 }
 
 impl Invite {
-    pub fn is_expired(&self) -> bool {
-        self.create_at + INVITE_TTL < Utc::now()
+    pub fn is_expired(&self, ttl: Duration) -> bool {
+        is_expired(self.create_at, self.ttl.unwrap_or(ttl))
+    }
+
+    /// Converts invite of the room into the public shape.
+    pub fn into_public(self, ttl: Duration) -> drophub::Invite {
+        let ttl = chrono::Duration::from_std(self.ttl.unwrap_or(ttl))
+            .unwrap_or(chrono::Duration::max_value());
+
+        drophub::Invite {
+            expire_at: self
+                .create_at
+                .checked_add_signed(ttl)
+                .unwrap_or(DateTime::<Utc>::MAX_UTC),
+            passphrase: self.passphrase,
+            room_id: self.room_id,
+        }
+    }
+}
+
+/// Checks whether record created at specified time outlived its TTL.
+pub fn is_expired(create_at: DateTime<Utc>, ttl: Duration) -> bool {
+    match chrono::Duration::from_std(ttl) {
+        Ok(ttl) => create_at + ttl < Utc::now(),
+        // TTL is too big to be ever reached
+        Err(_) => false,
+    }
+}
+
+/// Stores optional id as BSON binary of the UUID subtype, like ids are put into queries.
+mod uuid_option_as_binary {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use uuid::Uuid;
+
+    pub fn serialize<S: Serializer>(id: &Option<Uuid>, serializer: S) -> Result<S::Ok, S::Error> {
+        id.map(bson::Uuid::from_uuid_1).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Uuid>, D::Error> {
+        let id = Option::<bson::Uuid>::deserialize(deserializer)?;
+        Ok(id.map(bson::Uuid::to_uuid_1))
+    }
+}
+
+/// Stores ids as array of BSON binaries of the UUID subtype, like ids are put into queries.
+mod uuid_set_as_binary {
+    use std::collections::HashSet;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+    use uuid::Uuid;
+
+    pub fn serialize<S: Serializer>(ids: &HashSet<Uuid>, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(ids.iter().copied().map(bson::Uuid::from_uuid_1))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<HashSet<Uuid>, D::Error> {
+        let ids = Vec::<bson::Uuid>::deserialize(deserializer)?;
+        Ok(ids.into_iter().map(bson::Uuid::to_uuid_1).collect())
     }
 }
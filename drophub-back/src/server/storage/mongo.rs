@@ -0,0 +1,733 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use drophub::{EntityId, Error, PeerId, RoomId, RoomOptions, TransferState};
+use futures::TryStreamExt;
+use mongodb::{
+    bson::{doc, to_bson, Document},
+    error::{ErrorKind, WriteFailure},
+    options::{ClientOptions, FindOneAndUpdateOptions, IndexOptions, ReturnDocument},
+    Collection, Database, IndexModel,
+};
+use serde::de::DeserializeOwned;
+use tracing::instrument;
+
+use crate::{
+    config::{MongodbConfig, TtlConfig},
+    server::storage::{
+        models::{Entity, EntityTransfer, Invite, Peer, PeerState, Room},
+        Storage,
+    },
+};
+
+const DB_NAME: &str = "drophub";
+/// TTL indexes remove records later than the reaper does, so the reaper can notify rooms first.
+const TTL_INDEX_GRACE: Duration = Duration::from_secs(10 * 60);
+
+const DUPLICATE_KEY_CODE: i32 = 11000;
+const INDEX_OPTIONS_CONFLICT_CODE: i32 = 85;
+
+pub struct MongodbStorage {
+    client: mongodb::Client,
+}
+
+impl MongodbStorage {
+    pub async fn new(cfg: &MongodbConfig) -> anyhow::Result<Self> {
+        let mut client_options = ClientOptions::parse(&cfg.uri).await?;
+        client_options.app_name = Some(env!("CARGO_PKG_NAME").to_owned());
+
+        let client = mongodb::Client::with_options(client_options)?;
+        let storage = Self { client };
+
+        // Invites are looked up by passphrase, so it must not be handed out twice
+        let index = IndexModel::builder()
+            .keys(doc! { "passphrase": 1 })
+            .options(IndexOptions::builder().unique(true).build())
+            .build();
+        storage.invites().create_index(index, None).await?;
+
+        Ok(storage)
+    }
+
+    fn invites(&self) -> Collection<Invite> {
+        self.client.database(DB_NAME).collection("invites")
+    }
+
+    fn peers(&self) -> Collection<Peer> {
+        self.client.database(DB_NAME).collection("peers")
+    }
+
+    fn rooms(&self) -> Collection<Room> {
+        self.client.database(DB_NAME).collection("rooms")
+    }
+
+    fn entities(&self) -> Collection<Entity> {
+        self.client.database(DB_NAME).collection("entities")
+    }
+
+    async fn update_room(
+        &self,
+        room_id: RoomId,
+        update: Document,
+        details: &str,
+    ) -> Result<Option<Room>, Error> {
+        self.rooms()
+            .find_one_and_update(
+                doc! { "id": room_id },
+                update,
+                FindOneAndUpdateOptions::builder()
+                    .return_document(ReturnDocument::After)
+                    .build(),
+            )
+            .await
+            .map_err(|err| Error::MongodbError {
+                message: err.to_string(),
+                details: Some(serde_json::json! { details }),
+            })
+    }
+}
+
+/// Creates index removing records after the TTL. TTL of the existing index is updated,
+/// so the configured one can be changed.
+async fn create_ttl_index<T>(
+    db: &Database,
+    collection: Collection<T>,
+    ttl: Duration,
+) -> Result<(), Error> {
+    let expire_after = ttl + TTL_INDEX_GRACE;
+    let index = IndexModel::builder()
+        .keys(doc! { "create_at": 1 })
+        .options(
+            IndexOptions::builder()
+                .expire_after(Some(expire_after))
+                .build(),
+        )
+        .build();
+
+    let res = match collection.create_index(index, None).await {
+        Ok(_) => Ok(()),
+        // Index exists with TTL of the previous configuration
+        Err(err) if error_code(&err) == Some(INDEX_OPTIONS_CONFLICT_CODE) => {
+            let expire_after_secs = i64::try_from(expire_after.as_secs()).unwrap_or(i64::MAX);
+            let command = doc! {
+                "collMod": collection.name(),
+                "index": {
+                    "keyPattern": { "create_at": 1 },
+                    "expireAfterSeconds": expire_after_secs,
+                },
+            };
+            db.run_command(command, None).await.map(|_| ())
+        }
+        Err(err) => Err(err),
+    };
+
+    res.map_err(|err| Error::MongodbError {
+        message: err.to_string(),
+        details: Some(serde_json::json! { "Failed to create TTL index" }),
+    })
+}
+
+/// Returns code of the error reported by the server.
+fn error_code(err: &mongodb::error::Error) -> Option<i32> {
+    match &*err.kind {
+        ErrorKind::Command(err) => Some(err.code),
+        ErrorKind::Write(WriteFailure::WriteError(err)) => Some(err.code),
+        _ => None,
+    }
+}
+
+async fn find_created_before<T>(
+    collection: Collection<T>,
+    time: DateTime<Utc>,
+) -> Result<Vec<T>, Error>
+where
+    T: DeserializeOwned + Unpin + Send + Sync,
+{
+    let filter = doc! { "create_at": { "$lt": bson::DateTime::from_chrono(time) } };
+    let map_err = |err: mongodb::error::Error| Error::MongodbError {
+        message: err.to_string(),
+        details: Some(serde_json::json! { "Failed to find expired records" }),
+    };
+
+    collection
+        .find(filter, None)
+        .await
+        .map_err(map_err)?
+        .try_collect()
+        .await
+        .map_err(map_err)
+}
+
+#[async_trait]
+impl Storage for MongodbStorage {
+    #[instrument(skip(self))]
+    async fn init_ttl(&self, ttl: &TtlConfig) -> Result<(), Error> {
+        let db = self.client.database(DB_NAME);
+        create_ttl_index(&db, self.invites(), ttl.invite).await?;
+        create_ttl_index(&db, self.peers(), ttl.peer).await?;
+        create_ttl_index(&db, self.rooms(), ttl.room).await?;
+        create_ttl_index(&db, self.entities(), ttl.entity).await?;
+
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    async fn add_invite(&self, invite: Invite) -> Result<(), Error> {
+        let invite_passphrase = invite.passphrase.clone();
+        match self.invites().insert_one(invite, None).await {
+            Ok(_) => Ok(()),
+            Err(err) if error_code(&err) == Some(DUPLICATE_KEY_CODE) => {
+                Err(Error::InviteAlreadyExists { invite_passphrase })
+            }
+            Err(err) => Err(Error::MongodbError {
+                message: err.to_string(),
+                details: Some(serde_json::json! { "Failed to add invite" }),
+            }),
+        }
+    }
+
+    #[instrument(skip(self))]
+    async fn remove_invite(&self, invite_passphrase: &str) -> Result<Option<Invite>, Error> {
+        self.invites()
+            .find_one_and_delete(doc! { "passphrase": invite_passphrase }, None)
+            .await
+            .map_err(|err| Error::MongodbError {
+                message: err.to_string(),
+                details: Some(serde_json::json! { "Failed to remove invite" }),
+            })
+    }
+
+    #[instrument(skip(self))]
+    async fn get_invite(&self, invite_passphrase: &str) -> Result<Option<Invite>, Error> {
+        self.invites()
+            .find_one(doc! { "passphrase": invite_passphrase }, None)
+            .await
+            .map_err(|err| Error::MongodbError {
+                message: err.to_string(),
+                details: Some(serde_json::json! { "Failed to get invite" }),
+            })
+    }
+
+    #[instrument(skip(self))]
+    async fn invites_created_before(&self, time: DateTime<Utc>) -> Result<Vec<Invite>, Error> {
+        find_created_before(self.invites(), time).await
+    }
+
+    #[instrument(skip(self))]
+    async fn room_invites(&self, room_id: RoomId) -> Result<Vec<Invite>, Error> {
+        let map_err = |err: mongodb::error::Error| Error::MongodbError {
+            message: err.to_string(),
+            details: Some(serde_json::json! { "Failed to find room invites" }),
+        };
+
+        self.invites()
+            .find(doc! { "room_id": room_id }, None)
+            .await
+            .map_err(map_err)?
+            .try_collect()
+            .await
+            .map_err(map_err)
+    }
+
+    #[instrument(skip(self))]
+    async fn add_peer(&self, peer: Peer) -> Result<(), Error> {
+        self.peers()
+            .insert_one(peer, None)
+            .await
+            .map_err(|err| Error::MongodbError {
+                message: err.to_string(),
+                details: Some(serde_json::json! { "Failed to add peer" }),
+            })?;
+
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    async fn remove_peer(&self, peer_id: PeerId) -> Result<Option<Peer>, Error> {
+        self.peers()
+            .find_one_and_delete(doc! { "id": peer_id }, None)
+            .await
+            .map_err(|err| Error::MongodbError {
+                message: err.to_string(),
+                details: Some(serde_json::json! { "Failed to remove peer" }),
+            })
+    }
+
+    #[instrument(skip(self))]
+    async fn get_peer(&self, peer_id: PeerId) -> Result<Option<Peer>, Error> {
+        self.peers()
+            .find_one(doc! { "id": peer_id }, None)
+            .await
+            .map_err(|err| Error::MongodbError {
+                message: err.to_string(),
+                details: Some(serde_json::json! { "Failed to get peer" }),
+            })
+    }
+
+    #[instrument(skip(self))]
+    async fn set_peer_state(&self, peer_id: PeerId, state: PeerState) -> Result<(), Error> {
+        let state = to_bson(&state).map_err(|err| Error::MongodbError {
+            message: err.to_string(),
+            details: Some(serde_json::json! { "Failed to serialize peer state" }),
+        })?;
+
+        self.peers()
+            .update_one(
+                doc! { "id": peer_id },
+                doc! { "$set": { "state": state } },
+                None,
+            )
+            .await
+            .map_err(|err| Error::MongodbError {
+                message: err.to_string(),
+                details: Some(serde_json::json! { "Failed to set peer state" }),
+            })?;
+
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    async fn peers_created_before(&self, time: DateTime<Utc>) -> Result<Vec<Peer>, Error> {
+        find_created_before(self.peers(), time).await
+    }
+
+    #[instrument(skip(self))]
+    async fn add_room(&self, room: Room) -> Result<(), Error> {
+        self.rooms()
+            .insert_one(room, None)
+            .await
+            .map_err(|err| Error::MongodbError {
+                message: err.to_string(),
+                details: Some(serde_json::json! { "Failed to add room" }),
+            })?;
+
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    async fn remove_room(&self, room_id: RoomId) -> Result<Option<Room>, Error> {
+        self.rooms()
+            .find_one_and_delete(doc! { "id": room_id }, None)
+            .await
+            .map_err(|err| Error::MongodbError {
+                message: err.to_string(),
+                details: Some(serde_json::json! { "Failed to remove room" }),
+            })
+    }
+
+    #[instrument(skip(self))]
+    async fn get_room(&self, room_id: RoomId) -> Result<Option<Room>, Error> {
+        self.rooms()
+            .find_one(doc! { "id": room_id }, None)
+            .await
+            .map_err(|err| Error::MongodbError {
+                message: err.to_string(),
+                details: Some(serde_json::json! { "Failed to get room" }),
+            })
+    }
+
+    #[instrument(skip(self))]
+    async fn rooms_created_before(&self, time: DateTime<Utc>) -> Result<Vec<Room>, Error> {
+        find_created_before(self.rooms(), time).await
+    }
+
+    #[instrument(skip(self))]
+    async fn add_room_peer(&self, room_id: RoomId, peer_id: PeerId) -> Result<Option<Room>, Error> {
+        self.update_room(
+            room_id,
+            doc! { "$addToSet": { "peers": peer_id } },
+            "Failed to add peer to room",
+        )
+        .await
+    }
+
+    #[instrument(skip(self))]
+    async fn remove_room_peer(
+        &self,
+        room_id: RoomId,
+        peer_id: PeerId,
+    ) -> Result<Option<Room>, Error> {
+        self.update_room(
+            room_id,
+            doc! { "$pull": { "peers": peer_id } },
+            "Failed to remove peer from room",
+        )
+        .await
+    }
+
+    #[instrument(skip(self))]
+    async fn set_room_host(&self, room_id: RoomId, peer_id: PeerId) -> Result<Option<Room>, Error> {
+        self.update_room(
+            room_id,
+            doc! { "$set": { "host_id": peer_id } },
+            "Failed to set room host",
+        )
+        .await
+    }
+
+    #[instrument(skip(self))]
+    async fn set_room_locked(&self, room_id: RoomId, locked: bool) -> Result<Option<Room>, Error> {
+        self.update_room(
+            room_id,
+            doc! { "$set": { "locked": locked } },
+            "Failed to set room lock",
+        )
+        .await
+    }
+
+    #[instrument(skip(self))]
+    async fn set_room_options(
+        &self,
+        room_id: RoomId,
+        options: RoomOptions,
+    ) -> Result<Option<Room>, Error> {
+        let options = to_bson(&options).map_err(|err| Error::MongodbError {
+            message: err.to_string(),
+            details: Some(serde_json::json! { "Failed to serialize room options" }),
+        })?;
+
+        self.update_room(
+            room_id,
+            doc! { "$set": { "options": options } },
+            "Failed to set room options",
+        )
+        .await
+    }
+
+    #[instrument(skip(self))]
+    async fn add_room_entity(
+        &self,
+        room_id: RoomId,
+        entity_id: EntityId,
+    ) -> Result<Option<Room>, Error> {
+        self.update_room(
+            room_id,
+            doc! { "$addToSet": { "entities": entity_id } },
+            "Failed to add entity to room",
+        )
+        .await
+    }
+
+    #[instrument(skip(self))]
+    async fn remove_room_entity(
+        &self,
+        room_id: RoomId,
+        entity_id: EntityId,
+    ) -> Result<Option<Room>, Error> {
+        self.update_room(
+            room_id,
+            doc! { "$pull": { "entities": entity_id } },
+            "Failed to remove entity from room",
+        )
+        .await
+    }
+
+    #[instrument(skip(self))]
+    async fn add_entity(&self, entity: Entity) -> Result<(), Error> {
+        self.entities()
+            .insert_one(entity, None)
+            .await
+            .map_err(|err| Error::MongodbError {
+                message: err.to_string(),
+                details: Some(serde_json::json! { "Failed to add entity" }),
+            })?;
+
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    async fn remove_entity(&self, entity_id: EntityId) -> Result<Option<Entity>, Error> {
+        self.entities()
+            .find_one_and_delete(doc! { "id": entity_id }, None)
+            .await
+            .map_err(|err| Error::MongodbError {
+                message: err.to_string(),
+                details: Some(serde_json::json! { "Failed to remove entity" }),
+            })
+    }
+
+    #[instrument(skip(self))]
+    async fn get_entity(&self, entity_id: EntityId) -> Result<Option<Entity>, Error> {
+        self.entities()
+            .find_one(doc! { "id": entity_id }, None)
+            .await
+            .map_err(|err| Error::MongodbError {
+                message: err.to_string(),
+                details: Some(serde_json::json! { "Failed to get entity" }),
+            })
+    }
+
+    #[instrument(skip(self))]
+    async fn set_entity_transfer(
+        &self,
+        entity_id: EntityId,
+        peer_id: PeerId,
+        state: TransferState,
+    ) -> Result<Option<Entity>, Error> {
+        let transfer =
+            to_bson(&EntityTransfer { peer_id, state }).map_err(|err| Error::MongodbError {
+                message: err.to_string(),
+                details: Some(serde_json::json! { "Failed to serialize entity transfer" }),
+            })?;
+
+        // Pipeline update replaces transfer of the peer atomically
+        let update = vec![doc! {
+            "$set": {
+                "transfers": {
+                    "$concatArrays": [
+                        {
+                            "$filter": {
+                                "input": { "$ifNull": ["$transfers", []] },
+                                "cond": { "$ne": ["$$this.peer_id", peer_id] },
+                            }
+                        },
+                        [transfer],
+                    ]
+                }
+            }
+        }];
+        self.entities()
+            .find_one_and_update(
+                doc! { "id": entity_id },
+                update,
+                FindOneAndUpdateOptions::builder()
+                    .return_document(ReturnDocument::After)
+                    .build(),
+            )
+            .await
+            .map_err(|err| Error::MongodbError {
+                message: err.to_string(),
+                details: Some(serde_json::json! { "Failed to set entity transfer" }),
+            })
+    }
+
+    #[instrument(skip(self))]
+    async fn entities_created_before(&self, time: DateTime<Utc>) -> Result<Vec<Entity>, Error> {
+        find_created_before(self.entities(), time).await
+    }
+}
+
+/// Tests run against MongoDB at `DROPHUB_TEST_MONGODB_URI` and are skipped without it.
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use assert_matches::assert_matches;
+    use uuid::Uuid;
+
+    use super::*;
+
+    async fn storage() -> Option<MongodbStorage> {
+        let Ok(uri) = std::env::var("DROPHUB_TEST_MONGODB_URI") else {
+            eprintln!("DROPHUB_TEST_MONGODB_URI is not set, skipping");
+            return None;
+        };
+
+        Some(MongodbStorage::new(&MongodbConfig { uri }).await.unwrap())
+    }
+
+    #[test]
+    fn ids_are_stored_as_queried() {
+        let (host_id, entity_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let room = Room {
+            id: Uuid::new_v4(),
+            create_at: bson::DateTime::now().to_chrono(),
+            host_id,
+            locked: false,
+            options: RoomOptions {
+                capacity: 4,
+                max_invites: 2,
+                invite_ttl: None,
+            },
+            peers: HashSet::from([host_id]),
+            entities: HashSet::from([entity_id]),
+        };
+
+        // Records are inserted as raw documents, updates use documents
+        let raw = bson::to_raw_document_buf(&room).unwrap();
+        let document = bson::to_document(&room).unwrap();
+        for document in [raw.to_document().unwrap(), document] {
+            let query = doc! {
+                "id": room.id,
+                "host_id": host_id,
+                "peers": [host_id],
+                "entities": [entity_id],
+            };
+            for (key, value) in &query {
+                assert_eq!(document.get(key), Some(value), "{key}");
+            }
+        }
+        assert_eq!(bson::from_slice::<Room>(raw.as_bytes()).unwrap(), room);
+    }
+
+    #[tokio::test]
+    async fn peers() {
+        let Some(storage) = storage().await else {
+            return;
+        };
+        let peer = Peer {
+            id: Uuid::new_v4(),
+            create_at: bson::DateTime::now().to_chrono(),
+            state: PeerState::Disconnected,
+        };
+
+        storage.add_peer(peer.clone()).await.unwrap();
+        assert_eq!(storage.get_peer(peer.id).await.unwrap(), Some(peer.clone()));
+
+        let room_id = Uuid::new_v4();
+        storage
+            .set_peer_state(
+                peer.id,
+                PeerState::Connecting {
+                    connecting_at: Utc::now(),
+                    room_id,
+                },
+            )
+            .await
+            .unwrap();
+        assert_matches!(
+            storage.get_peer(peer.id).await.unwrap(),
+            Some(Peer { state: PeerState::Connecting { room_id: id, .. }, .. }) if id == room_id
+        );
+
+        assert_matches!(storage.remove_peer(peer.id).await.unwrap(), Some(_));
+        assert_eq!(storage.get_peer(peer.id).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn rooms() {
+        let Some(storage) = storage().await else {
+            return;
+        };
+        let (host_id, guest_id, entity_id) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        let room = Room {
+            id: Uuid::new_v4(),
+            // BSON keeps milliseconds only
+            create_at: bson::DateTime::now().to_chrono(),
+            host_id,
+            locked: false,
+            options: RoomOptions {
+                capacity: 4,
+                max_invites: 2,
+                invite_ttl: None,
+            },
+            peers: HashSet::from([host_id]),
+            entities: HashSet::new(),
+        };
+
+        storage.add_room(room.clone()).await.unwrap();
+        assert_eq!(storage.get_room(room.id).await.unwrap(), Some(room.clone()));
+
+        let updated = storage.add_room_peer(room.id, guest_id).await.unwrap();
+        assert_matches!(updated, Some(r) if r.peers == HashSet::from([host_id, guest_id]));
+        let updated = storage.set_room_host(room.id, guest_id).await.unwrap();
+        assert_matches!(updated, Some(r) if r.host_id == guest_id);
+        let updated = storage.remove_room_peer(room.id, host_id).await.unwrap();
+        assert_matches!(updated, Some(r) if r.peers == HashSet::from([guest_id]));
+        let updated = storage.set_room_locked(room.id, true).await.unwrap();
+        assert_matches!(updated, Some(r) if r.locked);
+
+        let updated = storage.add_room_entity(room.id, entity_id).await.unwrap();
+        assert_matches!(updated, Some(r) if r.entities == HashSet::from([entity_id]));
+        let updated = storage
+            .remove_room_entity(room.id, entity_id)
+            .await
+            .unwrap();
+        assert_matches!(updated, Some(r) if r.entities.is_empty());
+
+        assert_matches!(storage.remove_room(room.id).await.unwrap(), Some(_));
+        assert_eq!(storage.get_room(room.id).await.unwrap(), None);
+        assert_eq!(storage.add_room_peer(room.id, host_id).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn entity_transfers() {
+        let Some(storage) = storage().await else {
+            return;
+        };
+        let (peer_id, other_peer_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let entity = Entity {
+            id: Uuid::new_v4(),
+            create_at: bson::DateTime::now().to_chrono(),
+            room_id: Uuid::new_v4(),
+            kind: drophub::EntityKind::File,
+            name: "file.txt".to_owned(),
+            size: 10,
+            owner_id: Uuid::new_v4(),
+            content: None,
+            manifest: None,
+            meta: Default::default(),
+            transfers: Vec::new(),
+        };
+
+        storage.add_entity(entity.clone()).await.unwrap();
+        assert_eq!(
+            storage.get_entity(entity.id).await.unwrap(),
+            Some(entity.clone())
+        );
+
+        for state in [TransferState::Pending, TransferState::Transferring] {
+            storage
+                .set_entity_transfer(entity.id, peer_id, state)
+                .await
+                .unwrap();
+        }
+        let updated = storage
+            .set_entity_transfer(entity.id, other_peer_id, TransferState::Pending)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            updated.transfers,
+            [
+                EntityTransfer {
+                    peer_id,
+                    state: TransferState::Transferring,
+                },
+                EntityTransfer {
+                    peer_id: other_peer_id,
+                    state: TransferState::Pending,
+                },
+            ]
+        );
+
+        assert_matches!(storage.remove_entity(entity.id).await.unwrap(), Some(_));
+        assert_eq!(storage.get_entity(entity.id).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn invites() {
+        let Some(storage) = storage().await else {
+            return;
+        };
+        let room_id = Uuid::new_v4();
+        let invite = Invite {
+            passphrase: Uuid::new_v4().to_string(),
+            create_at: bson::DateTime::now().to_chrono(),
+            peer_id: Uuid::new_v4(),
+            room_id: Some(room_id),
+            ttl: None,
+        };
+
+        storage.add_invite(invite.clone()).await.unwrap();
+        assert_matches!(
+            storage.add_invite(invite.clone()).await,
+            Err(Error::InviteAlreadyExists { .. })
+        );
+        assert_eq!(
+            storage.get_invite(&invite.passphrase).await.unwrap(),
+            Some(invite.clone())
+        );
+        assert_eq!(
+            storage.room_invites(room_id).await.unwrap(),
+            std::slice::from_ref(&invite)
+        );
+
+        assert_eq!(
+            storage.remove_invite(&invite.passphrase).await.unwrap(),
+            Some(invite.clone())
+        );
+        assert_eq!(storage.get_invite(&invite.passphrase).await.unwrap(), None);
+    }
+}
@@ -1,262 +1,1755 @@
+use std::{net::SocketAddr, time::Duration};
+
 use assert_matches::assert_matches;
-use drophub::{Entity, FileMeta, RoomEvent, RoomOptions, RoomRpcClient};
-use jsonrpsee::ws_client::WsClientBuilder;
+use chrono::Utc;
+use drophub::{
+    digest::Digest,
+    manifest::{Manifest, ManifestEntry},
+    passphrase, AnnouncedEntity, Capability, ClientHello, ClientRole, DisconnectReason, EntityId,
+    EntityKind, EntityMeta, Error, InvitePassphrase, PeerEvent, PeerToken, PeerTokenEncoded,
+    RelayData, Room, RoomOptions, RpcClient, SignalPayload, Thumbnail, TransferProgress,
+    TransferState, Transport, PROTOCOL_VERSION,
+};
+use jsonrpsee::{
+    core::client::Subscription,
+    ws_client::{HeaderMap, HeaderValue, WsClient, WsClientBuilder},
+};
+use uuid::Uuid;
 
-use crate::{server, test_utils};
+use crate::{
+    config::{InviteAttemptsConfig, PassphraseConfig, TokenKeyAlgorithm, TokenKeyConfig},
+    server, test_utils,
+};
 
 #[tokio::test]
-async fn create() {
+async fn init() {
     let cfg = test_utils::test_config();
-    let (addr, _h) = server::run(&cfg).await.unwrap();
+    let (addr, _h) = server::run(cfg).await.unwrap();
     let client = WsClientBuilder::default()
         .build(format!("ws://{addr}"))
         .await
         .unwrap();
 
-    let mut sub = client.create(RoomOptions::default()).await.unwrap();
-    assert_matches!(sub.next().await, Some(Ok(RoomEvent::Init { .. })));
+    let mut sub = client
+        .sub_peer_events(Some(ClientHello::default()))
+        .await
+        .unwrap();
     assert_matches!(
         sub.next().await,
-        Some(Ok(RoomEvent::RoomInfo(info))) if info.clients.len() == 1
+        Some(Ok(PeerEvent::Init { token, .. }))
+            if PeerToken::decode(&token).unwrap().room_id.is_none()
     );
 }
 
 #[tokio::test]
-async fn connect() {
-    let cfg = test_utils::test_config();
-    let (addr, _h) = server::run(&cfg).await.unwrap();
+async fn protocol_version() {
+    let mut cfg = test_utils::test_config();
+    cfg.relay.enabled = false;
+    let (addr, _h) = server::run(cfg).await.unwrap();
     let client = WsClientBuilder::default()
         .build(format!("ws://{addr}"))
         .await
         .unwrap();
 
-    let mut host_sub = client.create(RoomOptions::default()).await.unwrap();
+    for hello in [
+        None,
+        Some(ClientHello {
+            version: PROTOCOL_VERSION + 1,
+            capabilities: Vec::new(),
+        }),
+    ] {
+        assert_matches!(
+            client.sub_peer_events(hello).await,
+            Err(err) if matches!(
+                Error::from_client_error(&err),
+                Some(Error::UnsupportedProtocolVersion { .. })
+            )
+        );
+    }
+
+    let hello = ClientHello::new(vec![Capability::Relay, Capability::Encryption]);
+    let mut sub = client.sub_peer_events(Some(hello)).await.unwrap();
+    assert_matches!(
+        sub.next().await,
+        Some(Ok(PeerEvent::Init { version, capabilities, .. }))
+            if version == PROTOCOL_VERSION && capabilities == [Capability::Encryption]
+    );
+}
 
-    let RoomEvent::Init{token: host_token, ..} = host_sub.next().await.unwrap().unwrap() else { panic!("unexpected event") };
-    let RoomEvent::RoomInfo(room_info) = host_sub.next().await.unwrap().unwrap() else { panic!("unexpected event") };
+#[tokio::test]
+async fn invite() {
+    let cfg = test_utils::test_config();
+    let (addr, _h) = server::run(cfg).await.unwrap();
+    let client = WsClientBuilder::default()
+        .build(format!("ws://{addr}"))
+        .await
+        .unwrap();
 
-    // Connect without invite
-    assert_matches!(client.connect(room_info.id, "123".into()).await, Err(_));
+    let mut host_sub = client
+        .sub_peer_events(Some(ClientHello::default()))
+        .await
+        .unwrap();
+    let Some(Ok(PeerEvent::Init {
+        invite_passphrase: host_invite,
+        ..
+    })) = host_sub.next().await
+    else {
+        panic!("unexpected event")
+    };
 
-    let invite = client.invite(host_token).await.unwrap();
-    assert_matches!(host_sub.next().await, Some(Ok(RoomEvent::RoomInfo(info))) if info.invites.len() == 1);
-    let mut guest1_sub = client
-        .connect(invite.room_id, invite.password.clone())
+    let mut guest_sub = client
+        .sub_peer_events(Some(ClientHello::default()))
         .await
         .unwrap();
-    assert_matches!(guest1_sub.next().await, Some(Ok(RoomEvent::Init { .. })));
-    assert_matches!(guest1_sub.next().await, Some(Ok(RoomEvent::RoomInfo(info))) if info.clients.len() == 2);
-    assert_matches!(host_sub.next().await, Some(Ok(RoomEvent::RoomInfo(info))) if info.clients.len() == 2);
+    let Some(Ok(PeerEvent::Init {
+        token: guest_token, ..
+    })) = guest_sub.next().await
+    else {
+        panic!("unexpected event")
+    };
+
+    client.invite(guest_token, host_invite).await.unwrap();
 
-    // Connect by used invite
-    assert_matches!(client.connect(room_info.id, invite.password).await, Err(_));
+    let Some(Ok(PeerEvent::Invite { token: host_token })) = host_sub.next().await else {
+        panic!("unexpected event")
+    };
+    let Some(Ok(PeerEvent::Invite { token: guest_token })) = guest_sub.next().await else {
+        panic!("unexpected event")
+    };
+
+    let host_token = PeerToken::decode(&host_token).unwrap();
+    let guest_token = PeerToken::decode(&guest_token).unwrap();
+    assert!(host_token.room_id.is_some());
+    assert_eq!(host_token.room_id, guest_token.room_id);
 }
 
 #[tokio::test]
-async fn invite() {
+async fn invite_errors() {
     let cfg = test_utils::test_config();
-    let (addr, _h) = server::run(&cfg).await.unwrap();
+    let (addr, _h) = server::run(cfg).await.unwrap();
     let client = WsClientBuilder::default()
         .build(format!("ws://{addr}"))
         .await
         .unwrap();
 
     let mut host_sub = client
-        .create(RoomOptions {
-            encryption: false,
-            capacity: 2,
-        })
+        .sub_peer_events(Some(ClientHello::default()))
         .await
         .unwrap();
-
-    let RoomEvent::Init{token: host_token, ..} = host_sub.next().await.unwrap().unwrap() else { panic!("unexpected event") };
-    assert_matches!(host_sub.next().await, Some(Ok(RoomEvent::RoomInfo(_))));
-
-    // Success connect via invite
-    let invite = client.invite(host_token.clone()).await.unwrap();
-    assert_matches!(host_sub.next().await, Some(Ok(RoomEvent::RoomInfo(info))) if info.invites.len() == 1);
+    let Some(Ok(PeerEvent::Init {
+        token: host_token,
+        invite_passphrase: host_invite,
+        ..
+    })) = host_sub.next().await
+    else {
+        panic!("unexpected event")
+    };
 
     let mut guest_sub = client
-        .connect(invite.room_id, invite.password)
+        .sub_peer_events(Some(ClientHello::default()))
         .await
         .unwrap();
-    let RoomEvent::Init{token: guest_token, ..} = guest_sub.next().await.unwrap().unwrap() else { panic!("unexpected event") };
+    let Some(Ok(PeerEvent::Init {
+        token: guest_token, ..
+    })) = guest_sub.next().await
+    else {
+        panic!("unexpected event")
+    };
+
+    // Unknown invite
+    assert_matches!(
+        client.invite(guest_token.clone(), "123".into()).await,
+        Err(_)
+    );
 
-    assert_matches!(host_sub.next().await, Some(Ok(RoomEvent::RoomInfo(info))) if info.invites.len() == 0 && info.clients.len() == 2);
-    assert_matches!(guest_sub.next().await, Some(Ok(RoomEvent::RoomInfo(info))) if info.invites.len() == 0 && info.clients.len() == 2);
+    // Own invite
+    assert_matches!(client.invite(host_token, host_invite.clone()).await, Err(_));
 
-    // Try create invite by guest
-    assert_matches!(client.invite(guest_token).await, Err(_));
+    client
+        .invite(guest_token.clone(), host_invite.clone())
+        .await
+        .unwrap();
+    let Some(Ok(PeerEvent::Invite {
+        token: guest_room_token,
+    })) = guest_sub.next().await
+    else {
+        panic!("unexpected event")
+    };
 
-    // Maximum invites reached
-    assert_matches!(client.invite(host_token).await, Err(_));
+    // Already connected
+    assert_matches!(
+        client.invite(guest_token, host_invite.clone()).await,
+        Err(_)
+    );
+    assert_matches!(client.invite(guest_room_token, host_invite).await, Err(_));
 }
 
 #[tokio::test]
-async fn invite_revoke() {
+async fn room_updates() {
     let cfg = test_utils::test_config();
-    let (addr, _h) = server::run(&cfg).await.unwrap();
+    let (addr, _h) = server::run(cfg).await.unwrap();
     let client = WsClientBuilder::default()
         .build(format!("ws://{addr}"))
         .await
         .unwrap();
 
-    let mut host_sub = client
-        .create(RoomOptions {
-            encryption: false,
-            capacity: 3,
-        })
+    let (mut host_sub, _host_token, mut guest_sub, _guest_token) = connect_pair(&client).await;
+
+    wait_room(&mut host_sub, |room| room.peers.len() == 2).await;
+    wait_room(&mut guest_sub, |room| room.peers.len() == 2).await;
+
+    // Guest leaves the room
+    guest_sub.unsubscribe().await.unwrap();
+    wait_room(&mut host_sub, |room| room.peers.len() == 1).await;
+}
+
+#[tokio::test]
+async fn announce_entity() {
+    let cfg = test_utils::test_config();
+    let (addr, _h) = server::run(cfg).await.unwrap();
+    let client = WsClientBuilder::default()
+        .build(format!("ws://{addr}"))
         .await
         .unwrap();
 
-    let RoomEvent::Init{token: host_token, ..} = host_sub.next().await.unwrap().unwrap() else { panic!("unexpected event") };
-    assert_matches!(host_sub.next().await, Some(Ok(RoomEvent::RoomInfo(_))));
+    let (mut host_sub, host_token, mut guest_sub, _guest_token) = connect_pair(&client).await;
+
+    let entity_id = client
+        .announce_entity(
+            host_token.clone(),
+            AnnouncedEntity {
+                kind: EntityKind::File,
+                name: "123".to_owned(),
+                size: 123,
+                content: None,
+                manifest: None,
+                meta: EntityMeta::default(),
+            },
+        )
+        .await
+        .unwrap();
 
-    let invite1 = client.invite(host_token.clone()).await.unwrap();
-    assert_matches!(host_sub.next().await, Some(Ok(RoomEvent::RoomInfo(info))) if info.invites.len() == 1);
+    let host_id = PeerToken::decode(&host_token).unwrap().peer_id;
+    let room = wait_room(&mut host_sub, |room| room.entities.contains_key(&entity_id)).await;
+    assert_eq!(room.entities[&entity_id].owner_id, host_id);
+    wait_room(&mut guest_sub, |room| {
+        room.entities.contains_key(&entity_id)
+    })
+    .await;
+}
 
-    let invite2 = client.invite(host_token.clone()).await.unwrap();
-    assert_matches!(host_sub.next().await, Some(Ok(RoomEvent::RoomInfo(info))) if info.invites.len() == 2);
+#[tokio::test]
+async fn announce_entity_limits() {
+    let cfg = test_utils::test_config();
+    let (addr, _h) = server::run(cfg).await.unwrap();
+    let client = WsClientBuilder::default()
+        .build(format!("ws://{addr}"))
+        .await
+        .unwrap();
 
-    assert_matches!(client.invite(host_token.clone()).await, Err(_));
+    let (_host_sub, host_token, _guest_sub, guest_token) = connect_pair(&client).await;
+    let entity = |size| AnnouncedEntity {
+        kind: EntityKind::File,
+        name: "123".to_owned(),
+        size,
+        content: None,
+        manifest: None,
+        meta: EntityMeta::default(),
+    };
 
+    // Peer size limit
     assert_matches!(
         client
-            .revoke_invite(host_token.clone(), invite1.password)
+            .announce_entity(host_token.clone(), entity(501))
             .await,
-        Ok(_)
+        Err(_)
     );
-    assert_matches!(host_sub.next().await, Some(Ok(RoomEvent::RoomInfo(info))) if info.invites.len() == 1);
 
+    // Peer count limit
+    client
+        .announce_entity(host_token.clone(), entity(1))
+        .await
+        .unwrap();
+    client
+        .announce_entity(host_token.clone(), entity(1))
+        .await
+        .unwrap();
     assert_matches!(
-        client
-            .revoke_invite(host_token.clone(), invite2.password)
-            .await,
-        Ok(_)
+        client.announce_entity(host_token.clone(), entity(1)).await,
+        Err(_)
     );
-    assert_matches!(host_sub.next().await, Some(Ok(RoomEvent::RoomInfo(info))) if info.invites.len() == 0);
 
+    // Room count limit
+    client
+        .announce_entity(guest_token.clone(), entity(1))
+        .await
+        .unwrap();
     assert_matches!(
-        client.revoke_invite(host_token.clone(), "123".into()).await,
+        client.announce_entity(guest_token.clone(), entity(1)).await,
         Err(_)
     );
 }
 
 #[tokio::test]
-async fn kick() {
+async fn remove_entity() {
     let cfg = test_utils::test_config();
-    let (addr, _h) = server::run(&cfg).await.unwrap();
+    let (addr, _h) = server::run(cfg).await.unwrap();
     let client = WsClientBuilder::default()
         .build(format!("ws://{addr}"))
         .await
         .unwrap();
 
-    let mut host_sub = client.create(RoomOptions::default()).await.unwrap();
+    let (mut host_sub, host_token, mut guest_sub, guest_token) = connect_pair(&client).await;
+
+    // Entity doesn't exist
+    assert_matches!(
+        client
+            .remove_entity(host_token.clone(), Uuid::new_v4())
+            .await,
+        Err(_)
+    );
+
+    let entity_id = client
+        .announce_entity(
+            host_token.clone(),
+            AnnouncedEntity {
+                kind: EntityKind::File,
+                name: "123".to_owned(),
+                size: 123,
+                content: None,
+                manifest: None,
+                meta: EntityMeta::default(),
+            },
+        )
+        .await
+        .unwrap();
+    wait_room(&mut host_sub, |room| room.entities.contains_key(&entity_id)).await;
+    wait_room(&mut guest_sub, |room| {
+        room.entities.contains_key(&entity_id)
+    })
+    .await;
+
+    // The owner of the entity is another peer
+    assert_matches!(client.remove_entity(guest_token, entity_id).await, Err(_));
+    assert_matches!(client.remove_entity(host_token, entity_id).await, Ok(_));
+
+    wait_room(&mut host_sub, |room| {
+        !room.entities.contains_key(&entity_id)
+    })
+    .await;
+    wait_room(&mut guest_sub, |room| {
+        !room.entities.contains_key(&entity_id)
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn text_entity() {
+    let mut cfg = test_utils::test_config();
+    cfg.limits.text_size = 16;
+    let (addr, _h) = server::run(cfg).await.unwrap();
+    let client = WsClientBuilder::default()
+        .build(format!("ws://{addr}"))
+        .await
+        .unwrap();
 
-    let RoomEvent::Init{token: host_token, ..} = host_sub.next().await.unwrap().unwrap() else { panic!("unexpected event") };
-    assert_matches!(host_sub.next().await, Some(Ok(RoomEvent::RoomInfo(_))));
+    let (_host_sub, host_token, mut guest_sub, _guest_token) = connect_pair(&client).await;
+    let text = |content: &str| AnnouncedEntity {
+        kind: EntityKind::Text,
+        name: "link".to_owned(),
+        size: content.len(),
+        content: Some(content.to_owned()),
+        manifest: None,
+        meta: EntityMeta::default(),
+    };
 
-    let invite = client.invite(host_token.clone()).await.unwrap();
-    assert_matches!(host_sub.next().await, Some(Ok(RoomEvent::RoomInfo(info))) if info.invites.len() == 1);
-    let mut guest1_sub = client
-        .connect(invite.room_id, invite.password.clone())
+    let entity_id = client
+        .announce_entity(host_token.clone(), text("https://a.b/c"))
         .await
         .unwrap();
-    assert_matches!(guest1_sub.next().await, Some(Ok(RoomEvent::Init { .. })));
-    assert_matches!(guest1_sub.next().await, Some(Ok(RoomEvent::RoomInfo(info))) if info.clients.len() == 2);
-    let RoomEvent::RoomInfo(room_info) = host_sub.next().await.unwrap().unwrap() else { panic!("unexpected event") };
+    let room = wait_room(&mut guest_sub, |room| {
+        room.entities.contains_key(&entity_id)
+    })
+    .await;
+    assert_eq!(
+        room.entities[&entity_id].content.as_deref(),
+        Some("https://a.b/c")
+    );
 
+    // Too long
+    assert_matches!(
+        client
+            .announce_entity(host_token.clone(), text("https://a.b/cdefghijkl"))
+            .await,
+        Err(_)
+    );
+    // Without content
+    assert_matches!(
+        client
+            .announce_entity(
+                host_token.clone(),
+                AnnouncedEntity {
+                    content: None,
+                    ..text("123")
+                }
+            )
+            .await,
+        Err(_)
+    );
+    // Size mismatch
     assert_matches!(
         client
-            .kick(
+            .announce_entity(
                 host_token.clone(),
-                room_info
-                    .clients
-                    .into_iter()
-                    .filter(|c| c != &room_info.host)
-                    .next()
-                    .unwrap(),
+                AnnouncedEntity {
+                    size: 4,
+                    ..text("123")
+                }
+            )
+            .await,
+        Err(_)
+    );
+    // File with content
+    assert_matches!(
+        client
+            .announce_entity(
+                host_token,
+                AnnouncedEntity {
+                    kind: EntityKind::File,
+                    ..text("123")
+                }
             )
             .await,
-        Ok(_)
+        Err(_)
+    );
+}
+
+#[tokio::test]
+async fn entity_meta() {
+    let mut cfg = test_utils::test_config();
+    cfg.limits.thumbnail_size = 4;
+    let (addr, _h) = server::run(cfg).await.unwrap();
+    let client = WsClientBuilder::default()
+        .build(format!("ws://{addr}"))
+        .await
+        .unwrap();
+
+    let (_host_sub, host_token, mut guest_sub, _guest_token) = connect_pair(&client).await;
+    let meta = EntityMeta {
+        mime_type: Some("text/plain".to_owned()),
+        digest: Some(Digest::sha256(b"123")),
+        modified_at: Some(Utc::now()),
+        thumbnail: Some(Thumbnail {
+            mime_type: "image/png".to_owned(),
+            data: RelayData(vec![1, 2, 3]),
+        }),
+    };
+    let text = |meta: EntityMeta| AnnouncedEntity {
+        kind: EntityKind::Text,
+        name: "123".to_owned(),
+        size: 3,
+        content: Some("123".to_owned()),
+        manifest: None,
+        meta,
+    };
+
+    let entity_id = client
+        .announce_entity(host_token.clone(), text(meta.clone()))
+        .await
+        .unwrap();
+    let room = wait_room(&mut guest_sub, |room| {
+        room.entities.contains_key(&entity_id)
+    })
+    .await;
+    assert_eq!(room.entities[&entity_id].meta, meta);
+
+    let invalid = [
+        EntityMeta {
+            mime_type: Some("text".to_owned()),
+            ..meta.clone()
+        },
+        EntityMeta {
+            digest: Some(Digest::sha256(b"1234")),
+            ..meta.clone()
+        },
+        EntityMeta {
+            digest: Some(Digest::Sha256("123".to_owned())),
+            ..meta.clone()
+        },
+        EntityMeta {
+            modified_at: Some(Utc::now() + chrono::Duration::days(1)),
+            ..meta.clone()
+        },
+        EntityMeta {
+            thumbnail: Some(Thumbnail {
+                mime_type: "text/plain".to_owned(),
+                data: RelayData(vec![1]),
+            }),
+            ..meta.clone()
+        },
+    ];
+    for meta in invalid {
+        assert_matches!(
+            client.announce_entity(host_token.clone(), text(meta)).await,
+            Err(err) if matches!(
+                Error::from_client_error(&err),
+                Some(Error::InvalidEntity { .. })
+            )
+        );
+    }
+
+    let meta = EntityMeta {
+        thumbnail: Some(Thumbnail {
+            mime_type: "image/png".to_owned(),
+            data: RelayData(vec![1, 2, 3, 4, 5]),
+        }),
+        ..meta
+    };
+    assert_matches!(
+        client.announce_entity(host_token, text(meta)).await,
+        Err(err) if matches!(
+            Error::from_client_error(&err),
+            Some(Error::ThumbnailSizeLimitExceeded { limit: 4 })
+        )
     );
+}
+
+#[tokio::test]
+async fn directory_entity() {
+    let mut cfg = test_utils::test_config();
+    cfg.limits.manifest_entries = 3;
+    let (addr, _h) = server::run(cfg).await.unwrap();
+    let client = WsClientBuilder::default()
+        .build(format!("ws://{addr}"))
+        .await
+        .unwrap();
+
+    let (_host_sub, host_token, mut guest_sub, _guest_token) = connect_pair(&client).await;
+    let directory = |paths: &[&str]| {
+        let manifest = Manifest {
+            entries: paths
+                .iter()
+                .map(|path| ManifestEntry {
+                    path: (*path).to_owned(),
+                    size: 2,
+                    digest: None,
+                })
+                .collect(),
+        };
+        AnnouncedEntity {
+            kind: EntityKind::Directory,
+            name: "dir".to_owned(),
+            size: manifest.size(),
+            content: None,
+            manifest: Some(manifest),
+            meta: EntityMeta::default(),
+        }
+    };
+
+    let entity = directory(&["a.txt", "b/c.txt", "b/d/e.txt"]);
+    let entity_id = client
+        .announce_entity(host_token.clone(), entity.clone())
+        .await
+        .unwrap();
+    let room = wait_room(&mut guest_sub, |room| {
+        room.entities.contains_key(&entity_id)
+    })
+    .await;
+    assert_eq!(room.entities[&entity_id].size, 6);
+    assert_eq!(room.entities[&entity_id].manifest, entity.manifest);
+
+    let invalid = [
+        // Path traversal
+        directory(&["../a.txt"]),
+        directory(&["/etc/passwd"]),
+        directory(&["b\\..\\..\\a.txt"]),
+        // Duplicate
+        directory(&["a.txt", "a.txt"]),
+        // Empty
+        directory(&[]),
+        // Size mismatch
+        AnnouncedEntity {
+            size: 5,
+            ..directory(&["a.txt"])
+        },
+        // Without manifest
+        AnnouncedEntity {
+            manifest: None,
+            ..directory(&["a.txt"])
+        },
+        // File with manifest
+        AnnouncedEntity {
+            kind: EntityKind::File,
+            ..directory(&["a.txt"])
+        },
+    ];
+    for entity in invalid {
+        assert_matches!(
+            client.announce_entity(host_token.clone(), entity).await,
+            Err(err) if matches!(
+                Error::from_client_error(&err),
+                Some(Error::InvalidEntity { .. })
+            )
+        );
+    }
 
-    assert_matches!(client.kick(host_token, room_info.host).await, Err(_));
+    assert_matches!(
+        client
+            .announce_entity(host_token, directory(&["a", "b", "c", "d"]))
+            .await,
+        Err(err) if matches!(
+            Error::from_client_error(&err),
+            Some(Error::ManifestEntriesLimitExceeded { limit: 3 })
+        )
+    );
 }
 
 #[tokio::test]
-async fn announce_entity() {
+async fn request_entity() {
     let cfg = test_utils::test_config();
-    let (addr, _h) = server::run(&cfg).await.unwrap();
+    let (addr, _h) = server::run(cfg).await.unwrap();
     let client = WsClientBuilder::default()
         .build(format!("ws://{addr}"))
         .await
         .unwrap();
 
-    let mut host_sub = client.create(RoomOptions::default()).await.unwrap();
-    let RoomEvent::Init{token: host_token, ..} = host_sub.next().await.unwrap().unwrap() else { panic!("unexpected event") };
-    assert_matches!(host_sub.next().await, Some(Ok(RoomEvent::RoomInfo(_))));
-
-    let file_id = client
+    let (mut host_sub, host_token, mut guest_sub, guest_token) = connect_pair(&client).await;
+    let guest_id = PeerToken::decode(&guest_token).unwrap().peer_id;
+    let entity_id = client
         .announce_entity(
-            host_token,
-            Entity::File(FileMeta {
+            host_token.clone(),
+            AnnouncedEntity {
+                kind: EntityKind::File,
                 name: "123".to_owned(),
                 size: 123,
-            }),
+                content: None,
+                manifest: None,
+                meta: EntityMeta::default(),
+            },
+        )
+        .await
+        .unwrap();
+
+    // Own entity
+    assert_matches!(
+        client.request_entity(host_token.clone(), entity_id).await,
+        Err(err) if matches!(Error::from_client_error(&err), Some(Error::SamePeer { .. }))
+    );
+    // Nothing to answer yet
+    assert_matches!(
+        client
+            .answer_entity_request(host_token.clone(), entity_id, guest_id, true)
+            .await,
+        Err(err) if matches!(
+            Error::from_client_error(&err),
+            Some(Error::TransferNotFound { .. })
+        )
+    );
+
+    client
+        .request_entity(guest_token.clone(), entity_id)
+        .await
+        .unwrap();
+    let wait = async {
+        loop {
+            match host_sub.next().await {
+                Some(Ok(PeerEvent::EntityRequested { by, entity_id })) => return (by, entity_id),
+                Some(Ok(_)) => continue,
+                other => panic!("unexpected event: {other:?}"),
+            }
+        }
+    };
+    let requested = tokio::time::timeout(Duration::from_secs(5), wait)
+        .await
+        .expect("request timed out");
+    assert_eq!(requested, (guest_id, entity_id));
+    wait_room(&mut guest_sub, |room| {
+        room.entities
+            .get(&entity_id)
+            .and_then(|entity| entity.transfers.get(&guest_id))
+            == Some(&TransferState::Pending)
+    })
+    .await;
+
+    // Only the owner answers
+    assert_matches!(
+        client
+            .answer_entity_request(guest_token.clone(), entity_id, guest_id, true)
+            .await,
+        Err(err) if matches!(
+            Error::from_client_error(&err),
+            Some(Error::PermissionDenied { .. })
+        )
+    );
+    // Not accepted yet
+    assert_matches!(
+        client
+            .report_transfer(guest_token.clone(), entity_id, guest_id, TransferState::Done)
+            .await,
+        Err(err) if matches!(
+            Error::from_client_error(&err),
+            Some(Error::InvalidTransferState {
+                state: TransferState::Pending,
+                ..
+            })
+        )
+    );
+
+    client
+        .answer_entity_request(host_token.clone(), entity_id, guest_id, true)
+        .await
+        .unwrap();
+    wait_room(&mut guest_sub, |room| {
+        room.entities
+            .get(&entity_id)
+            .and_then(|entity| entity.transfers.get(&guest_id))
+            == Some(&TransferState::Transferring)
+    })
+    .await;
+
+    // Only done or failed can be reported
+    assert_matches!(
+        client
+            .report_transfer(
+                guest_token.clone(),
+                entity_id,
+                guest_id,
+                TransferState::Pending
+            )
+            .await,
+        Err(_)
+    );
+    // Requested again while transferring
+    assert_matches!(
+        client.request_entity(guest_token.clone(), entity_id).await,
+        Err(_)
+    );
+    client
+        .report_transfer(
+            guest_token.clone(),
+            entity_id,
+            guest_id,
+            TransferState::Done,
         )
         .await
         .unwrap();
+    wait_room(&mut guest_sub, |room| {
+        room.entities
+            .get(&entity_id)
+            .and_then(|entity| entity.transfers.get(&guest_id))
+            == Some(&TransferState::Done)
+    })
+    .await;
 
-    assert_matches!(host_sub.next().await, Some(Ok(RoomEvent::RoomInfo(info))) if info.entities.contains_key(&file_id));
+    // Finished transfer can be requested again
+    client
+        .request_entity(guest_token.clone(), entity_id)
+        .await
+        .unwrap();
+    client
+        .answer_entity_request(host_token, entity_id, guest_id, false)
+        .await
+        .unwrap();
+    wait_room(&mut guest_sub, |room| {
+        room.entities
+            .get(&entity_id)
+            .and_then(|entity| entity.transfers.get(&guest_id))
+            == Some(&TransferState::Declined)
+    })
+    .await;
 }
 
 #[tokio::test]
-async fn remove_entity() {
-    let cfg = test_utils::test_config();
-    let (addr, _h) = server::run(&cfg).await.unwrap();
+async fn transfer_progress() {
+    let mut cfg = test_utils::test_config();
+    cfg.progress.interval = Duration::from_secs(60);
+    let (addr, _h) = server::run(cfg).await.unwrap();
     let client = WsClientBuilder::default()
         .build(format!("ws://{addr}"))
         .await
         .unwrap();
 
-    let mut host_sub = client.create(RoomOptions::default()).await.unwrap();
-    let RoomEvent::Init{token: host_token, ..} = host_sub.next().await.unwrap().unwrap() else { panic!("unexpected event") };
-    assert_matches!(host_sub.next().await, Some(Ok(RoomEvent::RoomInfo(_))));
+    // Only the host receives progress
+    let (mut host_sub, host_token, mut guest_sub, guest_token) =
+        connect_pair_with(&client, ClientHello::new(vec![Capability::Progress])).await;
+    let guest_id = PeerToken::decode(&guest_token).unwrap().peer_id;
+    let entity_id = client
+        .announce_entity(
+            host_token.clone(),
+            AnnouncedEntity {
+                kind: EntityKind::File,
+                name: "123".to_owned(),
+                size: 123,
+                content: None,
+                manifest: None,
+                meta: EntityMeta::default(),
+            },
+        )
+        .await
+        .unwrap();
+
+    // Not requested yet
+    assert_matches!(
+        client
+            .report_progress(guest_token.clone(), entity_id, guest_id, 10)
+            .await,
+        Err(err) if matches!(
+            Error::from_client_error(&err),
+            Some(Error::TransferNotFound { .. })
+        )
+    );
+
+    client
+        .request_entity(guest_token.clone(), entity_id)
+        .await
+        .unwrap();
+    client
+        .answer_entity_request(host_token.clone(), entity_id, guest_id, true)
+        .await
+        .unwrap();
+
+    assert_matches!(
+        client
+            .report_progress(guest_token.clone(), entity_id, guest_id, 124)
+            .await,
+        Err(err) if matches!(
+            Error::from_client_error(&err),
+            Some(Error::InvalidTransferProgress { transferred: 124, size: 123 })
+        )
+    );
+    client
+        .report_progress(guest_token.clone(), entity_id, guest_id, 10)
+        .await
+        .unwrap();
+    // Dropped, reported too soon
+    client
+        .report_progress(host_token.clone(), entity_id, guest_id, 20)
+        .await
+        .unwrap();
+    client
+        .report_progress(guest_token.clone(), entity_id, guest_id, 123)
+        .await
+        .unwrap();
+
+    let progress = wait_progress(&mut host_sub, entity_id).await;
+    assert_eq!((progress.transferred, progress.size), (10, 123));
+    let progress = wait_progress(&mut host_sub, entity_id).await;
+    assert_eq!((progress.transferred, progress.eta), (123, Some(0)));
 
-    let invite = client.invite(host_token.clone()).await.unwrap();
-    assert_matches!(host_sub.next().await, Some(Ok(RoomEvent::RoomInfo(info))) if info.invites.len() == 1);
+    client
+        .report_transfer(guest_token, entity_id, guest_id, TransferState::Done)
+        .await
+        .unwrap();
+    let wait = async {
+        loop {
+            match guest_sub.next().await {
+                Some(Ok(PeerEvent::UpdateRoom { room }))
+                    if room
+                        .entities
+                        .get(&entity_id)
+                        .and_then(|entity| entity.transfers.get(&guest_id))
+                        == Some(&TransferState::Done) =>
+                {
+                    return
+                }
+                Some(Ok(PeerEvent::TransferProgress { .. })) => {
+                    panic!("progress sent without capability")
+                }
+                Some(Ok(_)) => continue,
+                other => panic!("unexpected event: {other:?}"),
+            }
+        }
+    };
+    tokio::time::timeout(Duration::from_secs(5), wait)
+        .await
+        .expect("room update timed out");
+}
 
-    let mut guest1_sub = client
-        .connect(invite.room_id, invite.password.clone())
+#[tokio::test]
+async fn get_room_state() {
+    let cfg = test_utils::test_config();
+    let (addr, _h) = server::run(cfg).await.unwrap();
+    let client = WsClientBuilder::default()
+        .build(format!("ws://{addr}"))
         .await
         .unwrap();
-    let RoomEvent::Init{token: guest1_token, ..} = guest1_sub.next().await.unwrap().unwrap() else { panic!("unexpected event") };
-    assert_matches!(guest1_sub.next().await, Some(Ok(RoomEvent::RoomInfo(info))) if info.clients.len() == 2);
-    assert_matches!(host_sub.next().await, Some(Ok(RoomEvent::RoomInfo(info))) if info.clients.len() == 2);
 
-    // File doesn't exists
-    assert_matches!(client.remove_entity(host_token.clone(), 123).await, Err(_));
+    let (mut host_sub, host_token, _guest_sub, guest_token) = connect_pair(&client).await;
+    wait_room(&mut host_sub, |room| room.peers.len() == 2).await;
 
-    // Announce new file
-    let file_id = client
+    let entity_id = client
         .announce_entity(
             host_token.clone(),
-            Entity::File(FileMeta {
+            AnnouncedEntity {
+                kind: EntityKind::Text,
                 name: "123".to_owned(),
-                size: 123,
-            }),
+                size: 3,
+                content: Some("123".to_owned()),
+                manifest: None,
+                meta: EntityMeta::default(),
+            },
         )
         .await
         .unwrap();
 
-    assert_matches!(host_sub.next().await, Some(Ok(RoomEvent::RoomInfo(info))) if info.entities.contains_key(&file_id));
-    assert_matches!(guest1_sub.next().await, Some(Ok(RoomEvent::RoomInfo(info))) if info.entities.contains_key(&file_id));
+    let host_id = PeerToken::decode(&host_token).unwrap().peer_id;
+    let room = client.get_room_state(guest_token).await.unwrap();
+    assert_eq!(room.peers.len(), 2);
+    assert_eq!(room.entities[&entity_id].owner_id, host_id);
+    assert!(room.peers[&host_id].entities.contains(&entity_id));
 
-    // The owner of the file is another client
-    assert_matches!(client.remove_entity(guest1_token, file_id).await, Err(_));
-    assert_matches!(client.remove_entity(host_token, file_id).await, Ok(_));
+    // Token without room
+    let mut other_sub = client
+        .sub_peer_events(Some(ClientHello::default()))
+        .await
+        .unwrap();
+    let Some(Ok(PeerEvent::Init {
+        token: other_token, ..
+    })) = other_sub.next().await
+    else {
+        panic!("unexpected event")
+    };
+    assert_matches!(client.get_room_state(other_token).await, Err(_));
+
+    // Token signed by another secret
+    let forged_token = PeerToken {
+        room_id: Some(room.id),
+        ..PeerToken::decode(&host_token).unwrap()
+    }
+    .encode("forged")
+    .unwrap();
+    assert_matches!(client.get_room_state(forged_token).await, Err(_));
+}
+
+#[tokio::test]
+async fn send_signal() {
+    let cfg = test_utils::test_config();
+    let (addr, _h) = server::run(cfg).await.unwrap();
+    let client = WsClientBuilder::default()
+        .build(format!("ws://{addr}"))
+        .await
+        .unwrap();
+
+    let (_host_sub, host_token, mut guest_sub, guest_token) = connect_pair(&client).await;
+    let host_id = PeerToken::decode(&host_token).unwrap().peer_id;
+    let guest_id = PeerToken::decode(&guest_token).unwrap().peer_id;
+    let payload = SignalPayload::Offer {
+        sdp: "v=0".to_owned(),
+    };
+
+    client
+        .send_signal(host_token.clone(), guest_id, payload.clone())
+        .await
+        .unwrap();
+    let wait = async {
+        loop {
+            match guest_sub.next().await {
+                Some(Ok(PeerEvent::Signal {
+                    from_peer_id,
+                    payload,
+                })) => return (from_peer_id, payload),
+                Some(Ok(_)) => continue,
+                other => panic!("unexpected event: {other:?}"),
+            }
+        }
+    };
+    let signal = tokio::time::timeout(Duration::from_secs(5), wait)
+        .await
+        .expect("signal timed out");
+    assert_eq!(signal, (host_id, payload.clone()));
 
-    assert_matches!(host_sub.next().await, Some(Ok(RoomEvent::RoomInfo(info))) if !info.entities.contains_key(&file_id));
-    assert_matches!(guest1_sub.next().await, Some(Ok(RoomEvent::RoomInfo(info))) if !info.entities.contains_key(&file_id));
+    // Signal to itself
+    assert_matches!(
+        client
+            .send_signal(host_token.clone(), host_id, payload.clone())
+            .await,
+        Err(_)
+    );
+
+    // Peer from another room
+    let mut other_sub = client
+        .sub_peer_events(Some(ClientHello::default()))
+        .await
+        .unwrap();
+    let Some(Ok(PeerEvent::Init {
+        token: other_token, ..
+    })) = other_sub.next().await
+    else {
+        panic!("unexpected event")
+    };
+    let other_id = PeerToken::decode(&other_token).unwrap().peer_id;
+    assert_matches!(
+        client
+            .send_signal(host_token, other_id, payload.clone())
+            .await,
+        Err(_)
+    );
+    assert_matches!(
+        client.send_signal(other_token, host_id, payload).await,
+        Err(_)
+    );
+}
+
+#[tokio::test]
+async fn relay() {
+    let cfg = test_utils::test_config();
+    let (addr, _h) = server::run(cfg).await.unwrap();
+    let client = WsClientBuilder::default()
+        .build(format!("ws://{addr}"))
+        .await
+        .unwrap();
+
+    let (mut host_sub, host_token, mut guest_sub, guest_token) = connect_pair(&client).await;
+    let host_id = PeerToken::decode(&host_token).unwrap().peer_id;
+    let guest_id = PeerToken::decode(&guest_token).unwrap().peer_id;
+
+    client
+        .request_relay(host_token.clone(), guest_id)
+        .await
+        .unwrap();
+    for (sub, peer_id) in [(&mut host_sub, guest_id), (&mut guest_sub, host_id)] {
+        let wait = async {
+            loop {
+                match sub.next().await {
+                    Some(Ok(PeerEvent::Transport { peer_id, transport })) => {
+                        return (peer_id, transport)
+                    }
+                    Some(Ok(_)) => continue,
+                    other => panic!("unexpected event: {other:?}"),
+                }
+            }
+        };
+        let transport = tokio::time::timeout(Duration::from_secs(5), wait)
+            .await
+            .expect("transport timed out");
+        assert_eq!(transport, (peer_id, Transport::Relay));
+    }
+
+    let mut relay_sub = client.sub_relay_frames(guest_token).await.unwrap();
+    client
+        .relay_frame(host_token.clone(), guest_id, RelayData(vec![1; 16]))
+        .await
+        .unwrap();
+    let frame = relay_sub.next().await.unwrap().unwrap();
+    assert_eq!(frame.from_peer_id, host_id);
+    assert_eq!(frame.data, RelayData(vec![1; 16]));
+
+    // Frame size limit
+    assert_matches!(
+        client
+            .relay_frame(host_token.clone(), guest_id, RelayData(vec![1; 17]))
+            .await,
+        Err(_)
+    );
+
+    // Room size limit
+    client
+        .relay_frame(host_token.clone(), guest_id, RelayData(vec![1; 16]))
+        .await
+        .unwrap();
+    assert_matches!(
+        client
+            .relay_frame(host_token, guest_id, RelayData(vec![1]))
+            .await,
+        Err(_)
+    );
+}
+
+#[tokio::test]
+async fn room_expiration() {
+    let mut cfg = test_utils::test_config();
+    cfg.ttl.room = Duration::from_millis(500);
+    cfg.ttl.sweep_interval = Duration::from_millis(100);
+    let (addr, _h) = server::run(cfg).await.unwrap();
+    let client = WsClientBuilder::default()
+        .build(format!("ws://{addr}"))
+        .await
+        .unwrap();
+
+    let (mut host_sub, host_token, mut guest_sub, _guest_token) = connect_pair(&client).await;
+    let room_id = PeerToken::decode(&host_token).unwrap().room_id;
+
+    for sub in [&mut host_sub, &mut guest_sub] {
+        let wait = async {
+            loop {
+                match sub.next().await {
+                    Some(Ok(PeerEvent::Disconnect {
+                        room_id: disconnected_room_id,
+                        reason,
+                    })) => return (disconnected_room_id, reason),
+                    Some(Ok(_)) => continue,
+                    other => panic!("unexpected event: {other:?}"),
+                }
+            }
+        };
+        let (disconnected_room_id, reason) = tokio::time::timeout(Duration::from_secs(5), wait)
+            .await
+            .expect("disconnect timed out");
+        assert_eq!(disconnected_room_id, room_id);
+        assert_eq!(reason, DisconnectReason::RoomExpired);
+    }
+
+    assert_matches!(client.get_room_state(host_token).await, Err(_));
+}
+
+#[tokio::test]
+async fn refresh_token() {
+    let cfg = test_utils::test_config();
+    let (addr, _h) = server::run(cfg).await.unwrap();
+    let client = WsClientBuilder::default()
+        .build(format!("ws://{addr}"))
+        .await
+        .unwrap();
+
+    let (_host_sub, host_token, _guest_sub, _guest_token) = connect_pair(&client).await;
+    let refreshed = client.refresh_token(host_token.clone()).await.unwrap();
+    let old = PeerToken::decode(&host_token).unwrap();
+    let new = PeerToken::decode(&refreshed).unwrap();
+    assert_eq!(new.peer_id, old.peer_id);
+    assert_eq!(new.room_id, old.room_id);
+    assert_ne!(new.jti, old.jti);
+
+    // Old token is revoked
+    assert_matches!(client.get_room_state(host_token.clone()).await, Err(_));
+    assert_matches!(client.refresh_token(host_token).await, Err(_));
+    client.get_room_state(refreshed).await.unwrap();
+
+    // Token without room
+    let mut other_sub = client
+        .sub_peer_events(Some(ClientHello::default()))
+        .await
+        .unwrap();
+    let Some(Ok(PeerEvent::Init {
+        token: other_token, ..
+    })) = other_sub.next().await
+    else {
+        panic!("unexpected event")
+    };
+    let refreshed = client.refresh_token(other_token).await.unwrap();
+    assert_eq!(PeerToken::decode(&refreshed).unwrap().room_id, None);
+}
+
+#[tokio::test]
+async fn token_expiration() {
+    let mut cfg = test_utils::test_config();
+    cfg.ttl.token = Duration::from_secs(1);
+    let (addr, _h) = server::run(cfg).await.unwrap();
+    let client = WsClientBuilder::default()
+        .build(format!("ws://{addr}"))
+        .await
+        .unwrap();
+
+    let (_host_sub, host_token, _guest_sub, _guest_token) = connect_pair(&client).await;
+    client.get_room_state(host_token.clone()).await.unwrap();
+
+    tokio::time::sleep(Duration::from_millis(2100)).await;
+    assert_matches!(client.get_room_state(host_token.clone()).await, Err(_));
+    assert_matches!(client.refresh_token(host_token).await, Err(_));
+}
+
+#[tokio::test]
+async fn refreshed_token_outlives_original() {
+    let mut cfg = test_utils::test_config();
+    cfg.ttl.token = Duration::from_secs(3);
+    let (addr, _h) = server::run(cfg).await.unwrap();
+    let client = WsClientBuilder::default()
+        .build(format!("ws://{addr}"))
+        .await
+        .unwrap();
+
+    let (_host_sub, host_token, _guest_sub, _guest_token) = connect_pair(&client).await;
+    let original_exp = PeerToken::decode(&host_token).unwrap().exp;
+
+    // Claims are in seconds, so the refreshed token expires at least a second later
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    let refreshed = client.refresh_token(host_token).await.unwrap();
+    assert!(PeerToken::decode(&refreshed).unwrap().exp > original_exp);
+
+    tokio::time::sleep(Duration::from_millis(1600)).await;
+    assert!(Utc::now() > original_exp);
+    client.get_room_state(refreshed).await.unwrap();
+}
+
+#[tokio::test]
+async fn token_keys() {
+    let mut cfg = test_utils::test_config();
+    cfg.server.token_keys = vec![TokenKeyConfig {
+        kid: "test".to_owned(),
+        algorithm: TokenKeyAlgorithm::Es256,
+        public_key: "tests/keys/es256.pub".into(),
+        private_key: Some("tests/keys/es256.key".into()),
+    }];
+    let (addr, _h) = server::run(cfg).await.unwrap();
+    let client = WsClientBuilder::default()
+        .build(format!("ws://{addr}"))
+        .await
+        .unwrap();
+
+    let (_host_sub, host_token, _guest_sub, _guest_token) = connect_pair(&client).await;
+    assert_eq!(
+        PeerToken::key_id(&host_token).unwrap().as_deref(),
+        Some("test")
+    );
+    client.get_room_state(host_token.clone()).await.unwrap();
+
+    // Token signed by the secret
+    let forged_token = PeerToken::decode(&host_token)
+        .unwrap()
+        .encode(&test_utils::test_config().server.secret.unwrap())
+        .unwrap();
+    assert_matches!(client.get_room_state(forged_token).await, Err(_));
+}
+
+#[tokio::test]
+async fn kick_peer() {
+    let cfg = test_utils::test_config();
+    let (addr, _h) = server::run(cfg).await.unwrap();
+    let client = WsClientBuilder::default()
+        .build(format!("ws://{addr}"))
+        .await
+        .unwrap();
+
+    let (mut host_sub, host_token, mut guest_sub, guest_token) = connect_pair(&client).await;
+    let host_id = PeerToken::decode(&host_token).unwrap().peer_id;
+    let guest_id = PeerToken::decode(&guest_token).unwrap().peer_id;
+    let room = client.get_room_state(guest_token.clone()).await.unwrap();
+    assert_eq!(room.host, host_id);
+    assert_eq!(room.role(guest_id), ClientRole::Guest);
+
+    // Only the host can kick
+    assert_matches!(client.kick_peer(guest_token.clone(), host_id).await, Err(_));
+    assert_matches!(client.kick_peer(host_token.clone(), host_id).await, Err(_));
+
+    client
+        .kick_peer(host_token.clone(), guest_id)
+        .await
+        .unwrap();
+    let reason = wait_disconnect(&mut guest_sub).await;
+    assert_eq!(reason, DisconnectReason::Kicked);
+    wait_room(&mut host_sub, |room| !room.peers.contains_key(&guest_id)).await;
+
+    // Token of the kicked peer stops working immediately
+    assert_matches!(client.get_room_state(guest_token.clone()).await, Err(_));
+    assert_matches!(client.refresh_token(guest_token).await, Err(_));
+    client.get_room_state(host_token).await.unwrap();
+}
+
+#[tokio::test]
+async fn lock_room() {
+    let cfg = test_utils::test_config();
+    let (addr, _h) = server::run(cfg).await.unwrap();
+    let client = WsClientBuilder::default()
+        .build(format!("ws://{addr}"))
+        .await
+        .unwrap();
+
+    let mut host_sub = client
+        .sub_peer_events(Some(ClientHello::default()))
+        .await
+        .unwrap();
+    let Some(Ok(PeerEvent::Init {
+        invite_passphrase: host_invite,
+        ..
+    })) = host_sub.next().await
+    else {
+        panic!("unexpected event")
+    };
+
+    let mut guest_sub = client
+        .sub_peer_events(Some(ClientHello::default()))
+        .await
+        .unwrap();
+    let Some(Ok(PeerEvent::Init {
+        token: guest_token, ..
+    })) = guest_sub.next().await
+    else {
+        panic!("unexpected event")
+    };
+    let mut other_sub = client
+        .sub_peer_events(Some(ClientHello::default()))
+        .await
+        .unwrap();
+    let Some(Ok(PeerEvent::Init {
+        token: other_token, ..
+    })) = other_sub.next().await
+    else {
+        panic!("unexpected event")
+    };
+
+    client
+        .invite(guest_token, host_invite.clone())
+        .await
+        .unwrap();
+    let Some(Ok(PeerEvent::Invite { token: host_token })) = host_sub.next().await else {
+        panic!("unexpected event")
+    };
+    let Some(Ok(PeerEvent::Invite { token: guest_token })) = guest_sub.next().await else {
+        panic!("unexpected event")
+    };
+
+    assert_matches!(client.lock_room(guest_token, true).await, Err(_));
+    client.lock_room(host_token.clone(), true).await.unwrap();
+    wait_room(&mut host_sub, |room| room.locked).await;
+    assert_matches!(
+        client
+            .invite(other_token.clone(), host_invite.clone())
+            .await,
+        Err(_)
+    );
+
+    client.lock_room(host_token, false).await.unwrap();
+    client.invite(other_token, host_invite).await.unwrap();
+    wait_room(&mut host_sub, |room| room.peers.len() == 3).await;
+}
+
+#[tokio::test]
+async fn close_room() {
+    let cfg = test_utils::test_config();
+    let (addr, _h) = server::run(cfg).await.unwrap();
+    let client = WsClientBuilder::default()
+        .build(format!("ws://{addr}"))
+        .await
+        .unwrap();
+
+    let (mut host_sub, host_token, mut guest_sub, guest_token) = connect_pair(&client).await;
+    assert_matches!(client.close_room(guest_token.clone()).await, Err(_));
+    client.close_room(host_token.clone()).await.unwrap();
+
+    for sub in [&mut host_sub, &mut guest_sub] {
+        assert_eq!(wait_disconnect(sub).await, DisconnectReason::RoomClosed);
+    }
+    assert_matches!(client.get_room_state(host_token).await, Err(_));
+    assert_matches!(client.get_room_state(guest_token).await, Err(_));
+}
+
+#[tokio::test]
+async fn host_handover() {
+    let cfg = test_utils::test_config();
+    let (addr, _h) = server::run(cfg).await.unwrap();
+    let client = WsClientBuilder::default()
+        .build(format!("ws://{addr}"))
+        .await
+        .unwrap();
+
+    let (host_sub, host_token, mut guest_sub, guest_token) = connect_pair(&client).await;
+    let host_id = PeerToken::decode(&host_token).unwrap().peer_id;
+    let guest_id = PeerToken::decode(&guest_token).unwrap().peer_id;
+
+    drop(host_sub);
+    let room = wait_room(&mut guest_sub, |room| !room.peers.contains_key(&host_id)).await;
+    assert_eq!(room.host, guest_id);
+    client.lock_room(guest_token, true).await.unwrap();
+}
+
+#[tokio::test]
+async fn room_invites() {
+    let cfg = test_utils::test_config();
+    let (addr, _h) = server::run(cfg).await.unwrap();
+    let client = WsClientBuilder::default()
+        .build(format!("ws://{addr}"))
+        .await
+        .unwrap();
+
+    let (mut host_sub, host_token, _guest_sub, guest_token) = connect_pair(&client).await;
+    let options = RoomOptions {
+        capacity: 4,
+        max_invites: 1,
+        invite_ttl: Some(60),
+    };
+    assert_matches!(
+        client
+            .set_room_options(guest_token.clone(), options.clone())
+            .await,
+        Err(_)
+    );
+    client
+        .set_room_options(host_token.clone(), options.clone())
+        .await
+        .unwrap();
+    wait_room(&mut host_sub, |room| room.options == options).await;
+
+    assert_matches!(client.create_invite(guest_token.clone()).await, Err(_));
+    let invite = client.create_invite(host_token.clone()).await.unwrap();
+    assert_matches!(client.create_invite(host_token.clone()).await, Err(_));
+    assert_eq!(
+        client.list_invites(guest_token.clone()).await.unwrap(),
+        vec![invite.clone()]
+    );
+
+    // Invite is single-use
+    let mut other_sub = client
+        .sub_peer_events(Some(ClientHello::default()))
+        .await
+        .unwrap();
+    let Some(Ok(PeerEvent::Init {
+        token: other_token, ..
+    })) = other_sub.next().await
+    else {
+        panic!("unexpected event")
+    };
+    client
+        .invite(other_token, invite.passphrase.clone())
+        .await
+        .unwrap();
+    wait_room(&mut host_sub, |room| room.peers.len() == 3).await;
+    assert!(client
+        .list_invites(host_token.clone())
+        .await
+        .unwrap()
+        .is_empty());
+
+    // Revoked invite
+    let invite = client.create_invite(host_token.clone()).await.unwrap();
+    assert_matches!(
+        client
+            .revoke_invite(guest_token.clone(), invite.passphrase.clone())
+            .await,
+        Err(_)
+    );
+    client
+        .revoke_invite(host_token.clone(), invite.passphrase.clone())
+        .await
+        .unwrap();
+    let mut late_sub = client
+        .sub_peer_events(Some(ClientHello::default()))
+        .await
+        .unwrap();
+    let Some(Ok(PeerEvent::Init {
+        token: late_token, ..
+    })) = late_sub.next().await
+    else {
+        panic!("unexpected event")
+    };
+    assert_matches!(client.invite(late_token, invite.passphrase).await, Err(_));
+
+    // Capacity can't be less than the number of peers
+    let options = RoomOptions {
+        capacity: 2,
+        ..options
+    };
+    assert_matches!(client.set_room_options(host_token, options).await, Err(_));
+}
+
+#[tokio::test]
+async fn invite_passphrase_check() {
+    let mut cfg = test_utils::test_config();
+    cfg.passphrase = PassphraseConfig::Charset {
+        charset: "abcdefgh".to_owned(),
+        length: 8,
+        check: true,
+    };
+    let (addr, _h) = server::run(cfg).await.unwrap();
+    let client = WsClientBuilder::default()
+        .build(format!("ws://{addr}"))
+        .await
+        .unwrap();
+
+    let mut host_sub = client
+        .sub_peer_events(Some(ClientHello::default()))
+        .await
+        .unwrap();
+    let Some(Ok(PeerEvent::Init {
+        invite_passphrase: host_invite,
+        ..
+    })) = host_sub.next().await
+    else {
+        panic!("unexpected event")
+    };
+    assert!(passphrase::verify(&host_invite));
+
+    let mut guest_sub = client
+        .sub_peer_events(Some(ClientHello::default()))
+        .await
+        .unwrap();
+    let Some(Ok(PeerEvent::Init {
+        token: guest_token, ..
+    })) = guest_sub.next().await
+    else {
+        panic!("unexpected event")
+    };
+
+    // Mistyped character
+    let mistyped = host_invite.replacen(&host_invite[..1], "z", 1);
+    assert_matches!(client.invite(guest_token.clone(), mistyped).await, Err(_));
+
+    // Typed passphrase is normalized
+    client
+        .invite(guest_token, format!(" {} ", host_invite.to_uppercase()))
+        .await
+        .unwrap();
+    assert_matches!(guest_sub.next().await, Some(Ok(PeerEvent::Invite { .. })));
+}
+
+#[tokio::test]
+async fn invite_rate_limit() {
+    let mut cfg = test_utils::test_config();
+    cfg.server.forwarded_for = true;
+    cfg.invite_attempts = InviteAttemptsConfig {
+        free_attempts: 1,
+        backoff: Duration::from_secs(60),
+        max_backoff: Duration::from_secs(60),
+        invite_lockout: 2,
+    };
+    let (addr, _h) = server::run(cfg).await.unwrap();
+
+    let host_client = connect_via_proxy(addr, "10.0.0.1").await;
+    let (_host_sub, host_token, host_invite) = init_peer(&host_client).await;
+
+    // Guesses of the guest are delayed after the free attempt
+    let guest_client = connect_via_proxy(addr, "192.168.0.1, 10.0.0.2").await;
+    let (_guest_sub, guest_token, _) = init_peer(&guest_client).await;
+    for _ in 0..2 {
+        assert_matches!(
+            guest_client.invite(guest_token.clone(), "123".into()).await,
+            Err(err) if matches!(
+                Error::from_client_error(&err),
+                Some(Error::InviteNotFound { .. })
+            )
+        );
+    }
+    assert_matches!(
+        guest_client.invite(guest_token, host_invite.clone()).await,
+        Err(err) if matches!(
+            Error::from_client_error(&err),
+            Some(Error::RateLimited { .. })
+        )
+    );
+
+    // New peer of the same client stays blocked, even with spoofed address
+    let guest_client = connect_via_proxy(addr, "10.0.0.3, 10.0.0.2").await;
+    let (_guest_sub, guest_token, _) = init_peer(&guest_client).await;
+    assert_matches!(
+        guest_client.invite(guest_token, host_invite.clone()).await,
+        Err(err) if matches!(
+            Error::from_client_error(&err),
+            Some(Error::RateLimited { .. })
+        )
+    );
+
+    // Invite is locked out after failed attempts against it
+    for _ in 0..2 {
+        assert_matches!(
+            host_client.invite(host_token.clone(), host_invite.clone()).await,
+            Err(err) if matches!(
+                Error::from_client_error(&err),
+                Some(Error::SamePeer { .. })
+            )
+        );
+    }
+
+    let other_client = connect_via_proxy(addr, "10.0.0.4").await;
+    let (_other_sub, other_token, _) = init_peer(&other_client).await;
+    assert_matches!(
+        other_client.invite(other_token, host_invite).await,
+        Err(err) if matches!(
+            Error::from_client_error(&err),
+            Some(Error::InviteNotFound { .. })
+        )
+    );
+}
+
+#[tokio::test]
+async fn resume_session() {
+    let mut cfg = test_utils::test_config();
+    cfg.ttl.session = Duration::from_secs(1);
+    let (addr, _h) = server::run(cfg).await.unwrap();
+    let client = WsClientBuilder::default()
+        .build(format!("ws://{addr}"))
+        .await
+        .unwrap();
+
+    let (host_sub, host_token, mut guest_sub, guest_token) = connect_pair(&client).await;
+    let room_id = PeerToken::decode(&host_token).unwrap().room_id;
+    wait_room(&mut guest_sub, |room| room.peers.len() == 2).await;
+
+    // Room is updated while the host is away
+    host_sub.unsubscribe().await.unwrap();
+    let entity_id = client
+        .announce_entity(
+            guest_token,
+            AnnouncedEntity {
+                kind: EntityKind::File,
+                name: "123".to_owned(),
+                size: 123,
+                content: None,
+                manifest: None,
+                meta: EntityMeta::default(),
+            },
+        )
+        .await
+        .unwrap();
+
+    let mut host_sub = client
+        .resume_session(Some(ClientHello::default()), host_token.clone())
+        .await
+        .unwrap();
+    let Some(Ok(PeerEvent::Init {
+        token: resumed_token,
+        ..
+    })) = host_sub.next().await
+    else {
+        panic!("unexpected event")
+    };
+    assert_eq!(PeerToken::decode(&resumed_token).unwrap().room_id, room_id);
+    wait_room(&mut host_sub, |room| {
+        room.peers.len() == 2 && room.entities.contains_key(&entity_id)
+    })
+    .await;
+
+    // Resumed token is revoked
+    assert_matches!(client.get_room_state(host_token).await, Err(_));
+
+    // Session is closed after the grace period
+    host_sub.unsubscribe().await.unwrap();
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    assert_matches!(
+        client
+            .resume_session(Some(ClientHello::default()), resumed_token)
+            .await,
+        Err(_)
+    );
+    wait_room(&mut guest_sub, |room| room.peers.len() == 1).await;
+}
+
+/// Creates two peers connected to the same room. Returns their subscriptions and room tokens.
+async fn connect_pair(
+    client: &WsClient,
+) -> (
+    Subscription<PeerEvent>,
+    PeerTokenEncoded,
+    Subscription<PeerEvent>,
+    PeerTokenEncoded,
+) {
+    connect_pair_with(client, ClientHello::default()).await
+}
+
+/// Like [`connect_pair`], but the host subscribes with the hello.
+async fn connect_pair_with(
+    client: &WsClient,
+    host_hello: ClientHello,
+) -> (
+    Subscription<PeerEvent>,
+    PeerTokenEncoded,
+    Subscription<PeerEvent>,
+    PeerTokenEncoded,
+) {
+    let mut host_sub = client.sub_peer_events(Some(host_hello)).await.unwrap();
+    let Some(Ok(PeerEvent::Init {
+        invite_passphrase: host_invite,
+        ..
+    })) = host_sub.next().await
+    else {
+        panic!("unexpected event")
+    };
+
+    let mut guest_sub = client
+        .sub_peer_events(Some(ClientHello::default()))
+        .await
+        .unwrap();
+    let Some(Ok(PeerEvent::Init {
+        token: guest_token, ..
+    })) = guest_sub.next().await
+    else {
+        panic!("unexpected event")
+    };
+
+    client.invite(guest_token, host_invite).await.unwrap();
+
+    let Some(Ok(PeerEvent::Invite { token: host_token })) = host_sub.next().await else {
+        panic!("unexpected event")
+    };
+    let Some(Ok(PeerEvent::Invite { token: guest_token })) = guest_sub.next().await else {
+        panic!("unexpected event")
+    };
+
+    (host_sub, host_token, guest_sub, guest_token)
+}
+
+/// Skips room updates until the predicate is satisfied.
+/// Connects to the server as a client behind the reverse proxy.
+async fn connect_via_proxy(addr: SocketAddr, forwarded_for: &'static str) -> WsClient {
+    let mut headers = HeaderMap::new();
+    headers.insert("x-forwarded-for", HeaderValue::from_static(forwarded_for));
+    WsClientBuilder::default()
+        .set_headers(headers)
+        .build(format!("ws://{addr}"))
+        .await
+        .unwrap()
+}
+
+/// Subscribes new peer. Returns its subscription, token and invite.
+async fn init_peer(
+    client: &WsClient,
+) -> (Subscription<PeerEvent>, PeerTokenEncoded, InvitePassphrase) {
+    let mut sub = client
+        .sub_peer_events(Some(ClientHello::default()))
+        .await
+        .unwrap();
+    let Some(Ok(PeerEvent::Init {
+        token,
+        invite_passphrase,
+        ..
+    })) = sub.next().await
+    else {
+        panic!("unexpected event")
+    };
+
+    (sub, token, invite_passphrase)
+}
+
+async fn wait_room<F>(sub: &mut Subscription<PeerEvent>, pred: F) -> Room
+where
+    F: Fn(&Room) -> bool,
+{
+    let wait = async {
+        loop {
+            match sub.next().await {
+                Some(Ok(PeerEvent::UpdateRoom { room })) if pred(&room) => return room,
+                Some(Ok(_)) => continue,
+                other => panic!("unexpected event: {other:?}"),
+            }
+        }
+    };
+
+    tokio::time::timeout(Duration::from_secs(5), wait)
+        .await
+        .expect("room update timed out")
+}
+
+/// Skips events until progress of the entity transfer is received.
+async fn wait_progress(sub: &mut Subscription<PeerEvent>, entity_id: EntityId) -> TransferProgress {
+    let wait = async {
+        loop {
+            match sub.next().await {
+                Some(Ok(PeerEvent::TransferProgress {
+                    entity_id: id,
+                    progress,
+                    ..
+                })) if id == entity_id => return progress,
+                Some(Ok(_)) => continue,
+                other => panic!("unexpected event: {other:?}"),
+            }
+        }
+    };
+
+    tokio::time::timeout(Duration::from_secs(5), wait)
+        .await
+        .expect("progress timed out")
+}
+
+/// Skips events until the peer is disconnected. Returns the reason.
+async fn wait_disconnect(sub: &mut Subscription<PeerEvent>) -> DisconnectReason {
+    let wait = async {
+        loop {
+            match sub.next().await {
+                Some(Ok(PeerEvent::Disconnect { reason, .. })) => return reason,
+                Some(Ok(_)) => continue,
+                other => panic!("unexpected event: {other:?}"),
+            }
+        }
+    };
+
+    tokio::time::timeout(Duration::from_secs(5), wait)
+        .await
+        .expect("disconnect timed out")
 }
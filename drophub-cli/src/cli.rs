@@ -0,0 +1,36 @@
+use std::path::PathBuf;
+
+#[derive(clap::Parser, Debug)]
+#[command(
+    author,
+    version,
+    about,
+    long_about = None,
+    after_help = "The CLI has no WebRTC stack, entities are transferred only through \
+                  the server relay."
+)]
+pub struct Cli {
+    /// WebSocket URL of drophub server. Entities are transferred through the server relay,
+    /// so it must be enabled there.
+    #[arg(short, long, env = "DROPHUB_SERVER")]
+    pub server: String,
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum Command {
+    /// Announces files and directories and prints invite passphrase. Exits when every
+    /// entity is received.
+    Send {
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// Joins the room by invite passphrase and downloads every announced file and directory.
+    Receive {
+        passphrase: String,
+        /// Directory to save files to.
+        #[arg(short, long, default_value = ".")]
+        output: PathBuf,
+    },
+}
@@ -0,0 +1,190 @@
+use std::{
+    path::Path,
+    sync::{Arc, Mutex, Weak},
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail};
+use drophub::{
+    digest::{Digest, DigestHasher},
+    transfer::Frame,
+    Capability, ClientHello, Error, InvitePassphrase, PeerEvent, PeerId, PeerToken,
+    PeerTokenEncoded, RelayData, RpcClient,
+};
+use jsonrpsee::{
+    core::client::Subscription,
+    ws_client::{WsClient, WsClientBuilder},
+};
+use tokio::{fs::File, io::AsyncReadExt};
+
+/// Size of buffer the file is read with to compute its digest.
+const DIGEST_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Delay before relaying a frame again when the server limits are exceeded.
+const RELAY_RETRY_DELAY: Duration = Duration::from_millis(200);
+
+/// Delay before refreshing the room token again after a failure.
+const REFRESH_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Connection to the server with a subscription to peer events.
+pub struct Session {
+    pub client: Arc<WsClient>,
+    pub events: Subscription<PeerEvent>,
+    pub peer_id: PeerId,
+    pub token: PeerTokenEncoded,
+    pub invite_passphrase: InvitePassphrase,
+}
+
+impl Session {
+    /// Connects to the server. Relay and encryption are always requested: the CLI has
+    /// no WebRTC stack, so entities are transferred only through the relay, and relayed
+    /// chunks are sealed end-to-end.
+    pub async fn connect(server: &str, capabilities: &[Capability]) -> anyhow::Result<Self> {
+        let client = WsClientBuilder::default().build(server).await?;
+        let hello =
+            ClientHello::new([&[Capability::Relay, Capability::Encryption], capabilities].concat());
+        let mut events = client.sub_peer_events(Some(hello)).await?;
+
+        let PeerEvent::Init {
+            token,
+            invite_passphrase,
+            capabilities,
+            ..
+        } = next_event(&mut events).await?
+        else {
+            bail!("Unexpected first event");
+        };
+        if !capabilities.contains(&Capability::Relay) {
+            bail!(
+                "Relay is disabled on the server. The CLI transfers entities only through \
+                 the relay, use the web app to transfer them peer-to-peer"
+            );
+        }
+        if !capabilities.contains(&Capability::Encryption) {
+            bail!("Server doesn't support end-to-end encryption, update the server");
+        }
+        let peer_id = PeerToken::decode(&token)?.peer_id;
+
+        Ok(Self {
+            client: Arc::new(client),
+            events,
+            peer_id,
+            token,
+            invite_passphrase,
+        })
+    }
+
+    /// Waits until the peer is connected to a room. Returns room token, which is refreshed
+    /// in the background while the session is alive.
+    pub async fn wait_room_token(&mut self) -> anyhow::Result<RoomToken> {
+        let token = loop {
+            if let PeerEvent::Invite { token } = next_event(&mut self.events).await? {
+                break RoomToken(Arc::new(Mutex::new(token)));
+            }
+        };
+
+        tokio::spawn(keep_fresh(Arc::downgrade(&self.client), token.clone()));
+        Ok(token)
+    }
+}
+
+/// Room token shared by the session and the task refreshing it.
+#[derive(Debug, Clone)]
+pub struct RoomToken(Arc<Mutex<PeerTokenEncoded>>);
+
+impl RoomToken {
+    /// Returns the actual token. Refreshed token revokes the previous one, so it must
+    /// be taken for every request.
+    pub fn get(&self) -> PeerTokenEncoded {
+        self.0.lock().expect("token lock is poisoned").clone()
+    }
+
+    fn set(&self, token: PeerTokenEncoded) {
+        *self.0.lock().expect("token lock is poisoned") = token;
+    }
+}
+
+/// Refreshes the token before it expires until the client is dropped.
+async fn keep_fresh(client: Weak<WsClient>, token: RoomToken) {
+    loop {
+        let current = token.get();
+        let delay = match PeerToken::decode(&current) {
+            Ok(decoded) => decoded.refresh_delay(),
+            Err(err) => {
+                tracing::warn!(?err, "Failed to decode room token");
+                return;
+            }
+        };
+        tokio::time::sleep(delay).await;
+
+        let Some(client) = client.upgrade() else {
+            return;
+        };
+        match client.refresh_token(current).await {
+            Ok(refreshed) => token.set(refreshed),
+            Err(err) => match Error::from_client_error(&err) {
+                Some(Error::TokenExpired | Error::InvalidToken { .. }) => {
+                    tracing::warn!(?err, "Room token can't be refreshed anymore");
+                    return;
+                }
+                _ => {
+                    tracing::warn!(?err, "Failed to refresh room token");
+                    tokio::time::sleep(REFRESH_RETRY_DELAY).await;
+                }
+            },
+        }
+    }
+}
+
+pub async fn next_event(events: &mut Subscription<PeerEvent>) -> anyhow::Result<PeerEvent> {
+    match events.next().await {
+        Some(Ok(PeerEvent::Disconnect { reason, .. })) => bail!("Disconnected: {reason:?}"),
+        Some(event) => Ok(event?),
+        None => Err(anyhow!("Subscription closed by the server")),
+    }
+}
+
+/// Relays frame to another peer. Waits and retries while room bandwidth is exhausted
+/// or the receiver is busy.
+pub async fn relay_frame(
+    client: &WsClient,
+    token: &RoomToken,
+    to_peer_id: PeerId,
+    frame: &Frame,
+) -> anyhow::Result<()> {
+    let data = RelayData(frame.encode());
+    loop {
+        let current = token.get();
+        let err = match client
+            .relay_frame(current.clone(), to_peer_id, data.clone())
+            .await
+        {
+            Ok(()) => return Ok(()),
+            Err(err) => err,
+        };
+
+        match Error::from_client_error(&err) {
+            Some(Error::RoomRelayBandwidthLimitExceeded { .. } | Error::PeerIsBusy { .. }) => {
+                tokio::time::sleep(RELAY_RETRY_DELAY).await;
+            }
+            // Token is refreshed meanwhile
+            Some(Error::InvalidToken { .. }) if token.get() != current => {}
+            Some(err) => return Err(err.into()),
+            None => return Err(err.into()),
+        }
+    }
+}
+
+/// Computes digest of the file content.
+pub async fn file_digest(path: &Path) -> anyhow::Result<Digest> {
+    let mut file = File::open(path).await?;
+    let mut hasher = DigestHasher::new();
+    let mut buf = vec![0; DIGEST_BUFFER_SIZE];
+    loop {
+        let read = file.read(&mut buf).await?;
+        if read == 0 {
+            return Ok(hasher.finalize());
+        }
+        hasher.update(&buf[..read]);
+    }
+}
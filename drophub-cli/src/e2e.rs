@@ -0,0 +1,104 @@
+//! End-to-end encryption of relayed chunks, see [`drophub::crypto`]. Keys are exchanged
+//! with every peer the CLI transfers entities with, the server relays only sealed chunks.
+
+use std::collections::HashMap;
+
+use drophub::{
+    crypto::{KeyExchange, PublicKeyBytes, SessionKey},
+    PeerId, RpcClient, SignalPayload,
+};
+use jsonrpsee::ws_client::WsClient;
+
+use crate::client::RoomToken;
+
+/// Session keys shared with other peers of the room.
+pub struct PeerKeys {
+    local_peer_id: PeerId,
+    /// Exchanges started by the local peer and waiting for the remote public key.
+    pending: HashMap<PeerId, KeyExchange>,
+    keys: HashMap<PeerId, SessionKey>,
+}
+
+impl PeerKeys {
+    pub fn new(local_peer_id: PeerId) -> Self {
+        Self {
+            local_peer_id,
+            pending: HashMap::new(),
+            keys: HashMap::new(),
+        }
+    }
+
+    pub fn get(&self, peer_id: &PeerId) -> Option<&SessionKey> {
+        self.keys.get(peer_id)
+    }
+
+    /// Sends public key to the peer unless keys are exchanged or the exchange is started.
+    pub async fn start(
+        &mut self,
+        client: &WsClient,
+        token: &RoomToken,
+        peer_id: PeerId,
+    ) -> anyhow::Result<()> {
+        if self.keys.contains_key(&peer_id) || self.pending.contains_key(&peer_id) {
+            return Ok(());
+        }
+
+        let key_exchange = KeyExchange::new();
+        let public_key = key_exchange.public_key();
+        self.pending.insert(peer_id, key_exchange);
+        send_public_key(client, token, peer_id, public_key).await
+    }
+
+    /// Handles signal of the peer. Public key either completes the exchange started by
+    /// the local peer or starts a new one, then the local public key is sent back.
+    /// Returns `true` if a new session key is derived.
+    pub async fn handle_signal(
+        &mut self,
+        client: &WsClient,
+        token: &RoomToken,
+        from_peer_id: PeerId,
+        payload: SignalPayload,
+    ) -> anyhow::Result<bool> {
+        let SignalPayload::PublicKey { public_key } = payload else {
+            tracing::debug!(?from_peer_id, "Ignored WebRTC signal");
+            return Ok(false);
+        };
+
+        let (key_exchange, reply) = match self.pending.remove(&from_peer_id) {
+            Some(key_exchange) => (key_exchange, None),
+            None => {
+                let key_exchange = KeyExchange::new();
+                let reply = key_exchange.public_key();
+                (key_exchange, Some(reply))
+            }
+        };
+        let key = key_exchange.derive(self.local_peer_id, from_peer_id, public_key)?;
+        eprintln!(
+            "Key fingerprint with peer {from_peer_id}: {}. Compare it with the other side \
+             to make sure the server doesn't intercept the transfer",
+            key.fingerprint()
+        );
+        self.keys.insert(from_peer_id, key);
+
+        if let Some(public_key) = reply {
+            send_public_key(client, token, from_peer_id, public_key).await?;
+        }
+        Ok(true)
+    }
+}
+
+async fn send_public_key(
+    client: &WsClient,
+    token: &RoomToken,
+    to_peer_id: PeerId,
+    public_key: PublicKeyBytes,
+) -> anyhow::Result<()> {
+    client
+        .send_signal(
+            token.get(),
+            to_peer_id,
+            SignalPayload::PublicKey { public_key },
+        )
+        .await?;
+    Ok(())
+}
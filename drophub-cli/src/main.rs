@@ -0,0 +1,33 @@
+mod cli;
+mod client;
+mod e2e;
+mod receive;
+mod send;
+mod stream;
+
+use clap::Parser;
+use tracing_subscriber::EnvFilter;
+
+use crate::cli::{Cli, Command};
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    init_logging()?;
+
+    let cli = Cli::parse();
+    match cli.command {
+        Command::Send { files } => send::run(&cli.server, files).await,
+        Command::Receive { passphrase, output } => {
+            receive::run(&cli.server, passphrase, output).await
+        }
+    }
+}
+
+fn init_logging() -> anyhow::Result<()> {
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::from_default_env())
+        .with_writer(std::io::stderr)
+        .init();
+
+    Ok(())
+}
@@ -0,0 +1,484 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, bail};
+use drophub::{
+    crypto::SessionKey,
+    passphrase,
+    transfer::{ChunkReceiver, Frame, FrameError},
+    Entity, EntityId, EntityKind, PeerEvent, PeerId, RelayFrame, Room, RpcClient, TransferState,
+};
+use jsonrpsee::{core::client::Subscription, ws_client::WsClient};
+
+use crate::{
+    client::{file_digest, next_event, relay_frame, RoomToken, Session},
+    e2e::PeerKeys,
+    stream::{directory_members, file_members, Member, StreamWriter},
+};
+
+/// Time to wait for more entities after every known entity is downloaded.
+const ANNOUNCE_SETTLE: Duration = Duration::from_secs(2);
+/// Time to wait for the owner to accept the entity request or to send its public key.
+const ANSWER_TIMEOUT: Duration = Duration::from_secs(60);
+/// Transfer is requested again if no chunk arrives within this time.
+const CHUNK_TIMEOUT: Duration = Duration::from_secs(10);
+/// Number of requests without progress before the download fails.
+const MAX_RETRIES: usize = 5;
+/// Minimum time between progress reports. The server drops more frequent ones anyway.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+
+pub async fn run(server: &str, invite_passphrase: String, output: PathBuf) -> anyhow::Result<()> {
+    let invite_passphrase = passphrase::normalize(&invite_passphrase);
+    if !passphrase::verify(&invite_passphrase) {
+        bail!("Invite passphrase is mistyped, check character doesn't match");
+    }
+
+    // Events aren't read while downloading, so progress of transfers isn't requested
+    let mut session = Session::connect(server, &[]).await?;
+    session
+        .client
+        .invite(session.token.clone(), invite_passphrase)
+        .await?;
+    let token = session.wait_room_token().await?;
+    let mut relay = session.client.sub_relay_frames(token.get()).await?;
+    tokio::fs::create_dir_all(&output).await?;
+
+    let mut relayed_peers = HashSet::new();
+    let mut downloaded = HashSet::new();
+    let mut saved = HashSet::new();
+    let mut keys = PeerKeys::new(session.peer_id);
+    let mut room = session.client.get_room_state(token.get()).await?;
+    loop {
+        let pending = pending_entities(&room, session.peer_id, &downloaded);
+        if pending.is_empty() {
+            match wait_room_update(&mut session.events, ANNOUNCE_SETTLE).await? {
+                Some(updated) => {
+                    room = updated;
+                    continue;
+                }
+                None if downloaded.is_empty() => continue,
+                None => return Ok(()),
+            }
+        }
+
+        for (entity_id, entity) in pending {
+            if relayed_peers.insert(entity.owner_id) {
+                session
+                    .client
+                    .request_relay(token.get(), entity.owner_id)
+                    .await?;
+            }
+            if keys.get(&entity.owner_id).is_none() {
+                exchange_keys(
+                    &session.client,
+                    &mut session.events,
+                    &token,
+                    &mut keys,
+                    entity.owner_id,
+                )
+                .await?;
+            }
+            let key = keys.get(&entity.owner_id).expect("keys are exchanged");
+
+            // Declined entity isn't requested again
+            downloaded.insert(entity_id);
+            session
+                .client
+                .request_entity(token.get(), entity_id)
+                .await?;
+            if !wait_accepted(&mut session.events, entity_id, session.peer_id).await? {
+                eprintln!("Skipped {}, request declined", entity.name);
+                continue;
+            }
+
+            let path = unique_path(&output, &file_name(entity_id, &entity.name), &saved).await?;
+            saved.insert(path.clone());
+            if let Err(err) =
+                download(&session, &mut relay, &token, key, entity_id, &entity, &path).await
+            {
+                let _ = session
+                    .client
+                    .report_transfer(
+                        token.get(),
+                        entity_id,
+                        session.peer_id,
+                        TransferState::Failed,
+                    )
+                    .await;
+                return Err(err);
+            }
+            session
+                .client
+                .report_transfer(token.get(), entity_id, session.peer_id, TransferState::Done)
+                .await?;
+            eprintln!("Received {}", path.display());
+        }
+        room = session.client.get_room_state(token.get()).await?;
+    }
+}
+
+/// Returns files and directories announced by other peers and not downloaded yet.
+fn pending_entities(
+    room: &Room,
+    peer_id: PeerId,
+    downloaded: &HashSet<EntityId>,
+) -> Vec<(EntityId, Entity)> {
+    room.entities
+        .iter()
+        .filter(|(entity_id, entity)| {
+            matches!(entity.kind, EntityKind::File | EntityKind::Directory)
+                && entity.owner_id != peer_id
+                && !downloaded.contains(entity_id)
+        })
+        .map(|(entity_id, entity)| (*entity_id, entity.clone()))
+        .collect()
+}
+
+/// Exchanges keys with the owner before its entities are requested.
+async fn exchange_keys(
+    client: &WsClient,
+    events: &mut Subscription<PeerEvent>,
+    token: &RoomToken,
+    keys: &mut PeerKeys,
+    peer_id: PeerId,
+) -> anyhow::Result<()> {
+    keys.start(client, token, peer_id).await?;
+    let wait = async {
+        while keys.get(&peer_id).is_none() {
+            if let PeerEvent::Signal {
+                from_peer_id,
+                payload,
+            } = next_event(events).await?
+            {
+                keys.handle_signal(client, token, from_peer_id, payload)
+                    .await?;
+            }
+        }
+        Ok(())
+    };
+
+    match tokio::time::timeout(ANSWER_TIMEOUT, wait).await {
+        Ok(exchanged) => exchanged,
+        Err(_) => bail!("Peer {peer_id} didn't send its public key"),
+    }
+}
+
+/// Waits for the owner to answer request for the entity. Returns `false` if the request
+/// is declined or the entity is removed.
+async fn wait_accepted(
+    events: &mut Subscription<PeerEvent>,
+    entity_id: EntityId,
+    peer_id: PeerId,
+) -> anyhow::Result<bool> {
+    let wait = async {
+        loop {
+            let PeerEvent::UpdateRoom { room } = next_event(events).await? else {
+                continue;
+            };
+            let Some(entity) = room.entities.get(&entity_id) else {
+                return Ok(false);
+            };
+            match entity.transfers.get(&peer_id) {
+                Some(TransferState::Transferring) => return Ok(true),
+                Some(TransferState::Declined) => return Ok(false),
+                _ => {}
+            }
+        }
+    };
+
+    match tokio::time::timeout(ANSWER_TIMEOUT, wait).await {
+        Ok(accepted) => accepted,
+        Err(_) => bail!("Owner didn't answer request for entity {entity_id}"),
+    }
+}
+
+/// Waits for room update. Returns `None` on timeout.
+async fn wait_room_update(
+    events: &mut Subscription<PeerEvent>,
+    timeout: Duration,
+) -> anyhow::Result<Option<Room>> {
+    let wait = async {
+        loop {
+            if let PeerEvent::UpdateRoom { room } = next_event(events).await? {
+                return Ok(room);
+            }
+        }
+    };
+
+    match tokio::time::timeout(timeout, wait).await {
+        Ok(room) => room.map(Some),
+        Err(_) => Ok(None),
+    }
+}
+
+async fn download(
+    session: &Session,
+    relay: &mut Subscription<RelayFrame>,
+    token: &RoomToken,
+    key: &SessionKey,
+    entity_id: EntityId,
+    entity: &Entity,
+    path: &Path,
+) -> anyhow::Result<()> {
+    let owner_id = entity.owner_id;
+    let cipher = key.entity_cipher(entity_id);
+    let members = match (&entity.kind, &entity.manifest) {
+        (EntityKind::Directory, Some(manifest)) => {
+            manifest.validate()?;
+            directory_members(path, manifest)
+        }
+        (EntityKind::Directory, None) => bail!("Directory {} has no manifest", entity.name),
+        _ => file_members(path.to_path_buf(), entity.size as u64),
+    };
+    let mut writer = StreamWriter::new(members.clone());
+    let mut receiver = ChunkReceiver::new(entity_id, entity.size as u64);
+    relay_frame(&session.client, token, owner_id, &receiver.request()).await?;
+
+    let mut retries = 0;
+    let mut reported_at = Instant::now();
+    while !receiver.is_complete() {
+        let relay_frame_res = tokio::time::timeout(CHUNK_TIMEOUT, relay.next()).await;
+        let Ok(relay_frame_res) = relay_frame_res else {
+            retries += 1;
+            if retries > MAX_RETRIES {
+                bail!("Peer {owner_id} stopped sending {}", entity.name);
+            }
+
+            // Resume from the last acknowledged offset, bytes after it are overwritten
+            let request = receiver.request();
+            relay_frame(&session.client, token, owner_id, &request).await?;
+            continue;
+        };
+
+        let relayed = relay_frame_res.ok_or_else(|| anyhow!("Relay subscription closed"))??;
+        if relayed.from_peer_id != owner_id {
+            continue;
+        }
+        let Ok(Frame::Chunk {
+            entity_id: chunk_entity_id,
+            offset,
+            data,
+        }) = Frame::decode(&relayed.data.0)
+        else {
+            continue;
+        };
+        if chunk_entity_id != entity_id {
+            continue;
+        }
+        let data = match cipher.open(offset, &data) {
+            Ok(data) => data,
+            Err(err) => {
+                // Owner sends it again on the next request
+                tracing::warn!(?err, ?entity_id, offset, "Failed to open chunk");
+                continue;
+            }
+        };
+
+        match receiver.on_chunk(offset, data.len() as u64) {
+            Ok(ack) => {
+                retries = 0;
+                writer.write_at(offset, &data).await?;
+                if let Some(ack) = ack {
+                    writer.flush().await?;
+                    relay_frame(&session.client, token, owner_id, &ack).await?;
+                }
+                if reported_at.elapsed() >= PROGRESS_INTERVAL {
+                    reported_at = Instant::now();
+                    let transferred = receiver.offset() as usize;
+                    if let Err(err) = session
+                        .client
+                        .report_progress(token.get(), entity_id, session.peer_id, transferred)
+                        .await
+                    {
+                        tracing::warn!(?err, ?entity_id, "Failed to report progress");
+                    }
+                }
+            }
+            // Chunks sent before the last request, the expected one arrives later
+            Err(FrameError::UnexpectedOffset { .. }) => {}
+            Err(err) => return Err(err.into()),
+        }
+    }
+
+    writer.finish().await?;
+    verify_digests(entity, &members).await
+}
+
+/// Checks digests of the received files. Corrupted file is removed.
+async fn verify_digests(entity: &Entity, members: &[Member]) -> anyhow::Result<()> {
+    let digests: Vec<_> = match &entity.manifest {
+        Some(manifest) => manifest
+            .entries
+            .iter()
+            .map(|entry| entry.digest.as_ref())
+            .collect(),
+        None => vec![entity.meta.digest.as_ref()],
+    };
+    for (member, expected) in members.iter().zip(digests) {
+        let Some(expected) = expected else {
+            continue;
+        };
+
+        let actual = file_digest(&member.path).await?;
+        if actual != *expected {
+            let _ = tokio::fs::remove_file(&member.path).await;
+            bail!(
+                "Received {} is corrupted: expected digest {expected}, got {actual}",
+                member.path.display()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Returns path in the output directory not taken by an existing file or another entity.
+/// Suffix ` (n)` is added to the name if necessary.
+async fn unique_path(
+    output: &Path,
+    name: &str,
+    saved: &HashSet<PathBuf>,
+) -> anyhow::Result<PathBuf> {
+    let name = Path::new(name);
+    let stem = name
+        .file_stem()
+        .unwrap_or(name.as_os_str())
+        .to_string_lossy();
+    let ext = name
+        .extension()
+        .map(|ext| format!(".{}", ext.to_string_lossy()))
+        .unwrap_or_default();
+
+    let mut path = output.join(name);
+    for n in 1.. {
+        if !saved.contains(&path) && !tokio::fs::try_exists(&path).await? {
+            break;
+        }
+        path = output.join(format!("{stem} ({n}){ext}"));
+    }
+    Ok(path)
+}
+
+/// Returns name of the file or directory to save entity to. Directories of the name
+/// are ignored.
+fn file_name(entity_id: EntityId, name: &str) -> String {
+    Path::new(name)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| entity_id.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use drophub::{
+        digest::Digest,
+        manifest::{Manifest, ManifestEntry},
+        EntityMeta,
+    };
+    use uuid::Uuid;
+
+    use super::*;
+
+    fn entity(kind: EntityKind, manifest: Option<Manifest>, digest: Option<Digest>) -> Entity {
+        Entity {
+            kind,
+            name: "entity".to_owned(),
+            size: 0,
+            owner_id: Uuid::new_v4(),
+            content: None,
+            manifest,
+            meta: EntityMeta {
+                digest,
+                ..Default::default()
+            },
+            transfers: Default::default(),
+        }
+    }
+
+    #[test]
+    fn file_name_strips_directories() {
+        let entity_id = Uuid::new_v4();
+        assert_eq!(file_name(entity_id, "photo.png"), "photo.png");
+        assert_eq!(file_name(entity_id, "../../etc/passwd"), "passwd");
+        assert_eq!(file_name(entity_id, "/etc/passwd"), "passwd");
+        assert_eq!(file_name(entity_id, "dir/"), "dir");
+        assert_eq!(file_name(entity_id, ".."), entity_id.to_string());
+        assert_eq!(file_name(entity_id, "/"), entity_id.to_string());
+        assert_eq!(file_name(entity_id, ""), entity_id.to_string());
+    }
+
+    #[tokio::test]
+    async fn unique_path_adds_suffix() {
+        let output = tempfile::tempdir().unwrap();
+        let mut saved = HashSet::new();
+
+        let path = unique_path(output.path(), "a.txt", &saved).await.unwrap();
+        assert_eq!(path, output.path().join("a.txt"));
+
+        // Taken by another entity of this run
+        saved.insert(path);
+        let path = unique_path(output.path(), "a.txt", &saved).await.unwrap();
+        assert_eq!(path, output.path().join("a (1).txt"));
+
+        // Taken by an existing file
+        tokio::fs::write(&path, b"").await.unwrap();
+        let path = unique_path(output.path(), "a.txt", &saved).await.unwrap();
+        assert_eq!(path, output.path().join("a (2).txt"));
+
+        tokio::fs::create_dir(output.path().join("dir"))
+            .await
+            .unwrap();
+        let path = unique_path(output.path(), "dir", &saved).await.unwrap();
+        assert_eq!(path, output.path().join("dir (1)"));
+    }
+
+    #[tokio::test]
+    async fn verify_file_digest() {
+        let output = tempfile::tempdir().unwrap();
+        let path = output.path().join("a.txt");
+        tokio::fs::write(&path, b"content").await.unwrap();
+        let members = file_members(path.clone(), 7);
+
+        let valid = entity(EntityKind::File, None, Some(Digest::sha256(b"content")));
+        verify_digests(&valid, &members).await.unwrap();
+        assert!(path.exists());
+
+        let unknown = entity(EntityKind::File, None, None);
+        verify_digests(&unknown, &members).await.unwrap();
+        assert!(path.exists());
+
+        let corrupted = entity(EntityKind::File, None, Some(Digest::sha256(b"other")));
+        assert!(verify_digests(&corrupted, &members).await.is_err());
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn verify_directory_digests() {
+        let output = tempfile::tempdir().unwrap();
+        let manifest = Manifest {
+            entries: vec![
+                ManifestEntry {
+                    path: "a.txt".to_owned(),
+                    size: 1,
+                    digest: Some(Digest::sha256(b"a")),
+                },
+                ManifestEntry {
+                    path: "sub/b.txt".to_owned(),
+                    size: 1,
+                    digest: Some(Digest::sha256(b"b")),
+                },
+            ],
+        };
+        let members = directory_members(output.path(), &manifest);
+        let mut writer = StreamWriter::new(members.clone());
+        writer.write_at(0, b"ac").await.unwrap();
+        writer.finish().await.unwrap();
+
+        let directory = entity(EntityKind::Directory, Some(manifest), None);
+        assert!(verify_digests(&directory, &members).await.is_err());
+        assert!(output.path().join("a.txt").exists());
+        assert!(!output.path().join("sub").join("b.txt").exists());
+    }
+}
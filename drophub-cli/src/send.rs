@@ -0,0 +1,281 @@
+use std::{
+    collections::{hash_map::Entry, HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Context};
+use chrono::{DateTime, Utc};
+use drophub::{
+    crypto::EntityCipher,
+    manifest::{Manifest, ManifestEntry, PATH_SEPARATOR},
+    transfer::{ChunkSender, Frame},
+    AnnouncedEntity, Capability, EntityId, EntityKind, EntityMeta, PeerEvent, PeerId, RpcClient,
+};
+use jsonrpsee::ws_client::WsClient;
+
+use crate::{
+    client::{file_digest, next_event, relay_frame, RoomToken, Session},
+    e2e::PeerKeys,
+    stream::{directory_members, file_members, Member, StreamReader},
+};
+
+/// Announced file or directory.
+struct Announced {
+    path: PathBuf,
+    members: Vec<Member>,
+}
+
+struct Transfer {
+    sender: ChunkSender,
+    reader: StreamReader,
+    cipher: EntityCipher,
+}
+
+pub async fn run(server: &str, files: Vec<PathBuf>) -> anyhow::Result<()> {
+    let mut session = Session::connect(server, &[Capability::Progress]).await?;
+    // Passphrase is the only stdout output, so scripts can capture it
+    println!("{}", session.invite_passphrase);
+
+    let token = session.wait_room_token().await?;
+    let mut entities = HashMap::new();
+    for path in files {
+        let meta = tokio::fs::metadata(&path)
+            .await
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let name = path
+            .file_name()
+            .ok_or_else(|| anyhow!("Path {} has no file name", path.display()))?
+            .to_string_lossy()
+            .into_owned();
+        let (kind, manifest, digest, members) = if meta.is_dir() {
+            let manifest = directory_manifest(&path).await?;
+            let members = directory_members(&path, &manifest);
+            (EntityKind::Directory, Some(manifest), None, members)
+        } else {
+            let digest = file_digest(&path)
+                .await
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            let members = file_members(path.clone(), meta.len());
+            (EntityKind::File, None, Some(digest), members)
+        };
+        let size = members.last().map_or(0, |member| member.range.end) as usize;
+
+        let entity_id = session
+            .client
+            .announce_entity(
+                token.get(),
+                AnnouncedEntity {
+                    kind,
+                    name,
+                    size,
+                    content: None,
+                    manifest,
+                    meta: EntityMeta {
+                        digest,
+                        modified_at: meta.modified().ok().map(DateTime::<Utc>::from),
+                        ..Default::default()
+                    },
+                },
+            )
+            .await?;
+        entities.insert(entity_id, Announced { path, members });
+    }
+
+    let mut relay = session.client.sub_relay_frames(token.get()).await?;
+    let mut transfers = HashMap::<(PeerId, EntityId), Transfer>::new();
+    let mut delivered = HashSet::new();
+    let mut keys = PeerKeys::new(session.peer_id);
+    let mut relayed_peers = HashSet::new();
+
+    while delivered.len() < entities.len() {
+        tokio::select! {
+            relay_frame = relay.next() => {
+                let relay_frame = relay_frame.ok_or_else(|| anyhow!("Relay subscription closed"))??;
+                let peer_id = relay_frame.from_peer_id;
+                let frame = match Frame::decode(&relay_frame.data.0) {
+                    Ok(frame) => frame,
+                    Err(err) => {
+                        tracing::warn!(?err, ?peer_id, "Failed to decode frame");
+                        continue;
+                    }
+                };
+
+                match frame {
+                    Frame::Request { entity_id, offset } => {
+                        let Some(announced) = entities.get(&entity_id) else {
+                            tracing::warn!(?entity_id, ?peer_id, "Unknown entity requested");
+                            continue;
+                        };
+                        let Some(key) = keys.get(&peer_id) else {
+                            // Receiver requests again after keys are exchanged
+                            tracing::warn!(?entity_id, ?peer_id, "Entity requested before keys are exchanged");
+                            continue;
+                        };
+
+                        let transfer = match transfers.entry((peer_id, entity_id)) {
+                            Entry::Occupied(entry) => entry.into_mut(),
+                            Entry::Vacant(entry) => {
+                                let reader = StreamReader::new(announced.members.clone());
+                                entry.insert(Transfer {
+                                    sender: ChunkSender::new(entity_id, reader.size()),
+                                    reader,
+                                    cipher: key.entity_cipher(entity_id),
+                                })
+                            }
+                        };
+                        transfer.sender.resume(offset)?;
+                        if transfer.sender.is_complete() {
+                            // Empty file or the receiver already has everything
+                            transfers.remove(&(peer_id, entity_id));
+                            delivered.insert(entity_id);
+                            eprintln!("Sent {}", announced.path.display());
+                        }
+                    }
+                    Frame::Ack { entity_id, offset } => {
+                        let Some(transfer) = transfers.get_mut(&(peer_id, entity_id)) else {
+                            continue;
+                        };
+
+                        transfer.sender.on_ack(offset)?;
+                        if transfer.sender.is_complete() {
+                            transfers.remove(&(peer_id, entity_id));
+                            delivered.insert(entity_id);
+                            eprintln!("Sent {}", entities[&entity_id].path.display());
+                        }
+                    }
+                    Frame::Chunk { .. } => {}
+                }
+            }
+            event = next_event(&mut session.events) => {
+                match event? {
+                    PeerEvent::EntityRequested { by, entity_id } => {
+                        // Every announced entity is shared with anyone in the room
+                        let accept = entities.contains_key(&entity_id);
+                        session
+                            .client
+                            .answer_entity_request(token.get(), entity_id, by, accept)
+                            .await?;
+                        if accept {
+                            // Browsers switch to the relay at once instead of trying
+                            // to connect directly
+                            if relayed_peers.insert(by) {
+                                session.client.request_relay(token.get(), by).await?;
+                            }
+                            keys.start(&session.client, &token, by).await?;
+                        }
+                    }
+                    PeerEvent::Signal { from_peer_id, payload } => {
+                        match keys.handle_signal(&session.client, &token, from_peer_id, payload).await {
+                            // Chunks sealed with the previous key can't be opened anymore,
+                            // the receiver requests the entities again
+                            Ok(true) => transfers.retain(|(peer_id, _), _| *peer_id != from_peer_id),
+                            Ok(false) => {}
+                            Err(err) => tracing::warn!(?err, ?from_peer_id, "Failed to exchange keys"),
+                        }
+                    }
+                    PeerEvent::TransferProgress { entity_id, peer_id, progress } => {
+                        if let Some(announced) = entities.get(&entity_id) {
+                            let eta = progress
+                                .eta
+                                .map_or_else(|| "unknown".to_owned(), |eta| format!("{eta}s"));
+                            eprintln!(
+                                "Sending {} to {peer_id}: {}/{} bytes, {} bytes/s, ETA {eta}",
+                                announced.path.display(),
+                                progress.transferred,
+                                progress.size,
+                                progress.throughput,
+                            );
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        let mut failed = Vec::new();
+        for (&(peer_id, entity_id), transfer) in &mut transfers {
+            if let Err(err) = send_chunks(&session.client, &token, peer_id, transfer).await {
+                tracing::warn!(?err, ?peer_id, ?entity_id, "Transfer interrupted");
+                failed.push((peer_id, entity_id));
+            }
+        }
+        for key in failed {
+            transfers.remove(&key);
+        }
+    }
+
+    Ok(())
+}
+
+/// Sends chunks until the window of unacknowledged bytes is full.
+async fn send_chunks(
+    client: &WsClient,
+    token: &RoomToken,
+    peer_id: PeerId,
+    transfer: &mut Transfer,
+) -> anyhow::Result<()> {
+    while let Some(range) = transfer.sender.next_chunk() {
+        let data = transfer.reader.read_range(range.clone()).await?;
+        let data = transfer.cipher.seal(range.start, &data)?;
+        let frame = Frame::Chunk {
+            entity_id: transfer.sender.entity_id(),
+            offset: range.start,
+            data,
+        };
+        relay_frame(client, token, peer_id, &frame).await?;
+    }
+
+    Ok(())
+}
+
+/// Lists files of the directory recursively in a stable order. Symlinks are skipped,
+/// so files outside the directory are never sent.
+async fn directory_manifest(root: &Path) -> anyhow::Result<Manifest> {
+    let mut entries = Vec::new();
+    let mut dirs = vec![PathBuf::new()];
+    while let Some(dir) = dirs.pop() {
+        let mut read_dir = tokio::fs::read_dir(root.join(&dir))
+            .await
+            .with_context(|| format!("Failed to read {}", root.join(&dir).display()))?;
+        while let Some(dir_entry) = read_dir.next_entry().await? {
+            let relative = dir.join(dir_entry.file_name());
+            let file_type = dir_entry.file_type().await?;
+            if file_type.is_dir() {
+                dirs.push(relative);
+            } else if file_type.is_file() {
+                let path = root.join(&relative);
+                let digest = file_digest(&path)
+                    .await
+                    .with_context(|| format!("Failed to read {}", path.display()))?;
+                entries.push(ManifestEntry {
+                    path: manifest_path(&relative)?,
+                    size: dir_entry.metadata().await?.len() as usize,
+                    digest: Some(digest),
+                });
+            } else {
+                tracing::warn!(path = %root.join(&relative).display(), "Skipped not a regular file");
+            }
+        }
+    }
+
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    let manifest = Manifest { entries };
+    manifest
+        .validate()
+        .with_context(|| format!("Failed to send directory {}", root.display()))?;
+    Ok(manifest)
+}
+
+/// Joins components of the relative path with the manifest separator.
+fn manifest_path(relative: &Path) -> anyhow::Result<String> {
+    let components = relative
+        .components()
+        .map(|component| {
+            component
+                .as_os_str()
+                .to_str()
+                .ok_or_else(|| anyhow!("Path {} is not valid UTF-8", relative.display()))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    Ok(components.join(&PATH_SEPARATOR.to_string()))
+}
@@ -0,0 +1,256 @@
+//! Maps transfer stream of an entity to its files. File entity is a single member,
+//! members of directory entity follow in manifest order.
+
+use std::{
+    io::SeekFrom,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use drophub::manifest::{Manifest, PATH_SEPARATOR};
+use tokio::{
+    fs::{File, OpenOptions},
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+};
+
+/// File of the entity with byte range of its content in the transfer stream.
+#[derive(Debug, Clone)]
+pub struct Member {
+    pub path: PathBuf,
+    pub range: Range<u64>,
+}
+
+pub fn file_members(path: PathBuf, size: u64) -> Vec<Member> {
+    vec![Member {
+        path,
+        range: 0..size,
+    }]
+}
+
+/// Returns files of the directory saved to `root`. The manifest must be validated,
+/// so every path stays inside the root.
+pub fn directory_members(root: &Path, manifest: &Manifest) -> Vec<Member> {
+    manifest
+        .ranges()
+        .map(|(entry, range)| Member {
+            path: entry
+                .path
+                .split(PATH_SEPARATOR)
+                .fold(root.to_path_buf(), |path, component| path.join(component)),
+            range,
+        })
+        .collect()
+}
+
+/// Reads byte ranges of the stream from the member files.
+pub struct StreamReader {
+    members: Vec<Member>,
+    open: Option<(usize, File)>,
+}
+
+impl StreamReader {
+    pub fn new(members: Vec<Member>) -> Self {
+        Self {
+            members,
+            open: None,
+        }
+    }
+
+    /// Total size of the stream.
+    pub fn size(&self) -> u64 {
+        self.members.last().map_or(0, |member| member.range.end)
+    }
+
+    pub async fn read_range(&mut self, range: Range<u64>) -> anyhow::Result<Vec<u8>> {
+        let mut buf = vec![0; (range.end - range.start) as usize];
+        let mut filled = 0;
+        let idxs: Vec<_> = overlapping(&self.members, &range).collect();
+        for idx in idxs {
+            let member_range = self.members[idx].range.clone();
+            let start = range.start.max(member_range.start);
+            let len = (range.end.min(member_range.end) - start) as usize;
+
+            let file = self.file(idx).await?;
+            file.seek(SeekFrom::Start(start - member_range.start))
+                .await?;
+            file.read_exact(&mut buf[filled..filled + len]).await?;
+            filled += len;
+        }
+
+        Ok(buf)
+    }
+
+    async fn file(&mut self, idx: usize) -> anyhow::Result<&mut File> {
+        if !matches!(&self.open, Some((open_idx, _)) if *open_idx == idx) {
+            let file = File::open(&self.members[idx].path).await?;
+            self.open = Some((idx, file));
+        }
+        Ok(&mut self.open.as_mut().expect("file is open").1)
+    }
+}
+
+/// Writes the received stream to the member files, creating their directories.
+pub struct StreamWriter {
+    members: Vec<Member>,
+    /// Members whose files are created by this transfer.
+    created: Vec<bool>,
+    open: Option<(usize, File)>,
+}
+
+impl StreamWriter {
+    pub fn new(members: Vec<Member>) -> Self {
+        Self {
+            created: vec![false; members.len()],
+            members,
+            open: None,
+        }
+    }
+
+    /// Writes data received at the offset. Bytes requested again after resume are
+    /// overwritten in place.
+    pub async fn write_at(&mut self, offset: u64, data: &[u8]) -> anyhow::Result<()> {
+        let range = offset..offset + data.len() as u64;
+        let mut written = 0;
+        let idxs: Vec<_> = overlapping(&self.members, &range).collect();
+        for idx in idxs {
+            let member_range = self.members[idx].range.clone();
+            let start = range.start.max(member_range.start);
+            let len = (range.end.min(member_range.end) - start) as usize;
+
+            let file = self.file(idx).await?;
+            file.seek(SeekFrom::Start(start - member_range.start))
+                .await?;
+            file.write_all(&data[written..written + len]).await?;
+            written += len;
+        }
+
+        Ok(())
+    }
+
+    pub async fn flush(&mut self) -> anyhow::Result<()> {
+        if let Some((_, file)) = &mut self.open {
+            file.flush().await?;
+        }
+        Ok(())
+    }
+
+    /// Flushes written data and creates empty members, no chunk is sent for them.
+    pub async fn finish(mut self) -> anyhow::Result<()> {
+        self.flush().await?;
+        for idx in 0..self.members.len() {
+            if !self.created[idx] {
+                self.file(idx).await?;
+            }
+        }
+        self.flush().await
+    }
+
+    async fn file(&mut self, idx: usize) -> anyhow::Result<&mut File> {
+        if !matches!(&self.open, Some((open_idx, _)) if *open_idx == idx) {
+            self.flush().await?;
+            let path = &self.members[idx].path;
+            if let Some(parent) = path.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+
+            // Existing file is truncated once, then the transfer may switch back to it
+            let file = OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(!self.created[idx])
+                .open(path)
+                .await?;
+            self.created[idx] = true;
+            self.open = Some((idx, file));
+        }
+        Ok(&mut self.open.as_mut().expect("file is open").1)
+    }
+}
+
+/// Returns indexes of non-empty members overlapping the range.
+fn overlapping<'a>(
+    members: &'a [Member],
+    range: &'a Range<u64>,
+) -> impl Iterator<Item = usize> + 'a {
+    members
+        .iter()
+        .enumerate()
+        .filter(move |(_, member)| {
+            !member.range.is_empty()
+                && member.range.start < range.end
+                && range.start < member.range.end
+        })
+        .map(|(idx, _)| idx)
+}
+
+#[cfg(test)]
+mod tests {
+    use drophub::manifest::ManifestEntry;
+
+    use super::*;
+
+    fn manifest(entries: &[(&str, usize)]) -> Manifest {
+        Manifest {
+            entries: entries
+                .iter()
+                .map(|(path, size)| ManifestEntry {
+                    path: path.to_string(),
+                    size: *size,
+                    digest: None,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn members_of_directory() {
+        let root = Path::new("out");
+        let members = directory_members(root, &manifest(&[("a", 3), ("sub/b", 0), ("sub/c", 2)]));
+
+        let paths: Vec<_> = members.iter().map(|member| member.path.clone()).collect();
+        assert_eq!(
+            paths,
+            [
+                root.join("a"),
+                root.join("sub").join("b"),
+                root.join("sub").join("c"),
+            ]
+        );
+        let ranges: Vec<_> = members.iter().map(|member| member.range.clone()).collect();
+        assert_eq!(ranges, [0..3, 3..3, 3..5]);
+    }
+
+    #[tokio::test]
+    async fn write_and_read_across_members() {
+        let root = tempfile::tempdir().unwrap();
+        let members = directory_members(
+            root.path(),
+            &manifest(&[("a", 3), ("sub/b", 0), ("sub/c", 2)]),
+        );
+
+        let mut writer = StreamWriter::new(members.clone());
+        writer.write_at(2, b"cde").await.unwrap();
+        writer.write_at(0, b"ab").await.unwrap();
+        writer.finish().await.unwrap();
+
+        assert_eq!(
+            tokio::fs::read(root.path().join("a")).await.unwrap(),
+            b"abc"
+        );
+        assert!(tokio::fs::read(root.path().join("sub").join("b"))
+            .await
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            tokio::fs::read(root.path().join("sub").join("c"))
+                .await
+                .unwrap(),
+            b"de"
+        );
+
+        let mut reader = StreamReader::new(members);
+        assert_eq!(reader.size(), 5);
+        assert_eq!(reader.read_range(1..4).await.unwrap(), b"bcd");
+        assert_eq!(reader.read_range(0..5).await.unwrap(), b"abcde");
+    }
+}
@@ -1,5 +1,6 @@
 use std::{pin::pin, rc::Rc, time::Duration};
 
+use drophub::{PeerEvent, RpcClient};
 use futures::{FutureExt, StreamExt};
 use yew::{
     platform::{
@@ -16,10 +17,14 @@ use crate::{
     components::{Footer, FullScreenLoading, FullScreenNotify, Header, NotifyContainer},
     config::Config,
     error::{Error, ShareError},
-    hooks::{use_rpc_storage, RpcStorage},
+    hooks::{client_hello, use_rpc_storage, ResumedSession, RpcStorage, SessionStorage},
     routes::{switch, Route},
 };
 
+/// Delay before the first reconnection attempt, doubled after every failed one.
+const RECONNECT_MIN_DELAY: Duration = Duration::from_millis(500);
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(30);
+
 #[function_component(App)]
 pub fn app() -> Html {
     let (rpc, rpc_dispatch) = use_rpc_storage();
@@ -72,27 +77,90 @@ pub fn app() -> Html {
 
 async fn connect_to_server(rpc_dispatch: Dispatch<RpcStorage>) -> Result<(), ShareError> {
     let cfg = Config::from_env()?;
+    let rpc_client = Rc::new(connect(&cfg).await?);
+    rpc_dispatch.reduce_mut(|s| s.rpc_client = Some(Rc::clone(&rpc_client)));
+    spawn_local(reconnect_on_disconnect(cfg, rpc_dispatch, rpc_client));
+
+    Ok(())
+}
+
+/// Replaces RPC client when the connection to API server is lost. Client is removed from
+/// the storage until the connection is restored, then the session of the peer is resumed.
+async fn reconnect_on_disconnect(
+    cfg: Config,
+    rpc_dispatch: Dispatch<RpcStorage>,
+    mut rpc_client: Rc<jsonrpsee::core::client::Client>,
+) {
+    loop {
+        rpc_client.on_disconnect().await;
+        rpc_dispatch.reduce_mut(|s| s.rpc_client = None);
+        tracing::warn!("Disconnected from API server, reconnecting");
+
+        let mut delay = RECONNECT_MIN_DELAY;
+        rpc_client = loop {
+            sleep(delay).await;
+            match connect(&cfg).await {
+                Ok(rpc_client) => break Rc::new(rpc_client),
+                Err(err) => tracing::warn!(?err, ?delay, "Failed to reconnect to API server"),
+            }
+            delay = (delay * 2).min(RECONNECT_MAX_DELAY);
+        };
+
+        tracing::info!("Reconnected to API server");
+        rpc_dispatch.reduce_mut(|s| s.rpc_client = Some(Rc::clone(&rpc_client)));
+        resume_session(&rpc_client).await;
+    }
+}
+
+/// Re-attaches events of the peer to the new connection and hands them to the room page.
+/// Nothing is resumed if the peer isn't on the room page.
+async fn resume_session(rpc_client: &jsonrpsee::core::client::Client) {
+    let session = Dispatch::<SessionStorage>::new().get();
+    let (Some(token), Some(on_resume)) = (session.token.clone(), session.on_resume.clone()) else {
+        return;
+    };
+
+    let resumed = match rpc_client
+        .resume_session(Some(client_hello()), token)
+        .await
+        .map_err(Error::from)
+    {
+        Ok(mut events) => match events.next().await {
+            Some(Ok(PeerEvent::Init { token, .. })) => ResumedSession::Resumed { token, events },
+            Some(Ok(event)) => ResumedSession::Failed(Error::ReceivedUnexpectedResponse {
+                act: format!("{event:?}"),
+                exp: "Init".to_owned(),
+            }),
+            Some(Err(err)) => ResumedSession::Failed(Error::from(err)),
+            None => ResumedSession::Failed(Error::Other(anyhow::anyhow!(
+                "Resumed session is closed by the server"
+            ))),
+        },
+        Err(Error::Server(drophub::Error::SessionNotFound { .. })) => ResumedSession::NotFound,
+        Err(err) => ResumedSession::Failed(err),
+    };
+
+    tracing::info!(?resumed, "Session resumption finished");
+    let _ = on_resume.unbounded_send(resumed);
+}
+
+async fn connect(cfg: &Config) -> Result<jsonrpsee::core::client::Client, Error> {
     let rpc_client: jsonrpsee::core::client::Client =
         jsonrpsee::wasm_client::WasmClientBuilder::default()
-            .build(cfg.api_server_url)
-            .await
-            .map_err(Error::from)?;
+            .build(&cfg.api_server_url)
+            .await?;
 
     let mut connect_timeout = pin!(sleep(cfg.init_timeout).fuse());
     let mut interval = pin!(interval(Duration::from_millis(250)).fuse());
     loop {
         futures::select_biased! {
-            _ = rpc_client.on_disconnect().fuse() => return Err(Error::Other(anyhow::anyhow!("Disconnect from API server")).into()),
-            _ = &mut connect_timeout => return Err(Error::Other(anyhow::anyhow!("Connection to API server timed out")).into()),
+            _ = rpc_client.on_disconnect().fuse() => return Err(Error::Other(anyhow::anyhow!("Disconnect from API server"))),
+            _ = &mut connect_timeout => return Err(Error::Other(anyhow::anyhow!("Connection to API server timed out"))),
             _ = interval.next() => {},
         }
 
         if rpc_client.is_connected() {
-            break;
+            return Ok(rpc_client);
         }
     }
-
-    rpc_dispatch.reduce_mut(|s| s.rpc_client = Some(Rc::new(rpc_client)));
-
-    Ok(())
 }
@@ -1,12 +1,12 @@
 use std::ops::Deref;
 
-use drophub::{InvitePassword, RoomId};
+use drophub::{passphrase, InvitePassphrase};
 use web_sys::{HtmlFormElement, HtmlInputElement};
 use yew::prelude::*;
 use yew_router::hooks::use_navigator;
 
 use crate::{
-    hooks::{use_form_validation, use_notify},
+    hooks::{use_form_validation, use_notify, NotifyProps},
     routes::{
         room::query::{ActionConnect, Query},
         Route,
@@ -16,8 +16,7 @@ use crate::{
 
 #[derive(Debug, Default, Clone, Eq, PartialEq)]
 struct State {
-    room_id: Option<RoomId>,
-    invite_password: Option<InvitePassword>,
+    invite_passphrase: Option<InvitePassphrase>,
 }
 
 #[function_component(ConnectRoomForm)]
@@ -28,28 +27,7 @@ pub fn connect_room_form() -> Html {
     let state_handle = use_state(State::default);
     let form_node_ref = use_form_validation();
 
-    let room_id_onchange = Callback::from({
-        let state_handle = state_handle.clone();
-        let notify_manager = notify_manager.clone();
-        move |event: Event| {
-            let value = event
-                .target_dyn_into::<HtmlInputElement>()
-                .expect_notify(
-                    &notify_manager,
-                    "Failed to cast 'Event' to 'HtmlInputElement'",
-                )
-                .value();
-
-            let mut state = state_handle.deref().clone();
-            let value_int = value
-                .parse::<RoomId>()
-                .expect_notify(&notify_manager, "Failed to parse room id");
-            state.room_id = Some(value_int);
-            state_handle.set(state);
-        }
-    });
-
-    let invite_password_onchange = Callback::from({
+    let invite_passphrase_onchange = Callback::from({
         let state_handle = state_handle.clone();
         let notify_manager = notify_manager.clone();
         move |event: Event| {
@@ -59,7 +37,7 @@ pub fn connect_room_form() -> Html {
                 .value();
 
             let mut state = state_handle.deref().clone();
-            state.invite_password = Some(value);
+            state.invite_passphrase = Some(passphrase::normalize(&value));
             state_handle.set(state);
         }
     });
@@ -78,18 +56,20 @@ pub fn connect_room_form() -> Html {
                 .expect_notify(&notify_manager, "Failed to cast to 'HtmlFormElement'");
 
             if elem.check_validity() {
+                let invite_passphrase = state_handle
+                    .invite_passphrase
+                    .clone()
+                    .expect_notify(&notify_manager, "Invite passphrase is missing");
+                // Mistyped passphrase doesn't count as a failed attempt on the server
+                if !passphrase::verify(&invite_passphrase) {
+                    notify_manager.show_notify(NotifyProps::error("Invite passphrase is mistyped"));
+                    return;
+                }
+
                 navigator
                     .push_with_query(
                         &Route::Room,
-                        &Query::Connect(ActionConnect {
-                            room_id: state_handle
-                                .room_id
-                                .expect_notify(&notify_manager, "Room id is missing"),
-                            invite_password: state_handle
-                                .invite_password
-                                .clone()
-                                .expect_notify(&notify_manager, "Invite password is missing"),
-                        }),
+                        &Query::Connect(ActionConnect { invite_passphrase }),
                     )
                     .unwrap_notify(&notify_manager);
             }
@@ -108,28 +88,15 @@ pub fn connect_room_form() -> Html {
             <div class="form-floating">
                 <input
                     class="form-control"
-                    id="roomIdInput"
-                    type="number"
-                    placeholder="123456"
-                    required=true
-                    onchange={room_id_onchange}
-                    value={state_handle.room_id.map(|v| v.to_string())}
-                />
-                <label for="roomIdInput">{ "Room ID" }</label>
-                <div class="invalid-feedback">{ "Please provide valid room ID." }</div>
-            </div>
-            <div class="form-floating">
-                <input
-                    class="form-control"
-                    id="invitePasswordInput"
-                    type="password"
-                    placeholder="qwerty123456"
+                    id="invitePassphraseInput"
+                    type="text"
+                    placeholder="correct-horse-battery"
                     required=true
-                    onchange={invite_password_onchange}
-                    value={state_handle.invite_password.clone()}
+                    onchange={invite_passphrase_onchange}
+                    value={state_handle.invite_passphrase.clone()}
                 />
-                <label for="invitePasswordInput">{ "Invite password" }</label>
-                <div class="invalid-feedback">{ "Please provide valid invite password." }</div>
+                <label for="invitePassphraseInput">{ "Invite passphrase" }</label>
+                <div class="invalid-feedback">{ "Please provide valid invite passphrase." }</div>
             </div>
             <button
                 type="submit"
@@ -20,7 +20,6 @@ const MAX_CAPACITY: usize = 10;
 #[derive(Debug, Clone, Eq, PartialEq)]
 struct State {
     capacity: usize,
-    encryption: bool,
     is_loading: bool,
 }
 
@@ -28,7 +27,6 @@ impl Default for State {
     fn default() -> Self {
         Self {
             capacity: MIN_CAPACITY,
-            encryption: false,
             is_loading: false,
         }
     }
@@ -42,7 +40,6 @@ pub fn create_room_form() -> Html {
     let state_handle = use_state(State::default);
 
     let form_node_ref = use_node_ref();
-    let enc_node_ref = use_node_ref();
 
     let cap_oninput = Callback::from({
         let state_handle = state_handle.clone();
@@ -69,20 +66,6 @@ pub fn create_room_form() -> Html {
             state_handle.set(state);
         }
     });
-    let enc_onclick = Callback::from({
-        let state_handle = state_handle.clone();
-        let enc_node_ref = enc_node_ref.clone();
-        let notify_manager = notify_manager.clone();
-        move |_| {
-            let input_elem = enc_node_ref.cast::<HtmlInputElement>().expect_notify(
-                &notify_manager,
-                "Failed to cast encryption checkbox to 'HtmlInputElement'",
-            );
-            let mut state = state_handle.deref().clone();
-            state.encryption = input_elem.checked();
-            state_handle.set(state);
-        }
-    });
 
     let form_onsubmit = Callback::from({
         let state_handle = state_handle.clone();
@@ -102,7 +85,6 @@ pub fn create_room_form() -> Html {
                     .push_with_query(
                         &Route::Room,
                         &Query::Create(ActionCreate {
-                            encryption: state_handle.encryption,
                             capacity: state_handle.capacity,
                         }),
                     )
@@ -148,19 +130,6 @@ pub fn create_room_form() -> Html {
                     />
                 </div>
             </div>
-            <div class="form-check form-switch">
-                <input
-                    class="form-check-input"
-                    id="encryptionCheck"
-                    type="checkbox"
-                    role="switch"
-                    disabled=true
-                    checked={state_handle.encryption}
-                    onclick={enc_onclick}
-                    ref={enc_node_ref}
-                />
-                <label class="form-check-label" for="encryptionCheck">{ "Encryption" }</label>
-            </div>
             <button
                 type="submit"
                 class="btn
@@ -18,6 +18,8 @@ pub struct Props {
     pub clients: HashMap<PeerId, ClientRole>,
     pub cur_client: (PeerId, ClientRole),
     pub capacity: usize,
+    pub fingerprints: HashMap<PeerId, String>,
+    pub on_kick: Callback<PeerId>,
 }
 
 #[function_component(ClientList)]
@@ -156,6 +158,8 @@ pub fn client_list(props: &Props) -> Html {
                 loading={props.loading}
                 selected_client={*selected_client_handle}
                 cur_client={props.cur_client}
+                fingerprint={props.fingerprints.get(&selected_client_handle.0).cloned()}
+                on_kick={props.on_kick.clone()}
             />
         </>
     }
@@ -1,18 +1,35 @@
 use drophub::{ClientRole, PeerId};
 use yew::prelude::*;
 
+use crate::components::Placeholder;
+
 #[derive(Debug, Clone, PartialEq, Properties)]
 pub struct Props {
     #[prop_or_default]
     pub loading: bool,
     pub selected_client: (PeerId, ClientRole),
     pub cur_client: (PeerId, ClientRole),
+    /// Fingerprint of the key exchanged with the selected client.
+    pub fingerprint: Option<String>,
+    pub on_kick: Callback<PeerId>,
 }
 
 #[function_component(ClientModal)]
 pub fn client_modal(props: &Props) -> Html {
     let is_kick_enabled =
         props.cur_client.1 == ClientRole::Host && props.cur_client.0 != props.selected_client.0;
+    // Keys are exchanged only with peers the entities are transferred with
+    let fingerprint = match &props.fingerprint {
+        Some(fingerprint) => fingerprint.clone(),
+        None if props.selected_client.0 == props.cur_client.0 => "-".to_owned(),
+        None => "Not exchanged yet".to_owned(),
+    };
+
+    let kick_onclick = {
+        let on_kick = props.on_kick.clone();
+        let peer_id = props.selected_client.0;
+        Callback::from(move |_| on_kick.emit(peer_id))
+    };
 
     html! {
         <div
@@ -36,7 +53,38 @@ pub fn client_modal(props: &Props) -> Html {
                         <button type="button" class="btn-close" data-bs-dismiss="modal" aria-label="Close"></button>
                     </div>
                     <div class="modal-body">
-                        {"TODO: id, role, media list, etc."}
+                        <table class="table table-bordered">
+                            <tbody>
+                                <tr>
+                                    <th scope="row">{"Client ID"}</th>
+                                    <td>
+                                        <Placeholder<PeerId>
+                                            enabled={props.loading}
+                                            content={props.selected_client.0}
+                                        />
+                                    </td>
+                                </tr>
+                                <tr>
+                                    <th scope="row">{"Role"}</th>
+                                    <td>
+                                        <Placeholder<String>
+                                            enabled={props.loading}
+                                            content={format!("{:?}", props.selected_client.1)}
+                                        />
+                                    </td>
+                                </tr>
+                                <tr>
+                                    <th scope="row">{"Key fingerprint"}</th>
+                                    <td class="font-monospace">
+                                        {fingerprint}
+                                    </td>
+                                </tr>
+                            </tbody>
+                        </table>
+                        <p class="text-body-secondary mb-0">
+                            {"Compare the key fingerprint with the one shown to the client \
+                              to make sure the server doesn't intercept transfers."}
+                        </p>
                     </div>
                     <div class="modal-footer">
                         <button
@@ -45,7 +93,7 @@ pub fn client_modal(props: &Props) -> Html {
                             type="button"
                             data-bs-dismiss="modal"
                             disabled={!is_kick_enabled}
-                            // TODO: add onclick event
+                            onclick={kick_onclick}
                         >
                             {"Kick"}
                         </button>
@@ -1,6 +1,6 @@
 use std::ops::Deref;
 
-use drophub::{InvitePassword, RoomId};
+use drophub::InvitePassphrase;
 use web_sys::Element;
 use yew::prelude::*;
 
@@ -14,10 +14,12 @@ use crate::{
 pub struct Props {
     #[prop_or_default]
     pub loading: bool,
-    pub room_id: RoomId,
-    pub invites: Vec<InvitePassword>,
+    pub invites: Vec<InvitePassphrase>,
     pub capacity: usize,
+    pub max_invites: usize,
     pub clients_count: usize,
+    pub on_create: Callback<()>,
+    pub on_revoke: Callback<InvitePassphrase>,
 }
 
 #[function_component(InviteList)]
@@ -25,6 +27,11 @@ pub fn invite_list(props: &Props) -> Html {
     let notify_manager = use_notify();
 
     let selected_invite_handle = use_state(String::default);
+    // Every outstanding invite can be redeemed, so they never exceed free places of the room
+    let invites_limit = props
+        .capacity
+        .saturating_sub(props.clients_count)
+        .min(props.max_invites);
 
     let icon_node_ref = use_node_ref();
     let btn_node_ref = use_node_ref();
@@ -54,11 +61,11 @@ pub fn invite_list(props: &Props) -> Html {
     let invites = props
         .invites
         .iter()
-        .map(|invite_password| {
+        .map(|invite_passphrase| {
             let onclick = Callback::from({
                 let selected_invite_handle = selected_invite_handle.clone();
-                let invite_password = invite_password.clone();
-                move |_| selected_invite_handle.set(invite_password.clone())
+                let invite_passphrase = invite_passphrase.clone();
+                move |_| selected_invite_handle.set(invite_passphrase.clone())
             });
 
             html! {
@@ -79,9 +86,9 @@ pub fn invite_list(props: &Props) -> Html {
                                  ms-2
                                  d-inline-block"
                     >
-                        <Placeholder<InvitePassword>
+                        <Placeholder<InvitePassphrase>
                             enabled={props.loading}
-                            content={invite_password.clone()}
+                            content={invite_passphrase.clone()}
                         />
                     </span>
                 </button>
@@ -89,8 +96,8 @@ pub fn invite_list(props: &Props) -> Html {
         })
         .chain(
             std::iter::repeat_with(|| {
-                let rest_invites_count = props.capacity - props.clients_count - props.invites.len();
-                let no_more_invites = rest_invites_count == 0;
+                let no_more_invites = props.invites.len() >= invites_limit;
+                let onclick = props.on_create.reform(|_| ());
                 html! {
                     <button
                         class="btn
@@ -98,6 +105,7 @@ pub fn invite_list(props: &Props) -> Html {
                                text-center"
                         type="button"
                         disabled={no_more_invites}
+                        {onclick}
                     >
                         <i class="bi
                                   bi-plus-lg
@@ -137,7 +145,7 @@ pub fn invite_list(props: &Props) -> Html {
                     {"Invites "}
                     <Placeholder<String>
                         enabled={props.loading}
-                        content={format!("{} / {}", props.invites.len(), props.capacity - props.clients_count)}
+                        content={format!("{} / {}", props.invites.len(), invites_limit)}
                     />
                     <i
                         class="bi
@@ -161,8 +169,8 @@ pub fn invite_list(props: &Props) -> Html {
             </div>
             <InviteModal
                 loading={props.loading}
-                room_id={props.room_id}
-                invite_password={selected_invite_handle.deref().clone()}
+                invite_passphrase={selected_invite_handle.deref().clone()}
+                on_revoke={props.on_revoke.clone()}
             />
         </>
     }
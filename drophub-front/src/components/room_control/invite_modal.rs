@@ -1,4 +1,4 @@
-use drophub::{InvitePassword, RoomId};
+use drophub::InvitePassphrase;
 use yew::prelude::*;
 
 use crate::{
@@ -11,13 +11,8 @@ use crate::{
 pub struct Props {
     #[prop_or_default]
     pub loading: bool,
-    pub room_id: RoomId,
-    pub invite_password: InvitePassword,
-}
-
-#[derive(Debug, Clone, PartialEq)]
-struct State {
-    invite_link: String,
+    pub invite_passphrase: InvitePassphrase,
+    pub on_revoke: Callback<InvitePassphrase>,
 }
 
 #[function_component(InviteModal)]
@@ -25,24 +20,21 @@ pub fn invite_modal(props: &Props) -> Html {
     let notify_manager = use_notify();
 
     let display_mode_handle = use_display_mode();
-    let state_handle = use_state_eq({
-        let notify_manager = notify_manager.clone();
-        let props = props.clone();
-        move || {
-            let room_id = props.room_id;
-            let invite_password = props.invite_password;
-            let invite_link = {
-                let win = web_sys::window().expect_notify(&notify_manager, "Failed to get Window");
-                let base_url = win
-                    .location()
-                    .origin()
-                    .expect_notify(&notify_manager, "Failed to get origin");
-                format_invite_link(&base_url, room_id, &invite_password)
-            };
+    // Selected invite changes while the modal is mounted, so the link is built on render
+    let invite_link = {
+        let win = web_sys::window().expect_notify(&notify_manager, "Failed to get Window");
+        let base_url = win
+            .location()
+            .origin()
+            .expect_notify(&notify_manager, "Failed to get origin");
+        format_invite_link(&base_url, &props.invite_passphrase)
+    };
 
-            State { invite_link }
-        }
-    });
+    let revoke_onclick = {
+        let on_revoke = props.on_revoke.clone();
+        let invite_passphrase = props.invite_passphrase.clone();
+        Callback::from(move |_| on_revoke.emit(invite_passphrase.clone()))
+    };
 
     let qrcode = {
         let (color, bg_color) = match *display_mode_handle {
@@ -51,7 +43,7 @@ pub fn invite_modal(props: &Props) -> Html {
         };
         html! {
             <QrCode<String>
-                value={state_handle.invite_link.clone()}
+                value={invite_link.clone()}
                 size={300}
                 {color}
                 {bg_color}
@@ -93,23 +85,11 @@ pub fn invite_modal(props: &Props) -> Html {
                             </div>
                             <div>
                                 <h6>{"2. Follow the link"}</h6>
-                                <CopyInput content={state_handle.invite_link.clone()} />
+                                <CopyInput content={invite_link} />
                             </div>
                             <div>
-                                <h6>{"3. Enter credentials manually"}</h6>
-                                <div class="d-flex
-                                            flex-row
-                                            gap-3"
-                                >
-                                    <div>
-                                        <span>{"Room ID"}</span>
-                                        <CopyInput content={props.room_id.to_string()} />
-                                    </div>
-                                    <div>
-                                        <span>{"Invite password"}</span>
-                                        <CopyInput content={props.invite_password.clone()} />
-                                    </div>
-                                </div>
+                                <h6>{"3. Enter passphrase manually"}</h6>
+                                <CopyInput content={props.invite_passphrase.clone()} />
                             </div>
                         </div>
                     </div>
@@ -119,7 +99,7 @@ pub fn invite_modal(props: &Props) -> Html {
                                    btn-danger"
                             type="button"
                             data-bs-dismiss="modal"
-                            // TODO: add onclick event
+                            onclick={revoke_onclick}
                         >
                             {"Revoke"}
                         </button>
@@ -130,6 +110,6 @@ pub fn invite_modal(props: &Props) -> Html {
     }
 }
 
-fn format_invite_link(base_url: &str, room_id: RoomId, invite_password: &str) -> String {
-    format!("{base_url}/room?action=connect&room_id={room_id}&invite_password={invite_password}")
+fn format_invite_link(base_url: &str, invite_passphrase: &str) -> String {
+    format!("{base_url}/room?action=connect&invite_passphrase={invite_passphrase}")
 }
@@ -8,14 +8,14 @@ mod room_info_modal;
 
 use std::collections::HashMap;
 
-use drophub::{ClientRole, InvitePassword, PeerId, RoomId, RoomOptions};
+use drophub::{ClientRole, InvitePassphrase, PeerId, RoomId, RoomOptions};
 use web_sys::Element;
 use yew::prelude::*;
 
 use self::{client_list::ClientList, header::Header, invite_list::InviteList, room_info::RoomInfo};
 use crate::{components::Placeholder, hooks::use_notify, unwrap_notify_ext::UnwrapNotifyExt};
 
-#[derive(Debug, Clone, Eq, PartialEq, Properties)]
+#[derive(Debug, Clone, PartialEq, Properties)]
 pub struct Props {
     #[prop_or_default]
     pub loading: bool,
@@ -24,7 +24,12 @@ pub struct Props {
     pub clients: HashMap<PeerId, ClientRole>,
     pub cur_client: (PeerId, ClientRole),
     pub host: PeerId,
-    pub invites: Vec<InvitePassword>,
+    pub invites: Vec<InvitePassphrase>,
+    /// Fingerprints of keys exchanged with other peers.
+    pub fingerprints: HashMap<PeerId, String>,
+    pub on_kick: Callback<PeerId>,
+    pub on_create_invite: Callback<()>,
+    pub on_revoke_invite: Callback<InvitePassphrase>,
 }
 
 #[function_component(RoomControl)]
@@ -90,13 +95,17 @@ pub fn room_control(props: &Props) -> Html {
                     clients={props.clients.clone()}
                     cur_client={props.cur_client.clone()}
                     capacity={props.room_opts.capacity}
+                    fingerprints={props.fingerprints.clone()}
+                    on_kick={props.on_kick.clone()}
                 />
                 <InviteList
                     loading={props.loading}
-                    room_id={props.room_id}
                     invites={props.invites.clone()}
                     capacity={props.room_opts.capacity}
+                    max_invites={props.room_opts.max_invites}
                     clients_count={props.clients.len()}
+                    on_create={props.on_create_invite.clone()}
+                    on_revoke={props.on_revoke_invite.clone()}
                 />
             </div>
         </div>
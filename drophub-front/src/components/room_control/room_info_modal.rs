@@ -65,15 +65,6 @@ pub fn room_info_modal(props: &Props) -> Html {
                                         />
                                     </td>
                                 </tr>
-                                <tr>
-                                    <th scope="row">{"Encryption"}</th>
-                                    <td>
-                                        <Placeholder<bool>
-                                            enabled={props.loading}
-                                            content={props.room_opts.encryption}
-                                        />
-                                    </td>
-                                </tr>
                             </tbody>
                         </table>
                     </div>
@@ -1,16 +1,68 @@
+use gloo::file::{File, FileList};
+use web_sys::HtmlInputElement;
 use yew::prelude::*;
 
-#[derive(Debug, Clone, Eq, PartialEq, Properties)]
-pub struct Props {}
+use crate::{hooks::use_notify, unwrap_notify_ext::UnwrapNotifyExt};
+
+#[derive(Debug, Clone, PartialEq, Properties)]
+pub struct Props {
+    #[prop_or_default]
+    pub loading: bool,
+    pub on_announce: Callback<Vec<File>>,
+}
 
 #[function_component(EntityAnnounce)]
-pub fn entity_announce(_props: &Props) -> Html {
+pub fn entity_announce(props: &Props) -> Html {
+    let notify_manager = use_notify();
+    let input_node_ref = use_node_ref();
+
+    // Styled button opens dialog of the hidden file input
+    let btn_onclick = Callback::from({
+        let notify_manager = notify_manager.clone();
+        let input_node_ref = input_node_ref.clone();
+        move |_| {
+            input_node_ref
+                .cast::<HtmlInputElement>()
+                .expect_notify(
+                    &notify_manager,
+                    "Failed to cast 'NodeRef' to 'HtmlInputElement'",
+                )
+                .click();
+        }
+    });
+
+    let input_onchange = Callback::from({
+        let on_announce = props.on_announce.clone();
+        move |event: Event| {
+            let input = event
+                .target_dyn_into::<HtmlInputElement>()
+                .expect_notify(&notify_manager, "Failed to cast to 'HtmlInputElement'");
+            let files = input
+                .files()
+                .map(|files| FileList::from(files).to_vec())
+                .unwrap_or_default();
+            // Same files can be selected again
+            input.set_value("");
+
+            if !files.is_empty() {
+                on_announce.emit(files);
+            }
+        }
+    });
+
     html! {
         <div class="col
                     d-flex
                     flex-column
                     align-items-center"
         >
+            <input
+                class="d-none"
+                type="file"
+                multiple=true
+                onchange={input_onchange}
+                ref={input_node_ref}
+            />
             <button
                 class="btn
                        btn-shade-10
@@ -23,6 +75,9 @@ pub fn entity_announce(_props: &Props) -> Html {
                 style="height: 100px;
                        width: 100px;"
                 type="button"
+                title="Share files"
+                disabled={props.loading}
+                onclick={btn_onclick}
             >
                 <i class="bi
                           bi-cloud-arrow-up"
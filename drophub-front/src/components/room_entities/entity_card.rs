@@ -1,25 +1,95 @@
-use drophub::{Entity, EntityId, EntityKind};
+use std::time::Duration;
+
+use drophub::{Entity, EntityId, EntityKind, PeerId, TransferProgress, TransferState};
 use yew::prelude::*;
 
-use crate::components::Placeholder;
+use crate::components::{CopyInput, Placeholder};
 
-#[derive(Debug, Clone, Eq, PartialEq, Properties)]
+#[derive(Debug, Clone, PartialEq, Properties)]
 pub struct Props {
     #[prop_or_default]
     pub loading: bool,
     pub id: EntityId,
     pub meta: Entity,
+    /// Entity is announced by the local peer.
+    pub owned: bool,
+    /// Transfer of the entity to the local peer.
+    pub transfer: Option<TransferState>,
+    /// Local peer.
+    pub peer_id: PeerId,
+    /// Progress of running transfers by receiver.
+    #[prop_or_default]
+    pub progress: Vec<(PeerId, TransferProgress)>,
+    pub on_request: Callback<EntityId>,
 }
 
 fn icon(kind: EntityKind) -> Html {
     match kind {
         EntityKind::File => html! { <i class="bi bi-file-earmark"></i> },
         EntityKind::Text => html! { <i class="bi bi-text-left"></i> },
+        EntityKind::Directory => html! { <i class="bi bi-folder"></i> },
     }
 }
 
 #[function_component(EntityCard)]
 pub fn entity_card(props: &Props) -> Html {
+    // Text is delivered with the room, so it can be viewed and copied right away
+    let text = match (&props.meta.kind, &props.meta.content) {
+        (EntityKind::Text, Some(content)) => html! {
+            <div style="max-width: 200px;">
+                <CopyInput content={content.clone()} />
+            </div>
+        },
+        _ => html! { <></> },
+    };
+
+    // Thumbnail is small enough to be embedded as data URL
+    let preview = match &props.meta.meta.thumbnail {
+        Some(thumbnail) => html! {
+            <img
+                class="rounded"
+                style="max-height: 100px;
+                       max-width: 100px;"
+                src={thumbnail.data_url()}
+                alt={props.meta.name.clone()}
+            />
+        },
+        None => icon(props.meta.kind.clone()),
+    };
+
+    // Text has nothing to transfer, other entities are requested from the owner
+    // unless the previous request is not finished yet
+    let requestable = !props.owned
+        && props.meta.kind != EntityKind::Text
+        && props.transfer.map_or(true, TransferState::is_finished);
+    let onclick = if requestable {
+        let on_request = props.on_request.clone();
+        let id = props.id;
+        Callback::from(move |_: MouseEvent| on_request.emit(id))
+    } else {
+        Callback::noop()
+    };
+
+    let transfer = match props.transfer {
+        Some(state) => html! {
+            <small class="text-body-secondary">{transfer_label(state)}</small>
+        },
+        None => html! { <></> },
+    };
+
+    let progress = props
+        .progress
+        .iter()
+        .map(|(peer_id, progress)| {
+            let receiver = if *peer_id == props.peer_id {
+                "You".to_owned()
+            } else {
+                peer_id.to_string()
+            };
+            progress_bar(&receiver, progress)
+        })
+        .collect::<Html>();
+
     html! {
         <div class="d-flex
                     flex-column
@@ -38,8 +108,10 @@ pub fn entity_card(props: &Props) -> Html {
                 style="height: 100px;
                        width: 100px;"
                 type="button"
+                {onclick}
+                title={props.meta.content.clone().or_else(|| props.meta.meta.mime_type.clone())}
             >
-                {icon(props.meta.kind())}
+                {preview}
             </button>
             <div
                 class="text-truncate"
@@ -47,9 +119,56 @@ pub fn entity_card(props: &Props) -> Html {
             >
                 <Placeholder<String>
                     enabled={props.loading}
-                    content={props.meta.name().to_owned()}
+                    content={props.meta.name.clone()}
                 />
             </div>
+            {transfer}
+            {progress}
+            {text}
         </div>
     }
 }
+
+fn progress_bar(receiver: &str, progress: &TransferProgress) -> Html {
+    let percent = match progress.size {
+        0 => 100,
+        size => progress.transferred.min(size) * 100 / size,
+    };
+    let eta = progress.eta.map_or_else(
+        || "unknown".to_owned(),
+        |eta| humantime::format_duration(Duration::from_secs(eta)).to_string(),
+    );
+    let title = format!(
+        "{receiver}: {}/{} bytes, {} bytes/s, ETA {eta}",
+        progress.transferred, progress.size, progress.throughput
+    );
+
+    html! {
+        <div
+            class="progress
+                   w-100"
+            style="height: 4px;
+                   max-width: 100px;"
+            role="progressbar"
+            aria-valuenow={percent.to_string()}
+            aria-valuemin="0"
+            aria-valuemax="100"
+            title={title}
+        >
+            <div
+                class="progress-bar"
+                style={format!("width: {percent}%;")}
+            ></div>
+        </div>
+    }
+}
+
+fn transfer_label(state: TransferState) -> &'static str {
+    match state {
+        TransferState::Pending => "Requested",
+        TransferState::Transferring => "Receiving",
+        TransferState::Done => "Received",
+        TransferState::Failed => "Failed",
+        TransferState::Declined => "Declined",
+    }
+}
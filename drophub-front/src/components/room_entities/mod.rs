@@ -3,17 +3,25 @@ mod entity_card;
 
 use std::collections::HashMap;
 
-use drophub::{Entity, EntityId};
+use drophub::{Entity, EntityId, PeerId, TransferProgress};
+use gloo::file::File;
 use indexmap::IndexMap;
 use yew::prelude::*;
 
 use crate::components::room_entities::{entity_announce::EntityAnnounce, entity_card::EntityCard};
 
-#[derive(Debug, Clone, Eq, PartialEq, Properties)]
+#[derive(Debug, Clone, PartialEq, Properties)]
 pub struct Props {
     #[prop_or_default]
     pub loading: bool,
+    /// Local peer.
+    pub peer_id: PeerId,
     pub entities: IndexMap<EntityId, Entity>,
+    /// Progress of running transfers by entity and receiver.
+    #[prop_or_default]
+    pub progress: HashMap<(EntityId, PeerId), TransferProgress>,
+    pub on_request_entity: Callback<EntityId>,
+    pub on_announce: Callback<Vec<File>>,
 }
 
 #[function_component(RoomEntities)]
@@ -22,18 +30,34 @@ pub fn room_entities(props: &Props) -> Html {
         .entities
         .iter()
         .map(|(entity_id, entity_meta)| {
+            let mut progress = props
+                .progress
+                .iter()
+                .filter(|((id, _), _)| id == entity_id)
+                .map(|((_, peer_id), progress)| (*peer_id, progress.clone()))
+                .collect::<Vec<_>>();
+            progress.sort_by_key(|(peer_id, _)| *peer_id);
+
             html! {
                 <EntityCard
                     loading={props.loading}
                     id={entity_id}
                     meta={entity_meta.clone()}
+                    owned={entity_meta.owner_id == props.peer_id}
+                    transfer={entity_meta.transfers.get(&props.peer_id).copied()}
+                    peer_id={props.peer_id}
+                    progress={progress}
+                    on_request={props.on_request_entity.clone()}
                 />
             }
         })
         .collect::<Html>();
 
     let upload = html! {
-        <EntityAnnounce />
+        <EntityAnnounce
+            loading={props.loading}
+            on_announce={props.on_announce.clone()}
+        />
     };
 
     html! {
@@ -5,7 +5,11 @@ pub enum Error {
     #[error(transparent)]
     ParseUrl(#[from] url::ParseError),
     #[error(transparent)]
-    Jsonrpsee(#[from] jsonrpsee::core::Error),
+    Server(#[from] drophub::Error),
+    #[error(transparent)]
+    Jsonrpsee(jsonrpsee::core::ClientError),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
     #[error(transparent)]
     HumantimeParse(#[from] humantime::DurationError),
     #[error(transparent)]
@@ -18,4 +22,14 @@ pub enum Error {
     Other(#[from] anyhow::Error),
 }
 
+impl From<jsonrpsee::core::ClientError> for Error {
+    fn from(f: jsonrpsee::core::ClientError) -> Self {
+        // Errors returned by the server methods have meaningful messages
+        match drophub::Error::from_client_error(&f) {
+            Some(err) => Error::Server(err),
+            None => Error::Jsonrpsee(f),
+        }
+    }
+}
+
 pub type ShareError = Rc<Error>;
@@ -1,6 +1,7 @@
 pub mod display_mode;
 pub mod notify;
 pub mod rpc;
+pub mod session;
 pub mod validate;
 
-pub use self::{display_mode::*, notify::*, rpc::*, validate::*};
+pub use self::{display_mode::*, notify::*, rpc::*, session::*, validate::*};
@@ -1,12 +1,10 @@
 use std::rc::Rc;
 
-use drophub::RoomEvent;
-use jsonrpsee::core::client::Subscription;
-use wasm_bindgen::UnwrapThrowExt;
+use drophub::{Capability, ClientHello};
 use yew::prelude::*;
 use yewdux::prelude::*;
 
-use crate::{hooks::use_notify, unwrap_notify_ext::UnwrapNotifyExt};
+use crate::{error::Error, hooks::use_notify, unwrap_notify_ext::UnwrapNotifyExt};
 
 #[hook]
 pub fn use_rpc_storage() -> (Rc<RpcStorage>, Dispatch<RpcStorage>) {
@@ -33,3 +31,22 @@ impl PartialEq for RpcStorage {
         self.rpc_client.is_some() == other.rpc_client.is_some()
     }
 }
+
+/// Returns current RPC client outside of components, e.g. in tasks that outlive renders.
+pub fn rpc_client() -> Result<Rc<jsonrpsee::core::client::Client>, Error> {
+    Dispatch::<RpcStorage>::new()
+        .get()
+        .rpc_client
+        .clone()
+        .ok_or_else(|| Error::Other(anyhow::anyhow!("RPC client is missing")))
+}
+
+/// Hello the app sends when it subscribes to peer events. Relay is requested to fall back
+/// on it when direct connection fails, e.g. with peers using the CLI.
+pub fn client_hello() -> ClientHello {
+    ClientHello::new(vec![
+        Capability::Relay,
+        Capability::Encryption,
+        Capability::Progress,
+    ])
+}
@@ -0,0 +1,39 @@
+use drophub::{PeerEvent, PeerTokenEncoded};
+use futures::channel::mpsc;
+use jsonrpsee::core::client::Subscription;
+use yewdux::prelude::*;
+
+use crate::error::Error;
+
+/// Session of the peer on the room page. It's resumed when the connection to API server
+/// is restored.
+#[derive(Debug, Clone, Default, Store)]
+pub struct SessionStorage {
+    /// Current token of the peer.
+    pub token: Option<PeerTokenEncoded>,
+    /// Receives result of resuming the session, the room page listens to it.
+    pub on_resume: Option<mpsc::UnboundedSender<ResumedSession>>,
+}
+
+impl PartialEq for SessionStorage {
+    fn eq(&self, other: &Self) -> bool {
+        self.token == other.token
+            && match (&self.on_resume, &other.on_resume) {
+                (Some(a), Some(b)) => a.same_receiver(b),
+                (None, None) => true,
+                _ => false,
+            }
+    }
+}
+
+#[derive(Debug)]
+pub enum ResumedSession {
+    /// Subscription is re-attached to the peer. Missed events follow the refreshed token.
+    Resumed {
+        token: PeerTokenEncoded,
+        events: Subscription<PeerEvent>,
+    },
+    /// Grace period is over, the peer must start a new session.
+    NotFound,
+    Failed(Error),
+}
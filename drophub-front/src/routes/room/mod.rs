@@ -1,23 +1,26 @@
+pub mod network;
 pub mod query;
+mod session;
 pub mod state;
+mod transfer;
 
-use std::{collections::HashMap, ops::Deref, rc::Rc, str::FromStr};
+use std::collections::HashMap;
 
-use drophub::{ClientRole, RoomEvent, RoomOptions, RoomRpcClient};
-use jsonrpsee::core::client::Subscription;
-use serde::{Deserialize, Deserializer, Serialize};
-use yew::prelude::*;
-use yew_hooks::use_async;
+use drophub::{EntityId, InvitePassphrase, PeerId, PeerToken, PeerTokenEncoded, RpcClient};
+use futures::future::{AbortHandle, Abortable};
+use gloo::{file::File, timers::callback::Timeout};
+use indexmap::IndexMap;
+use yew::{platform::spawn_local, prelude::*};
 use yew_router::prelude::*;
 
 use crate::{
     components::{RoomControl, RoomEntities},
-    error::{Error, ShareError},
-    hooks::{use_notify, use_rpc, NotifyProps},
+    hooks::{use_notify, use_rpc_storage, NotifyProps},
     routes::{
         room::{
-            query::{ActionConnect, ActionCreate, Query},
-            state::State,
+            query::Query,
+            session::Shared,
+            state::{Action, State},
         },
         Route,
     },
@@ -29,67 +32,209 @@ pub fn room() -> Html {
     let notify_manager = use_notify();
     let location = use_location().expect_notify(&notify_manager, "Failed to get location");
     let navigator = use_navigator().expect_notify(&notify_manager, "Failed to get navigator");
-    let state_handle = use_state(State::default);
+    let state_handle = use_reducer(State::default);
+    let shared = use_mut_ref(Shared::default);
+    let (rpc_storage, _) = use_rpc_storage();
 
-    //let rpc_client = use_rpc();
+    let on_kick = Callback::from({
+        let notify_manager = notify_manager.clone();
+        let state_handle = state_handle.clone();
+        let rpc_storage = rpc_storage.clone();
+        move |peer_id: PeerId| {
+            let Some(rpc_client) = rpc_storage.rpc_client.clone() else {
+                notify_manager.show_notify(NotifyProps::error("RPC client is missing"));
+                return;
+            };
 
-    //let room_handle = use_async(handle_room_update(rpc_client, state_handle.clone()));
-    let room_handle = use_async(async { Ok::<(), ShareError>(()) });
+            let notify_manager = notify_manager.clone();
+            let token = state_handle.client.token.clone();
+            spawn_local(async move {
+                if let Err(err) = rpc_client.kick_peer(token, peer_id).await {
+                    notify_manager
+                        .show_notify(NotifyProps::error(format!("Failed to kick peer: {err:?}")));
+                }
+            });
+        }
+    });
+
+    let on_request_entity = Callback::from({
+        let notify_manager = notify_manager.clone();
+        let state_handle = state_handle.clone();
+        let rpc_storage = rpc_storage.clone();
+        move |entity_id: EntityId| {
+            let Some(rpc_client) = rpc_storage.rpc_client.clone() else {
+                notify_manager.show_notify(NotifyProps::error("RPC client is missing"));
+                return;
+            };
+
+            let notify_manager = notify_manager.clone();
+            let token = state_handle.client.token.clone();
+            spawn_local(async move {
+                if let Err(err) = rpc_client.request_entity(token, entity_id).await {
+                    notify_manager.show_notify(NotifyProps::error(format!(
+                        "Failed to request entity: {err:?}"
+                    )));
+                }
+            });
+        }
+    });
+
+    let on_announce = Callback::from({
+        let notify_manager = notify_manager.clone();
+        let shared = shared.clone();
+        move |files: Vec<File>| {
+            for file in files {
+                let notify_manager = notify_manager.clone();
+                let shared = shared.clone();
+                spawn_local(async move {
+                    if let Err(err) = transfer::announce_file(&shared, file).await {
+                        notify_manager.show_notify(NotifyProps::error(format!(
+                            "Failed to announce file: {err}"
+                        )));
+                    }
+                });
+            }
+        }
+    });
+
+    let on_create_invite = Callback::from({
+        let notify_manager = notify_manager.clone();
+        let state_handle = state_handle.clone();
+        let rpc_storage = rpc_storage.clone();
+        move |()| {
+            let Some(rpc_client) = rpc_storage.rpc_client.clone() else {
+                notify_manager.show_notify(NotifyProps::error("RPC client is missing"));
+                return;
+            };
+
+            let notify_manager = notify_manager.clone();
+            let token = state_handle.client.token.clone();
+            let dispatcher = state_handle.dispatcher();
+            spawn_local(async move {
+                match rpc_client.create_invite(token).await {
+                    Ok(invite) => dispatcher.dispatch(Action::AddInvite(invite.passphrase)),
+                    Err(err) => notify_manager.show_notify(NotifyProps::error(format!(
+                        "Failed to create invite: {err:?}"
+                    ))),
+                }
+            });
+        }
+    });
+
+    let on_revoke_invite = Callback::from({
+        let notify_manager = notify_manager.clone();
+        let state_handle = state_handle.clone();
+        let rpc_storage = rpc_storage.clone();
+        move |invite_passphrase: InvitePassphrase| {
+            let Some(rpc_client) = rpc_storage.rpc_client.clone() else {
+                notify_manager.show_notify(NotifyProps::error("RPC client is missing"));
+                return;
+            };
+
+            let notify_manager = notify_manager.clone();
+            let token = state_handle.client.token.clone();
+            let dispatcher = state_handle.dispatcher();
+            spawn_local(async move {
+                match rpc_client
+                    .revoke_invite(token, invite_passphrase.clone())
+                    .await
+                {
+                    Ok(()) => dispatcher.dispatch(Action::RemoveInvite(invite_passphrase)),
+                    Err(err) => notify_manager.show_notify(NotifyProps::error(format!(
+                        "Failed to revoke invite: {err:?}"
+                    ))),
+                }
+            });
+        }
+    });
 
+    // Refreshed before it expires, the refreshed token revokes the previous one
     use_effect_with_deps(
         {
             let notify_manager = notify_manager.clone();
-            let room_handle = room_handle.clone();
-            move |_| {
-                if let Some(err) = &room_handle.error {
-                    notify_manager
-                        .show_notify(NotifyProps::error(format!("Room handling failed: {err:?}")));
+            let dispatcher = state_handle.dispatcher();
+            let shared = shared.clone();
+            let rpc_storage = rpc_storage.clone();
+            move |token: &PeerTokenEncoded| {
+                let timeout = PeerToken::decode(token).ok().map(|decoded| {
+                    let token = token.clone();
+                    let delay = decoded.refresh_delay().as_millis();
+                    Timeout::new(u32::try_from(delay).unwrap_or(u32::MAX), move || {
+                        let Some(rpc_client) = rpc_storage.rpc_client.clone() else {
+                            notify_manager.show_notify(NotifyProps::error("RPC client is missing"));
+                            return;
+                        };
 
-                    navigator.push(&Route::Home);
-                }
+                        spawn_local(async move {
+                            match rpc_client.refresh_token(token).await {
+                                Ok(refreshed) => {
+                                    session::store_token(&shared, refreshed.clone());
+                                    dispatcher.dispatch(Action::SetToken(refreshed));
+                                }
+                                Err(err) => notify_manager.show_notify(NotifyProps::error(
+                                    format!("Failed to refresh token: {err:?}"),
+                                )),
+                            }
+                        });
+                    })
+                });
+                move || drop(timeout)
             }
         },
-        room_handle.error.is_some(),
+        state_handle.client.token.clone(),
     );
 
+    // Room is handled until the query changes or the page is left
     use_effect_with_deps(
         {
             let location = location.clone();
-            let state_handle = state_handle.clone();
-            move |_| match location.query::<Query>() {
-                Ok(query) => match query {
-                    q @ Query::Create(_) => {
-                        let mut s = State::placeholder_host().clone();
-                        s.query = Some(q);
-                        s.loading = true;
-                        state_handle.set(s);
-
-                        room_handle.run();
-                    }
-                    q @ Query::Connect(_) => {
-                        let mut s = State::placeholder_guest().clone();
-                        s.query = Some(q);
-                        s.loading = true;
-                        state_handle.set(s);
+            let dispatcher = state_handle.dispatcher();
+            let shared = shared.clone();
+            move |_| {
+                let (abort_handle, abort_registration) = AbortHandle::new_pair();
+                match location.query::<Query>() {
+                    Ok(query) => {
+                        dispatcher.dispatch(Action::Start(query.clone()));
+                        *shared.borrow_mut() = Shared::default();
 
-                        room_handle.run();
+                        let session = Abortable::new(
+                            session::run(query, shared, dispatcher),
+                            abort_registration,
+                        );
+                        spawn_local(async move {
+                            if let Ok(Err(err)) = session.await {
+                                notify_manager.show_notify(NotifyProps::error(format!(
+                                    "Room handling failed: {err}"
+                                )));
+
+                                navigator.push(&Route::Home);
+                            }
+                        });
                     }
-                },
-                Err(q_err) => {
-                    let mut s = state_handle.deref().clone();
-                    s.query = None;
-                    s.loading = true;
-                    state_handle.set(s);
+                    Err(q_err) => {
+                        dispatcher.dispatch(Action::InvalidQuery);
 
-                    notify_manager.show_notify(NotifyProps::error(format!(
-                        "Failed to parse URL query: {q_err:?}"
-                    )));
+                        notify_manager.show_notify(NotifyProps::error(format!(
+                            "Failed to parse URL query: {q_err:?}"
+                        )));
+                    }
                 }
+
+                move || abort_handle.abort()
             }
         },
         location.query_str().to_owned(),
     );
 
+    // Room keeps entities in a map, they are shown in a stable order
+    let mut entities = state_handle
+        .room
+        .entities
+        .iter()
+        .map(|(entity_id, entity)| (*entity_id, entity.clone()))
+        .collect::<IndexMap<_, _>>();
+    entities.sort_by(|_, a, _, b| a.name.cmp(&b.name));
+
     html! {
         <div class="container-fluid
                     h-100
@@ -105,85 +250,27 @@ pub fn room() -> Html {
                 clients={
                     state_handle
                         .room
-                        .clients
-                        .iter()
-                        .map(|id| (*id, if *id == state_handle.room.host { ClientRole::Host } else { ClientRole::Guest } ))
+                        .peers
+                        .keys()
+                        .map(|id| (*id, state_handle.room.role(*id)))
                         .collect::<HashMap<_, _>>()
                 }
                 cur_client={(state_handle.client.id, state_handle.client.role)}
-                invites={state_handle.room.invites.clone()}
+                invites={state_handle.invites.clone()}
+                fingerprints={state_handle.fingerprints.clone()}
                 host={state_handle.room.host}
+                on_kick={on_kick}
+                on_create_invite={on_create_invite}
+                on_revoke_invite={on_revoke_invite}
             />
             <RoomEntities
                 loading={state_handle.loading}
-                entities={state_handle.room.entities.clone()}
+                peer_id={state_handle.client.id}
+                entities={entities}
+                progress={state_handle.progress.clone()}
+                on_request_entity={on_request_entity}
+                on_announce={on_announce}
             />
         </div>
     }
 }
-
-async fn handle_room_update(
-    rpc_client: Rc<jsonrpsee::core::client::Client>,
-    state_handle: UseStateHandle<State>,
-) -> Result<(), ShareError> {
-    let query = state_handle
-        .deref()
-        .query
-        .clone()
-        .ok_or_else(|| Error::Other(anyhow::anyhow!("Failed to get query")))?;
-
-    match query {
-        Query::Create(ActionCreate {
-            encryption,
-            capacity,
-        }) => {
-            let sub = rpc_client
-                .create(RoomOptions {
-                    encryption,
-                    capacity,
-                })
-                .await
-                .map_err(Error::from)?;
-            handle_subscribe(sub, state_handle).await
-        }
-        Query::Connect(ActionConnect {
-            room_id,
-            invite_password,
-        }) => {
-            let sub = rpc_client
-                .connect(room_id, invite_password)
-                .await
-                .map_err(Error::from)?;
-            handle_subscribe(sub, state_handle).await
-        }
-    }
-}
-
-async fn handle_subscribe(
-    mut sub: Subscription<RoomEvent>,
-    state_handle: UseStateHandle<State>,
-) -> Result<(), ShareError> {
-    while let Some(maybe_event) = sub.next().await {
-        let event = maybe_event.map_err(Error::from)?;
-        match event {
-            RoomEvent::Init {
-                token,
-                peer_id: client_id,
-                peer_role: client_role,
-            } => {
-                let mut s = state_handle.deref().clone();
-                s.client.token = token;
-                s.client.id = client_id;
-                s.client.role = client_role;
-                state_handle.set(s);
-            }
-            RoomEvent::RoomInfo(room_info) => {
-                let mut s = state_handle.deref().clone();
-                s.room = room_info;
-                state_handle.set(s);
-            }
-        }
-    }
-
-    Ok(())
-}
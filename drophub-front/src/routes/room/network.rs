@@ -1,47 +1,306 @@
-use std::{cell::RefCell, fmt::Display, rc::Rc};
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    future::Future,
+    rc::Rc,
+};
 
-use js_sys::{Array, Object, Reflect};
+use drophub::{
+    crypto::{KeyExchange, PublicKeyBytes, SessionKey},
+    transfer::{ChunkSender, Frame},
+    EntityId, PeerId, RelayFrame, SignalPayload, Transport,
+};
+use futures::{
+    channel::{mpsc, oneshot},
+    StreamExt,
+};
+use js_sys::{Array, ArrayBuffer, Object, Reflect, Uint8Array};
 use wasm_bindgen::prelude::*;
 use wasm_bindgen_futures::JsFuture;
 use web_sys::{
-    Blob, RtcConfiguration, RtcDataChannel, RtcDataChannelEvent, RtcDataChannelInit,
+    Blob, MessageEvent, RtcConfiguration, RtcDataChannel, RtcDataChannelEvent, RtcDataChannelInit,
+    RtcDataChannelState, RtcDataChannelType, RtcIceCandidateInit, RtcIceConnectionState,
     RtcPeerConnection, RtcPeerConnectionIceEvent, RtcSdpType, RtcSessionDescriptionInit,
 };
-use yew::platform::spawn_local;
-
-pub trait NetworkManager {
-    fn send_file(&self, file: &Blob) -> Result<(), JsValue>;
-    fn send_text(&self, text: &str) -> Result<(), JsValue>;
-}
 
 const STUN_SERVER1: &str = "stun:stun.l.google.com:19302";
 const STUN_SERVER2: &str = "stun:stun1.l.google.com:19302";
+const DATA_CHANNEL_LABEL: &str = "entitiesChannel";
+/// Sending is paused while the data channel buffers more bytes than this.
+const MAX_BUFFERED_AMOUNT: u32 = 1024 * 1024;
+/// Sending is resumed when the data channel buffer drops below this.
+const BUFFERED_AMOUNT_LOW_THRESHOLD: u32 = 256 * 1024;
 
+/// Sends signaling message to the remote peer, e.g. via `send_signal` RPC.
+pub type SignalSender = Rc<dyn Fn(PeerId, SignalPayload)>;
+/// Handles frames from the remote peer which don't belong to outgoing transfers:
+/// chunks of incoming entities and requests to send an entity.
+pub type FrameHandler = Rc<dyn Fn(PeerId, Frame)>;
+/// Sends encoded frame to the remote peer through the server, e.g. via `relay_frame` RPC.
+pub type RelaySender = Rc<dyn Fn(PeerId, Vec<u8>)>;
+/// Called when direct connection to the remote peer fails, e.g. calls `request_relay` RPC.
+pub type P2pFailureHandler = Rc<dyn Fn(PeerId)>;
+/// Called when direct connection to the remote peer is closed, e.g. reconnects to resume
+/// transfers.
+pub type CloseHandler = Rc<dyn Fn(PeerId)>;
+/// Called when a key is exchanged with the remote peer, e.g. shows its fingerprint.
+pub type SessionKeyHandler = Rc<dyn Fn(PeerId, &SessionKey)>;
+
+/// Options shared by every connection of the local peer.
+#[derive(Clone)]
+pub struct ConnectionOptions {
+    pub local_peer_id: PeerId,
+    pub send_signal: SignalSender,
+    pub on_frame: FrameHandler,
+    pub send_relay: RelaySender,
+    pub on_p2p_failure: P2pFailureHandler,
+    pub on_close: CloseHandler,
+    pub on_session_key: SessionKeyHandler,
+}
+
+/// WebRTC connection with a single remote peer.
+#[derive(Clone)]
 pub struct WebRtcServer {
+    inner: Rc<WebRtcServerInner>,
+}
+
+struct WebRtcServerInner {
+    remote_peer_id: PeerId,
     peer_conn: RtcPeerConnection,
-    data_chan: RtcDataChannel,
-    offer: RtcSessionDescriptionInit,
+    data_chan: Rc<DataChannel>,
+    send_signal: SignalSender,
+    on_session_key: SessionKeyHandler,
+    _on_ice_candidate: Closure<dyn FnMut(RtcPeerConnectionIceEvent)>,
+    _on_data_channel: Closure<dyn FnMut(RtcDataChannelEvent)>,
+    _on_ice_connection_state_change: Closure<dyn FnMut(JsValue)>,
+}
+
+/// Data channel with routing of received frames.
+struct DataChannel {
+    remote_peer_id: PeerId,
+    chan: RefCell<Option<RtcDataChannel>>,
+    /// Acknowledgements and requests for outgoing transfers.
+    transfers: RefCell<HashMap<EntityId, mpsc::UnboundedSender<Frame>>>,
+    on_frame: FrameHandler,
+    buffered_amount_low: RefCell<Option<oneshot::Sender<()>>>,
+    callbacks: RefCell<Vec<Closure<dyn FnMut(JsValue)>>>,
+    e2e: E2e,
+    transport: Cell<Transport>,
+    send_relay: RelaySender,
+    on_close: CloseHandler,
+}
+
+/// End-to-end encryption state of the connection. Entity chunks are always sealed, keys
+/// are exchanged by whichever peer connects first, the other one replies with its key.
+struct E2e {
+    local_peer_id: PeerId,
+    /// Exchange started by the local peer and waiting for the remote public key.
+    key_exchange: RefCell<Option<KeyExchange>>,
+    session_key: RefCell<Option<SessionKey>>,
 }
 
-impl NetworkManager for WebRtcServer {
-    fn send_file(&self, file: &Blob) -> Result<(), JsValue> {
-        self.data_chan.send_with_blob(file)
+impl DataChannel {
+    fn set(self: &Rc<Self>, chan: RtcDataChannel) {
+        chan.set_binary_type(RtcDataChannelType::Arraybuffer);
+        chan.set_buffered_amount_low_threshold(BUFFERED_AMOUNT_LOW_THRESHOLD);
+
+        let on_message = Closure::<dyn FnMut(_)>::new({
+            let this = Rc::downgrade(self);
+            move |event: JsValue| {
+                let Some(this) = this.upgrade() else { return };
+                let event = event.unchecked_into::<MessageEvent>();
+                let Ok(buf) = event.data().dyn_into::<ArrayBuffer>() else {
+                    tracing::warn!("Unexpected data channel message");
+                    return;
+                };
+
+                this.handle_bytes(&Uint8Array::new(&buf).to_vec());
+            }
+        });
+        chan.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+
+        let on_buffered_amount_low = Closure::<dyn FnMut(_)>::new({
+            let this = Rc::downgrade(self);
+            move |_: JsValue| {
+                let Some(this) = this.upgrade() else { return };
+                if let Some(tx) = this.buffered_amount_low.borrow_mut().take() {
+                    let _ = tx.send(());
+                }
+            }
+        });
+        chan.set_onbufferedamountlow(Some(on_buffered_amount_low.as_ref().unchecked_ref()));
+
+        let on_close = Closure::<dyn FnMut(_)>::new({
+            let this = Rc::downgrade(self);
+            move |_: JsValue| {
+                let Some(this) = this.upgrade() else { return };
+                tracing::debug!(remote_peer_id = ?this.remote_peer_id, "Data channel closed");
+                // Outgoing transfers are interrupted, they are resumed by new requests
+                this.transfers.borrow_mut().clear();
+                this.buffered_amount_low.borrow_mut().take();
+                // Relayed frames don't depend on the channel
+                if this.transport.get() == Transport::P2p {
+                    (this.on_close)(this.remote_peer_id);
+                }
+            }
+        });
+        chan.set_onclose(Some(on_close.as_ref().unchecked_ref()));
+
+        *self.callbacks.borrow_mut() = vec![on_message, on_buffered_amount_low, on_close];
+        *self.chan.borrow_mut() = Some(chan);
     }
 
-    fn send_text(&self, text: &str) -> Result<(), JsValue> {
-        self.data_chan.send_with_str(text)
+    fn get(&self) -> Result<RtcDataChannel, JsValue> {
+        self.chan
+            .borrow()
+            .clone()
+            .ok_or_else(|| JsValue::from_str("Data channel is not opened"))
+    }
+
+    /// Handles encoded frame received by data channel or relayed by the server.
+    fn handle_bytes(&self, bytes: &[u8]) {
+        match Frame::decode(bytes) {
+            Ok(frame) => self.route_frame(frame),
+            Err(err) => tracing::warn!(?err, "Failed to decode frame"),
+        }
+    }
+
+    fn route_frame(&self, frame: Frame) {
+        match &frame {
+            Frame::Ack { entity_id, .. } | Frame::Request { entity_id, .. } => {
+                if let Some(tx) = self.transfers.borrow().get(entity_id) {
+                    let _ = tx.unbounded_send(frame);
+                    return;
+                }
+            }
+            Frame::Chunk { .. } => {}
+        }
+
+        let frame = match frame {
+            Frame::Chunk {
+                entity_id,
+                offset,
+                data,
+            } => match self.open(entity_id, offset, data) {
+                Ok(data) => Frame::Chunk {
+                    entity_id,
+                    offset,
+                    data,
+                },
+                Err(err) => {
+                    // Receiver re-requests the entity on offset mismatch
+                    tracing::warn!(?err, ?entity_id, offset, "Failed to open chunk");
+                    return;
+                }
+            },
+            frame => frame,
+        };
+
+        (self.on_frame)(self.remote_peer_id, frame);
+    }
+
+    /// Derives session key from public key of the remote peer. Public key either completes
+    /// the exchange started by the local peer or starts a new one, then the returned local
+    /// public key must be sent back.
+    fn handle_public_key(
+        &self,
+        public_key: PublicKeyBytes,
+    ) -> Result<(SessionKey, Option<PublicKeyBytes>), JsValue> {
+        let (key_exchange, reply) = match self.e2e.key_exchange.borrow_mut().take() {
+            Some(key_exchange) => (key_exchange, None),
+            None => {
+                let key_exchange = KeyExchange::new();
+                let reply = key_exchange.public_key();
+                (key_exchange, Some(reply))
+            }
+        };
+
+        let session_key = key_exchange
+            .derive(self.e2e.local_peer_id, self.remote_peer_id, public_key)
+            .map_err(|err| JsValue::from_str(&err.to_string()))?;
+        *self.e2e.session_key.borrow_mut() = Some(session_key.clone());
+
+        Ok((session_key, reply))
+    }
+
+    fn session_key(&self) -> Result<SessionKey, JsValue> {
+        self.e2e
+            .session_key
+            .borrow()
+            .clone()
+            .ok_or_else(|| JsValue::from_str("Encryption key is not exchanged yet"))
+    }
+
+    fn seal(&self, entity_id: EntityId, offset: u64, data: Vec<u8>) -> Result<Vec<u8>, JsValue> {
+        self.session_key()?
+            .entity_cipher(entity_id)
+            .seal(offset, &data)
+            .map_err(|err| JsValue::from_str(&err.to_string()))
+    }
+
+    fn open(&self, entity_id: EntityId, offset: u64, data: Vec<u8>) -> Result<Vec<u8>, JsValue> {
+        self.session_key()?
+            .entity_cipher(entity_id)
+            .open(offset, &data)
+            .map_err(|err| JsValue::from_str(&err.to_string()))
+    }
+
+    /// Sends encoded frame with the current transport.
+    fn send_bytes(&self, bytes: Vec<u8>) -> Result<(), JsValue> {
+        match self.transport.get() {
+            Transport::P2p => {
+                let chan = self.get()?;
+                if chan.ready_state() != RtcDataChannelState::Open {
+                    return Err(JsValue::from_str("Data channel is not opened"));
+                }
+                chan.send_with_u8_array(&bytes)
+            }
+            Transport::Relay => {
+                (self.send_relay)(self.remote_peer_id, bytes);
+                Ok(())
+            }
+        }
+    }
+
+    /// Waits until the data channel buffer is small enough to send more.
+    /// Relayed frames are limited by the server instead.
+    async fn wait_buffered_amount_low(&self) -> Result<(), JsValue> {
+        if self.transport.get() == Transport::Relay
+            || self.get()?.buffered_amount() <= MAX_BUFFERED_AMOUNT
+        {
+            return Ok(());
+        }
+
+        let (tx, rx) = oneshot::channel();
+        *self.buffered_amount_low.borrow_mut() = Some(tx);
+        rx.await
+            .map_err(|_| JsValue::from_str("Data channel closed"))
+    }
+
+    fn close(&self) {
+        if let Some(chan) = self.chan.borrow_mut().take() {
+            // Callbacks are dropped below, the channel must not call them
+            chan.set_onmessage(None);
+            chan.set_onbufferedamountlow(None);
+            chan.set_onclose(None);
+            chan.close();
+        }
+        self.transfers.borrow_mut().clear();
+        self.callbacks.borrow_mut().clear();
     }
 }
 
 impl WebRtcServer {
-    pub async fn new() -> Result<Rc<RefCell<Self>>, JsValue> {
+    /// Creates connection to the remote peer. Local ICE candidates are sent as soon as
+    /// they are gathered, the public key is sent immediately.
+    pub fn new(remote_peer_id: PeerId, opts: &ConnectionOptions) -> Result<Self, JsValue> {
+        let send_signal = opts.send_signal.clone();
         let peer_conn = {
             let ice_servers = Array::new();
             {
                 let server_entry = Object::new();
-
-                Reflect::set(&server_entry, &"urls".into(), &STUN_SERVER1.into())?;
-                Reflect::set(&server_entry, &"urls".into(), &STUN_SERVER2.into())?;
+                let urls = Array::of2(&STUN_SERVER1.into(), &STUN_SERVER2.into());
+                Reflect::set(&server_entry, &"urls".into(), &urls)?;
 
                 ice_servers.push(&*server_entry);
             }
@@ -52,57 +311,412 @@ impl WebRtcServer {
             RtcPeerConnection::new_with_configuration(&rtc_configuration)?
         };
 
+        let on_ice_candidate = Closure::<dyn FnMut(_)>::new({
+            let send_signal = send_signal.clone();
+            move |event: RtcPeerConnectionIceEvent| {
+                // `None` means that gathering is complete
+                if let Some(candidate) = event.candidate() {
+                    send_signal(
+                        remote_peer_id,
+                        SignalPayload::IceCandidate {
+                            candidate: candidate.candidate(),
+                            sdp_mid: candidate.sdp_mid(),
+                            sdp_m_line_index: candidate.sdp_m_line_index(),
+                        },
+                    );
+                }
+            }
+        });
+        peer_conn.set_onicecandidate(Some(on_ice_candidate.as_ref().unchecked_ref()));
+
+        let key_exchange = KeyExchange::new();
+        send_signal(
+            remote_peer_id,
+            SignalPayload::PublicKey {
+                public_key: key_exchange.public_key(),
+            },
+        );
+        let e2e = E2e {
+            local_peer_id: opts.local_peer_id,
+            key_exchange: RefCell::new(Some(key_exchange)),
+            session_key: RefCell::new(None),
+        };
+
+        let data_chan = Rc::new(DataChannel {
+            remote_peer_id,
+            chan: RefCell::new(None),
+            transfers: RefCell::new(HashMap::new()),
+            on_frame: opts.on_frame.clone(),
+            buffered_amount_low: RefCell::new(None),
+            callbacks: RefCell::new(Vec::new()),
+            e2e,
+            transport: Cell::new(Transport::P2p),
+            send_relay: opts.send_relay.clone(),
+            on_close: opts.on_close.clone(),
+        });
+        let on_data_channel = Closure::<dyn FnMut(_)>::new({
+            let data_chan = data_chan.clone();
+            move |event: RtcDataChannelEvent| {
+                tracing::debug!(?remote_peer_id, "Data channel opened by remote peer");
+                data_chan.set(event.channel());
+            }
+        });
+        peer_conn.set_ondatachannel(Some(on_data_channel.as_ref().unchecked_ref()));
+
+        let on_ice_connection_state_change = Closure::<dyn FnMut(_)>::new({
+            let peer_conn = peer_conn.clone();
+            let on_p2p_failure = opts.on_p2p_failure.clone();
+            move |_: JsValue| {
+                if peer_conn.ice_connection_state() == RtcIceConnectionState::Failed {
+                    tracing::warn!(?remote_peer_id, "Direct connection failed");
+                    on_p2p_failure(remote_peer_id);
+                }
+            }
+        });
+        peer_conn.set_oniceconnectionstatechange(Some(
+            on_ice_connection_state_change.as_ref().unchecked_ref(),
+        ));
+
+        Ok(Self {
+            inner: Rc::new(WebRtcServerInner {
+                remote_peer_id,
+                peer_conn,
+                data_chan,
+                send_signal,
+                on_session_key: opts.on_session_key.clone(),
+                _on_ice_candidate: on_ice_candidate,
+                _on_data_channel: on_data_channel,
+                _on_ice_connection_state_change: on_ice_connection_state_change,
+            }),
+        })
+    }
+
+    /// Opens data channel and sends offer to the remote peer.
+    pub async fn connect(&self) -> Result<(), JsValue> {
         let data_chan = {
             let mut data_channel_init = RtcDataChannelInit::new();
             data_channel_init.ordered(true);
 
-            peer_conn
-                .create_data_channel_with_data_channel_dict("entitiesChannel", &data_channel_init)
+            self.inner
+                .peer_conn
+                .create_data_channel_with_data_channel_dict(DATA_CHANNEL_LABEL, &data_channel_init)
         };
+        self.inner.data_chan.set(data_chan);
 
-        let this = Rc::new(RefCell::new(Self {
-            peer_conn,
-            data_chan,
-            offer: RtcSessionDescriptionInit::new(RtcSdpType::Offer),
-        }));
+        let offer = JsFuture::from(self.inner.peer_conn.create_offer()).await?;
+        let sdp = self
+            .set_local_description(RtcSdpType::Offer, &offer)
+            .await?;
+        (self.inner.send_signal)(self.inner.remote_peer_id, SignalPayload::Offer { sdp });
 
-        let create_offer_closure = Closure::new({
-            let this = this.clone();
-            move |offer: JsValue| {
-                let rtc_session_description: RtcSessionDescriptionInit =
-                    offer.unchecked_into::<RtcSessionDescriptionInit>();
+        Ok(())
+    }
 
-                let _promise = peer_conn
-                    .set_local_description(&rtc_session_description)
-                    .catch(&exception_handler("set_local_description failed"));
+    /// Handles signaling message received from the remote peer.
+    pub async fn handle_signal(&self, payload: SignalPayload) -> Result<(), JsValue> {
+        match payload {
+            SignalPayload::Offer { sdp } => {
+                self.set_remote_description(RtcSdpType::Offer, &sdp).await?;
 
-                this.borrow_mut().offer = rtc_session_description;
+                let answer = JsFuture::from(self.inner.peer_conn.create_answer()).await?;
+                let sdp = self
+                    .set_local_description(RtcSdpType::Answer, &answer)
+                    .await?;
+                (self.inner.send_signal)(self.inner.remote_peer_id, SignalPayload::Answer { sdp });
             }
-        });
+            SignalPayload::Answer { sdp } => {
+                self.set_remote_description(RtcSdpType::Answer, &sdp)
+                    .await?;
+            }
+            SignalPayload::IceCandidate {
+                candidate,
+                sdp_mid,
+                sdp_m_line_index,
+            } => {
+                let mut candidate_init = RtcIceCandidateInit::new(&candidate);
+                candidate_init
+                    .sdp_mid(sdp_mid.as_deref())
+                    .sdp_m_line_index(sdp_m_line_index);
+
+                JsFuture::from(
+                    self.inner
+                        .peer_conn
+                        .add_ice_candidate_with_opt_rtc_ice_candidate_init(Some(&candidate_init)),
+                )
+                .await?;
+            }
+            SignalPayload::PublicKey { public_key } => {
+                let (session_key, reply) = self.inner.data_chan.handle_public_key(public_key)?;
+                if let Some(public_key) = reply {
+                    (self.inner.send_signal)(
+                        self.inner.remote_peer_id,
+                        SignalPayload::PublicKey { public_key },
+                    );
+                }
+                (self.inner.on_session_key)(self.inner.remote_peer_id, &session_key);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sends frame to the remote peer, e.g. acknowledgement of incoming transfer.
+    pub fn send_frame(&self, frame: &Frame) -> Result<(), JsValue> {
+        self.inner.data_chan.send_bytes(frame.encode())
+    }
+
+    /// Returns true if frames can be sent to the remote peer right away.
+    pub fn is_connected(&self) -> bool {
+        match self.transport() {
+            Transport::P2p => self
+                .inner
+                .data_chan
+                .chan
+                .borrow()
+                .as_ref()
+                .is_some_and(|chan| chan.ready_state() == RtcDataChannelState::Open),
+            Transport::Relay => true,
+        }
+    }
+
+    pub fn transport(&self) -> Transport {
+        self.inner.data_chan.transport.get()
+    }
+
+    /// Switches transport, e.g. when the server reports that frames are relayed.
+    pub fn set_transport(&self, transport: Transport) {
+        tracing::info!(remote_peer_id = ?self.inner.remote_peer_id, ?transport, "Transport changed");
+        self.inner.data_chan.transport.set(transport);
+    }
+
+    /// Handles frame relayed by the server.
+    pub fn handle_relay_frame(&self, frame: &RelayFrame) {
+        self.inner.data_chan.handle_bytes(&frame.data.0);
+    }
+
+    /// Sends file in chunks starting from the offset requested by the remote peer.
+    /// Completes when the remote peer acknowledges every chunk.
+    ///
+    /// The transfer is registered before the future is polled, so following requests
+    /// of the entity resume it instead of being passed to the frame handler.
+    pub fn send_file(
+        &self,
+        entity_id: EntityId,
+        file: Blob,
+        offset: u64,
+    ) -> impl Future<Output = Result<(), JsValue>> {
+        let (tx, mut rx) = mpsc::unbounded();
+        self.inner
+            .data_chan
+            .transfers
+            .borrow_mut()
+            .insert(entity_id, tx);
+
+        let this = self.clone();
+        async move {
+            let res = this
+                .send_file_chunks(entity_id, &file, offset, &mut rx)
+                .await;
+            this.inner
+                .data_chan
+                .transfers
+                .borrow_mut()
+                .remove(&entity_id);
+
+            res
+        }
+    }
+
+    async fn send_file_chunks(
+        &self,
+        entity_id: EntityId,
+        file: &Blob,
+        offset: u64,
+        rx: &mut mpsc::UnboundedReceiver<Frame>,
+    ) -> Result<(), JsValue> {
+        let mut sender = ChunkSender::new(entity_id, file.size() as u64);
+        sender.resume(offset).map_err(frame_error)?;
+
+        let handle_frame = |sender: &mut ChunkSender, frame: Frame| match frame {
+            Frame::Ack { offset, .. } => sender.on_ack(offset),
+            Frame::Request { offset, .. } => sender.resume(offset),
+            Frame::Chunk { .. } => Ok(()),
+        };
+
+        while !sender.is_complete() {
+            while let Ok(Some(frame)) = rx.try_next() {
+                handle_frame(&mut sender, frame).map_err(frame_error)?;
+            }
+
+            match sender.next_chunk() {
+                Some(range) => {
+                    self.inner.data_chan.wait_buffered_amount_low().await?;
+
+                    let chunk =
+                        file.slice_with_f64_and_f64(range.start as f64, range.end as f64)?;
+                    let buf = JsFuture::from(chunk.array_buffer()).await?;
+                    let data = self.inner.data_chan.seal(
+                        entity_id,
+                        range.start,
+                        Uint8Array::new(&buf).to_vec(),
+                    )?;
+                    let frame = Frame::Chunk {
+                        entity_id,
+                        offset: range.start,
+                        data,
+                    };
+                    self.inner.data_chan.send_bytes(frame.encode())?;
+                }
+                None => {
+                    // Too many bytes are not acknowledged, wait for the receiver
+                    let frame = rx
+                        .next()
+                        .await
+                        .ok_or_else(|| JsValue::from_str("Data channel closed"))?;
+                    handle_frame(&mut sender, frame).map_err(frame_error)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn close(&self) {
+        self.inner.data_chan.close();
+        self.inner.peer_conn.close();
+    }
 
+    /// Sets local description and returns its SDP.
+    async fn set_local_description(
+        &self,
+        kind: RtcSdpType,
+        description: &JsValue,
+    ) -> Result<String, JsValue> {
+        let sdp = Reflect::get(description, &"sdp".into())?
+            .as_string()
+            .ok_or_else(|| JsValue::from_str("Session description does not contain SDP"))?;
+
+        let mut description_init = RtcSessionDescriptionInit::new(kind);
+        description_init.sdp(&sdp);
         JsFuture::from(
-            peer_conn
-                .create_offer()
-                .then(&create_offer_closure)
-                .catch(&exception_handler("create_offer failed")),
+            self.inner
+                .peer_conn
+                .set_local_description(&description_init),
         )
-        .await
-        .expect_throw("create_offer future failed");
+        .await?;
 
-        create_offer_closure.forget();
+        Ok(sdp)
+    }
 
-        Ok(this)
+    async fn set_remote_description(&self, kind: RtcSdpType, sdp: &str) -> Result<(), JsValue> {
+        let mut description_init = RtcSessionDescriptionInit::new(kind);
+        description_init.sdp(sdp);
+        JsFuture::from(
+            self.inner
+                .peer_conn
+                .set_remote_description(&description_init),
+        )
+        .await?;
+
+        Ok(())
     }
 }
 
-fn exception_handler<M>(msg: M) -> Closure<dyn FnMut(JsValue)>
-where
-    M: Display + 'static,
-{
-    Closure::new(move |value: JsValue| {
-        // let notify_manager = get_notify();
-        // notify_manager.show_notify(NotifyProps::error(format!("Exception handled: {msg}")));
+/// WebRTC connections with every other peer in the room.
+pub struct RoomNetwork {
+    opts: ConnectionOptions,
+    conns: HashMap<PeerId, WebRtcServer>,
+}
+
+impl RoomNetwork {
+    pub fn new(opts: ConnectionOptions) -> Self {
+        Self {
+            opts,
+            conns: HashMap::new(),
+        }
+    }
+
+    pub fn get(&self, peer_id: &PeerId) -> Option<&WebRtcServer> {
+        self.conns.get(peer_id)
+    }
+
+    /// Opens connection to the peer the local peer exchanges entities with. Returns
+    /// connection that must send offer.
+    ///
+    /// Both peers call it once a request is accepted, but only the peer with the lower id
+    /// sends offer, so each pair of peers opens exactly one connection.
+    pub fn connect(&mut self, peer_id: PeerId) -> Result<Option<WebRtcServer>, JsValue> {
+        if self.opts.local_peer_id > peer_id || self.conns.contains_key(&peer_id) {
+            return Ok(None);
+        }
+
+        let conn = WebRtcServer::new(peer_id, &self.opts)?;
+        self.conns.insert(peer_id, conn.clone());
+        Ok(Some(conn))
+    }
+
+    /// Returns connection to the peer, creating it if there is none, e.g. when the server
+    /// switches the peer to the relay before any signal is exchanged.
+    pub fn get_or_create(&mut self, peer_id: PeerId) -> Result<WebRtcServer, JsValue> {
+        if let Some(conn) = self.conns.get(&peer_id) {
+            return Ok(conn.clone());
+        }
+
+        let conn = WebRtcServer::new(peer_id, &self.opts)?;
+        self.conns.insert(peer_id, conn.clone());
+        Ok(conn)
+    }
+
+    /// Closes connection to the peer, e.g. when its data channel is closed.
+    pub fn remove(&mut self, peer_id: &PeerId) {
+        if let Some(conn) = self.conns.remove(peer_id) {
+            conn.close();
+        }
+    }
+
+    /// Closes connections to peers that left the room.
+    pub fn retain_peers<I>(&mut self, room_peers: I)
+    where
+        I: IntoIterator<Item = PeerId>,
+    {
+        let room_peers = room_peers.into_iter().collect::<Vec<_>>();
+        self.conns.retain(|peer_id, conn| {
+            let keep = room_peers.contains(peer_id);
+            if !keep {
+                conn.close();
+            }
+            keep
+        });
+    }
+
+    /// Returns connection that must handle signal from the remote peer. Connection is
+    /// created when the remote peer sends offer or its public key, the latter comes first.
+    pub fn signal_target(
+        &mut self,
+        from_peer_id: PeerId,
+        payload: &SignalPayload,
+    ) -> Result<Option<WebRtcServer>, JsValue> {
+        if let Some(conn) = self.conns.get(&from_peer_id) {
+            return Ok(Some(conn.clone()));
+        }
+
+        match payload {
+            SignalPayload::Offer { .. } | SignalPayload::PublicKey { .. } => {
+                self.get_or_create(from_peer_id).map(Some)
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+impl Drop for RoomNetwork {
+    fn drop(&mut self) {
+        for conn in self.conns.values() {
+            conn.close();
+        }
+    }
+}
 
-        tracing::error!(?value, "Exception handled: {msg}");
-    })
+fn frame_error(err: drophub::transfer::FrameError) -> JsValue {
+    JsValue::from_str(&err.to_string())
 }
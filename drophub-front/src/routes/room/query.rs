@@ -1,5 +1,6 @@
 use std::str::FromStr;
 
+use drophub::InvitePassphrase;
 use serde::{de::Error, Deserialize, Deserializer, Serialize};
 
 fn from_str<'de, D, S>(deserializer: D) -> Result<S, D::Error>
@@ -20,15 +21,11 @@ pub enum Query {
 
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct ActionCreate {
-    #[serde(deserialize_with = "from_str")]
-    pub encryption: bool,
     #[serde(deserialize_with = "from_str")]
     pub capacity: usize,
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct ActionConnect {
-    #[serde(deserialize_with = "from_str")]
-    pub room_id: u64,
-    pub invite_password: String,
+    pub invite_passphrase: InvitePassphrase,
 }
@@ -0,0 +1,804 @@
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    pin::pin,
+    rc::Rc,
+    time::Duration,
+};
+
+use drophub::{
+    transfer::Frame, Capability, DisconnectReason, EntityId, PeerEvent, PeerId, PeerToken,
+    PeerTokenEncoded, RelayData, RelayFrame, Room, RoomId, RoomOptions, RpcClient, TransferState,
+};
+use futures::{channel::mpsc, future::pending, FutureExt, StreamExt};
+use jsonrpsee::core::client::Subscription;
+use web_sys::Blob;
+use yew::{
+    platform::{
+        spawn_local,
+        time::{interval, sleep},
+    },
+    UseReducerDispatcher,
+};
+use yewdux::prelude::*;
+
+use crate::{
+    error::{Error, ShareError},
+    hooks::{client_hello, rpc_client, ResumedSession, SessionStorage},
+    routes::room::{
+        network::{ConnectionOptions, RoomNetwork, WebRtcServer},
+        query::{ActionConnect, Query},
+        state::{Action, State},
+        transfer::Download,
+    },
+};
+
+/// Stalled downloads are requested again with this interval.
+const REQUEST_INTERVAL: Duration = Duration::from_secs(2);
+/// Download fails if the owner sends nothing after this number of requests.
+const MAX_RETRIES: u32 = 15;
+/// Frames are relayed if direct connection isn't opened after this number of requests.
+const RELAY_FALLBACK_RETRIES: u32 = 3;
+/// Delay before relaying a frame again when the server limits are exceeded.
+const RELAY_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// State of the room session shared with callbacks of the page.
+#[derive(Default)]
+pub(super) struct Shared {
+    /// Current token of the peer, it's refreshed and replaced on joining the room.
+    pub token: PeerTokenEncoded,
+    /// Files of entities announced by the local peer.
+    pub files: HashMap<EntityId, Blob>,
+}
+
+pub(super) type SharedHandle = Rc<RefCell<Shared>>;
+
+/// Joins the room according to the query and handles it until the peer is disconnected.
+/// New session is started if the previous one can't be resumed after reconnection.
+pub(super) async fn run(
+    query: Query,
+    shared: SharedHandle,
+    dispatcher: UseReducerDispatcher<State>,
+) -> Result<(), ShareError> {
+    loop {
+        match run_session(query.clone(), shared.clone(), dispatcher.clone()).await {
+            Err(err) if matches!(*err, Error::Server(drophub::Error::SessionNotFound { .. })) => {
+                tracing::warn!("Session is expired, starting a new one");
+                // Entities are removed with the expired peer
+                shared.borrow_mut().files.clear();
+                dispatcher.dispatch(Action::Start(query.clone()));
+            }
+            res => return res,
+        }
+    }
+}
+
+/// Replaces token of the peer. Stored token is used to resume the session after
+/// reconnection.
+pub(super) fn store_token(shared: &SharedHandle, token: PeerTokenEncoded) {
+    shared.borrow_mut().token = token.clone();
+    Dispatch::<SessionStorage>::new().reduce_mut(|s| s.token = Some(token));
+}
+
+async fn run_session(
+    query: Query,
+    shared: SharedHandle,
+    dispatcher: UseReducerDispatcher<State>,
+) -> Result<(), ShareError> {
+    let rpc_client = rpc_client()?;
+    let mut events = rpc_client
+        .sub_peer_events(Some(client_hello()))
+        .await
+        .map_err(Error::from)?;
+    let (token, invite_passphrase, capabilities) = match next_event(&mut events).await? {
+        PeerEvent::Init {
+            token,
+            invite_passphrase,
+            capabilities,
+            ..
+        } => (token, invite_passphrase, capabilities),
+        event => {
+            return Err(Error::ReceivedUnexpectedResponse {
+                act: format!("{event:?}"),
+                exp: "Init".to_owned(),
+            }
+            .into())
+        }
+    };
+    let local_peer_id = PeerToken::decode(&token).map_err(Error::from)?.peer_id;
+    store_token(&shared, token.clone());
+    dispatcher.dispatch(Action::Init {
+        token: token.clone(),
+        invite_passphrase,
+    });
+
+    let capacity = match query {
+        Query::Create(action) => Some(action.capacity),
+        Query::Connect(ActionConnect { invite_passphrase }) => {
+            rpc_client
+                .invite(token, invite_passphrase)
+                .await
+                .map_err(Error::from)?;
+            None
+        }
+    };
+
+    let relay_enabled = capabilities.contains(&Capability::Relay);
+    Session::new(local_peer_id, capacity, relay_enabled, shared, dispatcher)
+        .run(events)
+        .await
+}
+
+/// Input of the session loop.
+enum Input {
+    Event(PeerEvent),
+    /// Connection to API server is lost.
+    Disconnected,
+    Resumed(ResumedSession),
+    Frame(PeerId, Frame),
+    Relay(RelayFrame),
+    RelayClosed,
+    P2pFailure(PeerId),
+    Closed(PeerId),
+    Retry,
+}
+
+struct Session {
+    local_peer_id: PeerId,
+    /// Capacity requested by the host, applied once the room is created.
+    capacity: Option<usize>,
+    room_id: Option<RoomId>,
+    /// Server relays frames, it's the fallback when direct connection fails.
+    relay_enabled: bool,
+    /// Frames relayed from other peers, subscribed once the peer joins the room.
+    relay: Option<Subscription<RelayFrame>>,
+    /// Peers the relay is requested for.
+    relayed_peers: HashSet<PeerId>,
+    shared: SharedHandle,
+    dispatcher: UseReducerDispatcher<State>,
+    network: RoomNetwork,
+    frames: mpsc::UnboundedReceiver<(PeerId, Frame)>,
+    p2p_failures: mpsc::UnboundedReceiver<PeerId>,
+    closed: mpsc::UnboundedReceiver<PeerId>,
+    resumed: mpsc::UnboundedReceiver<ResumedSession>,
+    /// Peers entities are being transferred with, in either direction.
+    transfer_peers: HashSet<PeerId>,
+    downloads: HashMap<EntityId, Download>,
+}
+
+impl Session {
+    fn new(
+        local_peer_id: PeerId,
+        capacity: Option<usize>,
+        relay_enabled: bool,
+        shared: SharedHandle,
+        dispatcher: UseReducerDispatcher<State>,
+    ) -> Self {
+        let (frame_tx, frames) = mpsc::unbounded();
+        let (p2p_failure_tx, p2p_failures) = mpsc::unbounded();
+        let (closed_tx, closed) = mpsc::unbounded();
+        let (resume_tx, resumed) = mpsc::unbounded();
+        Dispatch::<SessionStorage>::new().reduce_mut(|s| s.on_resume = Some(resume_tx));
+        let (relay_tx, relay_rx) = mpsc::unbounded();
+        // Ends when the network is dropped with the sender
+        spawn_local(relay_frames(shared.clone(), relay_rx));
+        let opts = ConnectionOptions {
+            local_peer_id,
+            send_signal: Rc::new({
+                let shared = shared.clone();
+                move |to_peer_id, payload| {
+                    let token = shared.borrow().token.clone();
+                    spawn_local(async move {
+                        let res: Result<(), Error> = async {
+                            rpc_client()?
+                                .send_signal(token, to_peer_id, payload)
+                                .await?;
+                            Ok(())
+                        }
+                        .await;
+                        if let Err(err) = res {
+                            tracing::warn!(?err, ?to_peer_id, "Failed to send signal");
+                        }
+                    });
+                }
+            }),
+            on_frame: Rc::new(move |from_peer_id, frame| {
+                let _ = frame_tx.unbounded_send((from_peer_id, frame));
+            }),
+            send_relay: Rc::new(move |to_peer_id, data| {
+                let _ = relay_tx.unbounded_send((to_peer_id, data));
+            }),
+            on_p2p_failure: Rc::new(move |peer_id| {
+                let _ = p2p_failure_tx.unbounded_send(peer_id);
+            }),
+            on_close: Rc::new(move |peer_id| {
+                let _ = closed_tx.unbounded_send(peer_id);
+            }),
+            on_session_key: Rc::new({
+                let dispatcher = dispatcher.clone();
+                move |peer_id, session_key| {
+                    dispatcher.dispatch(Action::SetFingerprint {
+                        peer_id,
+                        fingerprint: session_key.fingerprint(),
+                    })
+                }
+            }),
+        };
+
+        Self {
+            local_peer_id,
+            capacity,
+            room_id: None,
+            relay_enabled,
+            relay: None,
+            relayed_peers: HashSet::new(),
+            shared,
+            dispatcher,
+            network: RoomNetwork::new(opts),
+            frames,
+            p2p_failures,
+            closed,
+            resumed,
+            transfer_peers: HashSet::new(),
+            downloads: HashMap::new(),
+        }
+    }
+
+    async fn run(mut self, events: Subscription<PeerEvent>) -> Result<(), ShareError> {
+        let mut events = Some(events);
+        let mut retry = pin!(interval(REQUEST_INTERVAL).fuse());
+        loop {
+            let input = futures::select! {
+                event = next_session_event(&mut events).fuse() => match event {
+                    Some(event) => Input::Event(event?),
+                    None => Input::Disconnected,
+                },
+                resumed = self.resumed.select_next_some() => Input::Resumed(resumed),
+                (from_peer_id, frame) = self.frames.select_next_some() => {
+                    Input::Frame(from_peer_id, frame)
+                }
+                frame = next_relay_frame(&mut self.relay).fuse() => match frame {
+                    Some(frame) => Input::Relay(frame),
+                    None => Input::RelayClosed,
+                },
+                peer_id = self.p2p_failures.select_next_some() => Input::P2pFailure(peer_id),
+                peer_id = self.closed.select_next_some() => Input::Closed(peer_id),
+                _ = retry.next() => Input::Retry,
+            };
+
+            match input {
+                Input::Event(event) => self.handle_event(event).await?,
+                Input::Disconnected => {
+                    // Transfers over direct connections go on meanwhile
+                    tracing::warn!("Peer events are closed, waiting for the session to be resumed");
+                    events = None;
+                }
+                Input::Resumed(resumed) => events = Some(self.handle_resumed(resumed).await?),
+                Input::Frame(from_peer_id, frame) => self.handle_frame(from_peer_id, frame).await,
+                Input::Relay(frame) => self.handle_relay_frame(frame),
+                Input::RelayClosed => self.relay = None,
+                Input::P2pFailure(peer_id) => self.fallback_to_relay(peer_id).await,
+                Input::Closed(peer_id) => self.handle_closed(peer_id),
+                Input::Retry => self.retry_downloads().await,
+            }
+        }
+    }
+
+    async fn handle_event(&mut self, event: PeerEvent) -> Result<(), ShareError> {
+        match event {
+            PeerEvent::Invite { token } => {
+                store_token(&self.shared, token.clone());
+                self.dispatcher.dispatch(Action::SetToken(token));
+                self.subscribe_relay().await;
+            }
+            PeerEvent::UpdateRoom { room } => self.handle_room(room).await,
+            PeerEvent::EntityRequested { by, entity_id } => {
+                // Every announced entity is shared with anyone in the room
+                let accept = self.shared.borrow().files.contains_key(&entity_id);
+                let res = rpc_client()?
+                    .answer_entity_request(self.token(), entity_id, by, accept)
+                    .await;
+                match res {
+                    Ok(()) if accept => self.connect(by),
+                    Ok(()) => {}
+                    Err(err) => tracing::warn!(?err, ?entity_id, ?by, "Failed to answer request"),
+                }
+            }
+            PeerEvent::Signal {
+                from_peer_id,
+                payload,
+            } => match self.network.signal_target(from_peer_id, &payload) {
+                // Signals are handled in order, e.g. candidates after the offer
+                Ok(Some(conn)) => {
+                    if let Err(err) = conn.handle_signal(payload).await {
+                        tracing::warn!(?err, ?from_peer_id, "Failed to handle signal");
+                    }
+                }
+                Ok(None) => tracing::debug!(?from_peer_id, "Signal without connection"),
+                Err(err) => tracing::warn!(?err, ?from_peer_id, "Failed to open connection"),
+            },
+            PeerEvent::Disconnect { reason, .. } => {
+                let reason = match reason {
+                    DisconnectReason::RoomExpired => "Room is expired",
+                    DisconnectReason::PeerExpired => "Session is expired",
+                    DisconnectReason::Kicked => "You are kicked from the room",
+                    DisconnectReason::RoomClosed => "Room is closed by the host",
+                };
+                return Err(Error::Other(anyhow::anyhow!(reason)).into());
+            }
+            PeerEvent::Transport { peer_id, transport } => {
+                match self.network.get_or_create(peer_id) {
+                    Ok(conn) => conn.set_transport(transport),
+                    Err(err) => {
+                        tracing::warn!(?err, ?peer_id, "Failed to open connection");
+                        return Ok(());
+                    }
+                }
+
+                // Downloads stalled on the previous transport are resumed at once
+                let entity_ids = self
+                    .downloads
+                    .iter()
+                    .filter(|(_, download)| {
+                        download.owner_id() == peer_id && !download.is_finished()
+                    })
+                    .map(|(entity_id, _)| *entity_id)
+                    .collect::<Vec<_>>();
+                for entity_id in entity_ids {
+                    self.request(entity_id);
+                }
+            }
+            PeerEvent::TransferProgress {
+                entity_id,
+                peer_id,
+                progress,
+            } => self.dispatcher.dispatch(Action::SetProgress {
+                entity_id,
+                peer_id,
+                progress,
+            }),
+            PeerEvent::Init { .. } => {}
+        }
+
+        Ok(())
+    }
+
+    async fn handle_room(&mut self, room: Room) {
+        self.network.retain_peers(room.peers.keys().copied());
+
+        if self.room_id != Some(room.id) && room.host == self.local_peer_id {
+            if let Some(capacity) = self.capacity.take() {
+                self.set_capacity(&room, capacity).await;
+            }
+        }
+        self.room_id = Some(room.id);
+
+        self.transfer_peers.clear();
+        let mut started = Vec::new();
+        for (entity_id, entity) in &room.entities {
+            if entity.owner_id == self.local_peer_id {
+                let receivers = entity
+                    .transfers
+                    .iter()
+                    .filter(|(_, state)| **state == TransferState::Transferring);
+                self.transfer_peers
+                    .extend(receivers.map(|(peer_id, _)| *peer_id));
+                continue;
+            }
+
+            match entity.transfers.get(&self.local_peer_id) {
+                Some(TransferState::Transferring) => {
+                    self.transfer_peers.insert(entity.owner_id);
+                    if !self.downloads.contains_key(entity_id) {
+                        self.downloads
+                            .insert(*entity_id, Download::new(*entity_id, entity.clone()));
+                        started.push(*entity_id);
+                    }
+                }
+                _ => {
+                    self.downloads.remove(entity_id);
+                }
+            }
+        }
+        self.downloads
+            .retain(|entity_id, _| room.entities.contains_key(entity_id));
+
+        for peer_id in self.transfer_peers.clone() {
+            self.connect(peer_id);
+        }
+        for entity_id in started {
+            if self.downloads[&entity_id].is_complete() {
+                // Nothing to receive, e.g. empty file
+                self.finish_download(entity_id).await;
+            } else {
+                // Connection may be opened already, otherwise the request is retried
+                self.request(entity_id);
+            }
+        }
+
+        self.dispatcher.dispatch(Action::UpdateRoom(room));
+    }
+
+    async fn set_capacity(&self, room: &Room, capacity: usize) {
+        if room.options.capacity == capacity {
+            return;
+        }
+
+        let options = RoomOptions {
+            capacity,
+            ..room.options.clone()
+        };
+        let res: Result<(), Error> = async {
+            rpc_client()?
+                .set_room_options(self.token(), options)
+                .await?;
+            Ok(())
+        }
+        .await;
+        if let Err(err) = res {
+            tracing::warn!(?err, capacity, "Failed to set room capacity");
+        }
+    }
+
+    async fn handle_frame(&mut self, from_peer_id: PeerId, frame: Frame) {
+        match frame {
+            Frame::Request { entity_id, offset } => {
+                let Some(file) = self.shared.borrow().files.get(&entity_id).cloned() else {
+                    tracing::warn!(?entity_id, ?from_peer_id, "Unknown entity requested");
+                    return;
+                };
+                let Some(conn) = self.network.get(&from_peer_id).cloned() else {
+                    return;
+                };
+
+                let send = conn.send_file(entity_id, file, offset);
+                spawn_local(async move {
+                    if let Err(err) = send.await {
+                        // Receiver resumes the transfer with a new request
+                        tracing::warn!(?err, ?entity_id, ?from_peer_id, "Transfer interrupted");
+                    }
+                });
+            }
+            Frame::Chunk {
+                entity_id,
+                offset,
+                data,
+            } => {
+                let Some(download) = self.downloads.get_mut(&entity_id) else {
+                    return;
+                };
+                if download.owner_id() != from_peer_id || download.is_finished() {
+                    return;
+                }
+
+                let ack = match download.on_chunk(offset, data) {
+                    Ok(ack) => ack,
+                    Err(err) => {
+                        // Chunks sent before the last request, the expected one arrives later
+                        tracing::debug!(?err, ?entity_id, "Unexpected chunk");
+                        return;
+                    }
+                };
+                let complete = download.is_complete();
+                let progress = download.progress_to_report();
+
+                if let Some(ack) = ack {
+                    self.send_frame(from_peer_id, &ack);
+                }
+                if let Some(transferred) = progress {
+                    self.report_progress(entity_id, transferred);
+                }
+                if complete {
+                    self.finish_download(entity_id).await;
+                }
+            }
+            Frame::Ack { .. } => {}
+        }
+    }
+
+    /// Continues the session with events re-attached after reconnection. Relayed frames
+    /// are subscribed again.
+    async fn handle_resumed(
+        &mut self,
+        resumed: ResumedSession,
+    ) -> Result<Subscription<PeerEvent>, ShareError> {
+        match resumed {
+            ResumedSession::Resumed { token, events } => {
+                store_token(&self.shared, token.clone());
+                self.dispatcher.dispatch(Action::SetToken(token));
+                if self.room_id.is_some() {
+                    self.relay = None;
+                    self.subscribe_relay().await;
+                }
+
+                Ok(events)
+            }
+            ResumedSession::NotFound => Err(Error::Server(drophub::Error::SessionNotFound {
+                peer_id: self.local_peer_id,
+            })
+            .into()),
+            ResumedSession::Failed(err) => Err(err.into()),
+        }
+    }
+
+    /// Saves received entity and reports the transfer result to the room.
+    async fn finish_download(&mut self, entity_id: EntityId) {
+        let Some(download) = self.downloads.get_mut(&entity_id) else {
+            return;
+        };
+
+        let state = match download.save() {
+            Ok(()) => TransferState::Done,
+            Err(err) => {
+                tracing::warn!(?err, ?entity_id, "Failed to save entity");
+                TransferState::Failed
+            }
+        };
+        self.report_transfer(entity_id, state).await;
+    }
+
+    /// Drops closed direct connection. Transfers are resumed from the acknowledged offsets
+    /// once the connection is opened again.
+    fn handle_closed(&mut self, peer_id: PeerId) {
+        tracing::debug!(?peer_id, "Direct connection closed");
+        self.network.remove(&peer_id);
+        if self.transfer_peers.contains(&peer_id) {
+            self.connect(peer_id);
+        }
+    }
+
+    fn handle_relay_frame(&self, frame: RelayFrame) {
+        match self.network.get(&frame.from_peer_id) {
+            Some(conn) => conn.handle_relay_frame(&frame),
+            None => {
+                let from_peer_id = frame.from_peer_id;
+                tracing::debug!(?from_peer_id, "Relayed frame without connection");
+            }
+        }
+    }
+
+    /// Requests downloads that received nothing since the previous check again.
+    async fn retry_downloads(&mut self) {
+        let mut to_request = Vec::new();
+        let mut failed = Vec::new();
+        let mut unreachable = HashSet::new();
+        for (entity_id, download) in &mut self.downloads {
+            if download.is_finished() {
+                continue;
+            }
+
+            match download.check_stalled() {
+                Some(retries) if retries > MAX_RETRIES => {
+                    download.fail();
+                    failed.push(*entity_id);
+                }
+                Some(retries) => {
+                    if retries == RELAY_FALLBACK_RETRIES {
+                        unreachable.insert(download.owner_id());
+                    }
+                    to_request.push(*entity_id);
+                }
+                None => {}
+            }
+        }
+
+        for peer_id in unreachable {
+            // Direct connection isn't opened, e.g. the owner uses the CLI
+            let connected = self
+                .network
+                .get(&peer_id)
+                .is_some_and(WebRtcServer::is_connected);
+            if !connected {
+                self.fallback_to_relay(peer_id).await;
+            }
+        }
+
+        for entity_id in to_request {
+            self.request(entity_id);
+        }
+        for entity_id in failed {
+            tracing::warn!(?entity_id, "Owner stopped sending entity");
+            self.report_transfer(entity_id, TransferState::Failed).await;
+        }
+    }
+
+    fn request(&mut self, entity_id: EntityId) {
+        let Some(download) = self.downloads.get_mut(&entity_id) else {
+            return;
+        };
+
+        let owner_id = download.owner_id();
+        let request = download.request();
+        self.send_frame(owner_id, &request);
+    }
+
+    fn send_frame(&self, to_peer_id: PeerId, frame: &Frame) {
+        let Some(conn) = self.network.get(&to_peer_id) else {
+            return;
+        };
+
+        if let Err(err) = conn.send_frame(frame) {
+            tracing::debug!(?err, ?to_peer_id, "Failed to send frame");
+        }
+    }
+
+    /// Opens connection to the peer entities are exchanged with, if it's not opened yet.
+    fn connect(&mut self, peer_id: PeerId) {
+        match self.network.connect(peer_id) {
+            Ok(Some(conn)) => spawn_local(async move {
+                if let Err(err) = conn.connect().await {
+                    tracing::warn!(?err, ?peer_id, "Failed to connect to peer");
+                }
+            }),
+            Ok(None) => {}
+            Err(err) => tracing::warn!(?err, ?peer_id, "Failed to open connection"),
+        }
+    }
+
+    /// Subscribes to relayed frames, the token must be issued for the room.
+    async fn subscribe_relay(&mut self) {
+        if !self.relay_enabled || self.relay.is_some() {
+            return;
+        }
+
+        let res: Result<_, Error> = async {
+            let relay = rpc_client()?.sub_relay_frames(self.token()).await?;
+            Ok(relay)
+        }
+        .await;
+        match res {
+            Ok(relay) => self.relay = Some(relay),
+            Err(err) => tracing::warn!(?err, "Failed to subscribe to relayed frames"),
+        }
+    }
+
+    /// Asks the server to relay frames exchanged with the peer. Both peers are notified
+    /// with the transport event, then frames are sent through the server.
+    async fn fallback_to_relay(&mut self, peer_id: PeerId) {
+        if !self.relay_enabled {
+            tracing::warn!(
+                ?peer_id,
+                "Direct connection failed and the server has relay disabled"
+            );
+            return;
+        }
+        if !self.relayed_peers.insert(peer_id) {
+            return;
+        }
+
+        let res: Result<(), Error> = async {
+            rpc_client()?.request_relay(self.token(), peer_id).await?;
+            Ok(())
+        }
+        .await;
+        if let Err(err) = res {
+            tracing::warn!(?err, ?peer_id, "Failed to request relay");
+            self.relayed_peers.remove(&peer_id);
+        }
+    }
+
+    async fn report_transfer(&self, entity_id: EntityId, state: TransferState) {
+        let res: Result<(), Error> = async {
+            rpc_client()?
+                .report_transfer(self.token(), entity_id, self.local_peer_id, state)
+                .await?;
+            Ok(())
+        }
+        .await;
+        if let Err(err) = res {
+            tracing::warn!(?err, ?entity_id, ?state, "Failed to report transfer");
+        }
+    }
+
+    /// Reports received bytes without waiting for the server, it publishes them
+    /// throttled anyway.
+    fn report_progress(&self, entity_id: EntityId, transferred: usize) {
+        let token = self.token();
+        let peer_id = self.local_peer_id;
+        spawn_local(async move {
+            let res: Result<(), Error> = async {
+                rpc_client()?
+                    .report_progress(token, entity_id, peer_id, transferred)
+                    .await?;
+                Ok(())
+            }
+            .await;
+            if let Err(err) = res {
+                tracing::debug!(?err, ?entity_id, "Failed to report progress");
+            }
+        });
+    }
+
+    fn token(&self) -> PeerTokenEncoded {
+        self.shared.borrow().token.clone()
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        // Session of the next room page may be stored already
+        Dispatch::<SessionStorage>::new().reduce_mut(|s| {
+            let stored = s
+                .on_resume
+                .as_ref()
+                .is_some_and(|tx| tx.is_connected_to(&self.resumed));
+            if stored {
+                *s = SessionStorage::default();
+            }
+        });
+    }
+}
+
+async fn next_event(events: &mut Subscription<PeerEvent>) -> Result<PeerEvent, ShareError> {
+    let event = events
+        .next()
+        .await
+        .ok_or_else(|| Error::Other(anyhow::anyhow!("Disconnected from API server")))?
+        .map_err(Error::from)?;
+    Ok(event)
+}
+
+/// Waits for the next event of the peer. Returns `None` when the connection to API server
+/// is lost, then never completes until the session is resumed.
+async fn next_session_event(
+    events: &mut Option<Subscription<PeerEvent>>,
+) -> Option<Result<PeerEvent, ShareError>> {
+    let Some(events) = events else {
+        return pending().await;
+    };
+
+    let event = events.next().await?;
+    Some(event.map_err(|err| Error::from(err).into()))
+}
+
+/// Waits for the next relayed frame. Returns `None` when the subscription is closed,
+/// never completes until relayed frames are subscribed.
+async fn next_relay_frame(relay: &mut Option<Subscription<RelayFrame>>) -> Option<RelayFrame> {
+    let Some(relay) = relay else {
+        return pending().await;
+    };
+
+    loop {
+        match relay.next().await? {
+            Ok(frame) => return Some(frame),
+            Err(err) => tracing::warn!(?err, "Failed to decode relayed frame"),
+        }
+    }
+}
+
+/// Relays frames in the order they are sent. Waits and retries while room bandwidth
+/// is exhausted or the receiver is busy, other failures are recovered by the receiver
+/// requesting the entity again.
+async fn relay_frames(
+    shared: SharedHandle,
+    mut frames: mpsc::UnboundedReceiver<(PeerId, Vec<u8>)>,
+) {
+    while let Some((to_peer_id, data)) = frames.next().await {
+        let data = RelayData(data);
+        loop {
+            let token = shared.borrow().token.clone();
+            let res: Result<(), Error> = async {
+                rpc_client()?
+                    .relay_frame(token.clone(), to_peer_id, data.clone())
+                    .await?;
+                Ok(())
+            }
+            .await;
+
+            match res {
+                Ok(()) => break,
+                Err(Error::Server(
+                    drophub::Error::RoomRelayBandwidthLimitExceeded { .. }
+                    | drophub::Error::PeerIsBusy { .. },
+                )) => sleep(RELAY_RETRY_DELAY).await,
+                // Token is refreshed meanwhile
+                Err(Error::Server(drophub::Error::InvalidToken { .. }))
+                    if shared.borrow().token != token => {}
+                Err(err) => {
+                    tracing::warn!(?err, ?to_peer_id, "Failed to relay frame");
+                    break;
+                }
+            }
+        }
+    }
+}
@@ -1,9 +1,16 @@
+use std::{
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
+
+use chrono::{DateTime, Utc};
 use drophub::{
-    AccessTokenEncoded, ClientRole, Entity, FileMeta, PeerId, Room, RoomOptions, TextMeta,
+    ClientRole, Entity, EntityId, EntityKind, EntityMeta, InvitePassphrase, Peer, PeerId,
+    PeerToken, PeerTokenEncoded, Room, RoomOptions, TransferProgress, TransferState,
 };
-use indexmap::IndexMap;
 use lazy_static::lazy_static;
 use uuid::Uuid;
+use yew::Reducible;
 
 use crate::routes::room::query::Query;
 
@@ -11,24 +18,143 @@ use crate::routes::room::query::Query;
 pub(super) struct State {
     pub client: ClientInfo,
     pub room: Room,
+    /// Invites shown to the host: passphrase of the peer until a guest creates the room
+    /// with it, then invites created for the room.
+    pub invites: Vec<InvitePassphrase>,
+    /// Fingerprints of keys exchanged with other peers of the room.
+    pub fingerprints: HashMap<PeerId, String>,
+    /// Progress of running transfers by entity and receiver.
+    pub progress: HashMap<(EntityId, PeerId), TransferProgress>,
     pub loading: bool,
     pub query: Option<Query>,
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientInfo {
+    pub token: PeerTokenEncoded,
+    pub id: PeerId,
+    pub role: ClientRole,
+}
+
+#[derive(Debug, Clone)]
+pub(super) enum Action {
+    /// Room page is opened with the query, placeholder is shown until the room is joined.
+    Start(Query),
+    InvalidQuery,
+    /// Peer is subscribed to events. The host waits for a guest in an empty room until
+    /// the guest redeems the peer invite.
+    Init {
+        token: PeerTokenEncoded,
+        invite_passphrase: InvitePassphrase,
+    },
+    SetToken(PeerTokenEncoded),
+    UpdateRoom(Room),
+    AddInvite(InvitePassphrase),
+    RemoveInvite(InvitePassphrase),
+    SetFingerprint {
+        peer_id: PeerId,
+        fingerprint: String,
+    },
+    SetProgress {
+        entity_id: EntityId,
+        peer_id: PeerId,
+        progress: TransferProgress,
+    },
+}
+
 impl Default for State {
     fn default() -> Self {
         Self::placeholder_host().clone()
     }
 }
 
-impl State {
-    pub fn placeholder(role: ClientRole) -> &'static Self {
-        match role {
-            ClientRole::Host => Self::placeholder_host(),
-            ClientRole::Guest => Self::placeholder_guest(),
+impl Reducible for State {
+    type Action = Action;
+
+    fn reduce(self: Rc<Self>, action: Self::Action) -> Rc<Self> {
+        let mut s = (*self).clone();
+        match action {
+            Action::Start(query) => {
+                s = match query {
+                    Query::Create(_) => Self::placeholder_host().clone(),
+                    Query::Connect(_) => Self::placeholder_guest().clone(),
+                };
+                s.query = Some(query);
+                s.loading = true;
+            }
+            Action::InvalidQuery => {
+                s.query = None;
+                s.loading = true;
+            }
+            Action::Init {
+                token,
+                invite_passphrase,
+            } => {
+                let peer_id = PeerToken::decode(&token)
+                    .map(|decoded| decoded.peer_id)
+                    .unwrap_or(s.client.id);
+                let role = match &s.query {
+                    Some(Query::Create(action)) => {
+                        s.room = empty_room(peer_id, action.capacity);
+                        s.invites = vec![invite_passphrase];
+                        s.loading = false;
+                        ClientRole::Host
+                    }
+                    _ => ClientRole::Guest,
+                };
+                s.client = ClientInfo {
+                    token,
+                    id: peer_id,
+                    role,
+                };
+            }
+            Action::SetToken(token) => s.client.token = token,
+            Action::UpdateRoom(room) => {
+                // Fingerprints of left peers are useless, keys are exchanged again on rejoin
+                s.fingerprints
+                    .retain(|peer_id, _| room.peers.contains_key(peer_id));
+                // Progress is shown only while the transfer is running
+                s.progress.retain(|(entity_id, peer_id), _| {
+                    room.entities.get(entity_id).is_some_and(|entity| {
+                        entity.transfers.get(peer_id) == Some(&TransferState::Transferring)
+                    })
+                });
+                s.client.role = room.role(s.client.id);
+                if s.room.id != room.id {
+                    // Peer invite is redeemed, the room has its own invites
+                    s.invites.clear();
+                }
+                s.room = room;
+                s.loading = false;
+            }
+            Action::AddInvite(invite) => s.invites.push(invite),
+            Action::RemoveInvite(invite) => s.invites.retain(|i| *i != invite),
+            Action::SetFingerprint {
+                peer_id,
+                fingerprint,
+            } => {
+                s.fingerprints.insert(peer_id, fingerprint);
+            }
+            Action::SetProgress {
+                entity_id,
+                peer_id,
+                progress,
+            } => {
+                let transferring = s.room.entities.get(&entity_id).is_some_and(|entity| {
+                    entity.transfers.get(&peer_id) == Some(&TransferState::Transferring)
+                });
+                // Reports published before the transfer is finished may come later
+                if transferring {
+                    s.progress.insert((entity_id, peer_id), progress);
+                }
+            }
         }
+
+        s.into()
     }
+}
 
+impl State {
     pub fn placeholder_host() -> &'static Self {
         lazy_static! {
             static ref PLACEHOLDER_HOST: State = {
@@ -39,73 +165,25 @@ impl State {
                         id: client_id,
                         role: ClientRole::Host,
                     },
-                    room: Room {
-                        id: 123456,
-                        host: client_id,
-                        entities: {
-                            let mut f = IndexMap::new();
-                            f.insert(
-                                73532627,
-                                Entity::File(FileMeta {
-                                    name: "text.txt".into(),
-                                    size: 256,
-                                }),
-                            );
-                            f.insert(
-                                12512517,
-                                Entity::File(FileMeta {
-                                    name: "movie.mp4".into(),
-                                    size: 521425,
-                                }),
-                            );
-                            f.insert(
-                                68854343,
-                                Entity::File(FileMeta {
-                                    name: "music.mp3".into(),
-                                    size: 33521,
-                                }),
-                            );
-                            f.insert(
-                                157899765,
-                                Entity::File(FileMeta {
-                                    name: "word.doc".into(),
-                                    size: 14512,
-                                }),
-                            );
-                            f.insert(
-                                678534342,
-                                Entity::File(FileMeta {
-                                    name: "image.png".into(),
-                                    size: 21512,
-                                }),
-                            );
-                            f.insert(
-                                678534342,
-                                Entity::Text(TextMeta {
-                                    name: "text".into(),
-                                }),
-                            );
-                            f
-                        },
-                        clients: vec![client_id, Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4()],
-                        invites: vec![
-                            "a16DqGr0".into(),
-                            "h52hj5wf".into(),
-                            "oug19b23".into(),
-                            "11jie8fd".into(),
-                        ],
-                        options: RoomOptions {
-                            encryption: false,
-                            capacity: 10,
-                        },
-                    },
+                    room: placeholder_room(
+                        client_id,
+                        [client_id, Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4()],
+                    ),
+                    invites: vec![
+                        "a16DqGr0".into(),
+                        "h52hj5wf".into(),
+                        "oug19b23".into(),
+                        "11jie8fd".into(),
+                    ],
+                    fingerprints: HashMap::new(),
+                    progress: HashMap::new(),
                     loading: true,
                     query: None,
                 }
             };
         }
 
-        &*PLACEHOLDER_HOST
+        &PLACEHOLDER_HOST
     }
 
     pub fn placeholder_guest() -> &'static Self {
@@ -117,81 +195,86 @@ impl State {
                     client: ClientInfo {
                         token: "".into(),
                         id: client_id,
-                        role: ClientRole::Host,
-                    },
-                    room: Room {
-                        id: 123456,
-                        host: host_id,
-                        entities: {
-                            let mut f = IndexMap::new();
-                            f.insert(
-                                73532627,
-                                Entity::File(FileMeta {
-                                    name: "text.txt".into(),
-                                    size: 256,
-                                }),
-                            );
-                            f.insert(
-                                12512517,
-                                Entity::File(FileMeta {
-                                    name: "movie.mp4".into(),
-                                    size: 521425,
-                                }),
-                            );
-                            f.insert(
-                                68854343,
-                                Entity::File(FileMeta {
-                                    name: "music.mp3".into(),
-                                    size: 33521,
-                                }),
-                            );
-                            f.insert(
-                                157899765,
-                                Entity::File(FileMeta {
-                                    name: "word.doc".into(),
-                                    size: 14512,
-                                }),
-                            );
-                            f.insert(
-                                678534342,
-                                Entity::File(FileMeta {
-                                    name: "image.png".into(),
-                                    size: 21512,
-                                }),
-                            );
-                            f.insert(
-                                678534342,
-                                Entity::Text(TextMeta {
-                                    name: "text".into(),
-                                }),
-                            );
-                            f
-                        },
-                        clients: vec![client_id, host_id, Uuid::new_v4(), Uuid::new_v4()],
-                        invites: vec![
-                            "a16DqGr0".into(),
-                            "h52hj5wf".into(),
-                            "oug19b23".into(),
-                            "11jie8fd".into(),
-                        ],
-                        options: RoomOptions {
-                            encryption: false,
-                            capacity: 10,
-                        },
+                        role: ClientRole::Guest,
                     },
+                    room: placeholder_room(
+                        host_id,
+                        [client_id, host_id, Uuid::new_v4(), Uuid::new_v4()],
+                    ),
+                    invites: Vec::new(),
+                    fingerprints: HashMap::new(),
+                    progress: HashMap::new(),
                     loading: true,
                     query: None,
                 }
             };
         }
 
-        &*PLACEHOLDER_GUEST
+        &PLACEHOLDER_GUEST
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
-pub struct ClientInfo {
-    pub token: AccessTokenEncoded,
-    pub id: PeerId,
-    pub role: ClientRole,
+/// Room of the host waiting for the first guest.
+fn empty_room(host: PeerId, capacity: usize) -> Room {
+    Room {
+        id: Uuid::nil(),
+        host,
+        locked: false,
+        options: RoomOptions {
+            capacity,
+            max_invites: capacity,
+            invite_ttl: None,
+        },
+        entities: HashMap::new(),
+        peers: HashMap::from([(host, peer())]),
+    }
+}
+
+fn placeholder_room<I>(host: PeerId, peers: I) -> Room
+where
+    I: IntoIterator<Item = PeerId>,
+{
+    let entities = [
+        (EntityKind::File, "text.txt", 256),
+        (EntityKind::File, "movie.mp4", 521425),
+        (EntityKind::File, "music.mp3", 33521),
+        (EntityKind::File, "word.doc", 14512),
+        (EntityKind::File, "image.png", 21512),
+        (EntityKind::Text, "text", 4),
+    ]
+    .into_iter()
+    .map(|(kind, name, size)| {
+        let entity = Entity {
+            kind,
+            name: name.into(),
+            size,
+            owner_id: host,
+            content: None,
+            manifest: None,
+            meta: EntityMeta::default(),
+            transfers: HashMap::new(),
+        };
+        (Uuid::new_v4(), entity)
+    })
+    .collect();
+
+    Room {
+        id: Uuid::new_v4(),
+        host,
+        locked: false,
+        options: RoomOptions {
+            capacity: 10,
+            max_invites: 10,
+            invite_ttl: None,
+        },
+        entities,
+        peers: peers.into_iter().map(|peer_id| (peer_id, peer())).collect(),
+    }
+}
+
+fn peer() -> Peer {
+    Peer {
+        connected_ts: DateTime::<Utc>::default(),
+        entities: HashSet::new(),
+    }
 }
@@ -0,0 +1,300 @@
+use std::ops::Range;
+
+use chrono::{DateTime, Utc};
+use drophub::{
+    digest::{Digest, DigestHasher},
+    manifest::PATH_SEPARATOR,
+    transfer::{ChunkReceiver, Frame, FrameError},
+    AnnouncedEntity, Entity, EntityId, EntityKind, EntityMeta, PeerId, RpcClient,
+};
+use gloo::{file::File, timers::callback::Timeout};
+use js_sys::{Array, Uint8Array};
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{Blob, HtmlElement};
+
+use crate::{error::Error, hooks::rpc_client, routes::room::session::SharedHandle};
+
+/// Size of file parts read to compute digest.
+const DIGEST_READ_SIZE: u64 = 1024 * 1024;
+/// Object URL of saved file is revoked after the browser starts downloading it.
+const OBJECT_URL_TTL_MS: u32 = 60_000;
+/// Progress of the download is reported to the room at most this often.
+const PROGRESS_INTERVAL_MS: f64 = 500.0;
+/// Acknowledged bytes are moved out of memory to blobs of this size, browsers keep
+/// large blobs on disk.
+const PART_SIZE: usize = 8 * 1024 * 1024;
+
+/// Entity received from its owner. Received bytes are kept in blobs until the entity
+/// is saved.
+pub(super) struct Download {
+    entity: Entity,
+    members: Vec<Member>,
+    receiver: ChunkReceiver,
+    parts: Vec<Blob>,
+    /// Received bytes not moved to blobs yet, starting from the `flushed` offset.
+    buf: Vec<u8>,
+    flushed: u64,
+    /// Digest of the member being received, it's computed over moved bytes.
+    hasher: DigestHasher,
+    /// Digests of received members.
+    digests: Vec<Digest>,
+    /// Chunk was received since the previous stall check.
+    progressed: bool,
+    /// Time of the last progress report in milliseconds since epoch.
+    reported_at: f64,
+    /// Requests sent in a row without receiving anything.
+    retries: u32,
+    /// Saved or failed, waiting for the room to reflect the reported state.
+    finished: bool,
+}
+
+/// File saved from the entity: the file itself or a file of the directory.
+struct Member {
+    name: String,
+    /// Byte range of the file content in the transfer stream.
+    range: Range<u64>,
+    digest: Option<Digest>,
+}
+
+impl Download {
+    pub fn new(entity_id: EntityId, entity: Entity) -> Self {
+        let receiver = ChunkReceiver::new(entity_id, entity.size as u64);
+        let members = match &entity.manifest {
+            Some(manifest) => manifest
+                .ranges()
+                .map(|(entry, range)| Member {
+                    name: directory_member_name(&entity.name, &entry.path),
+                    range,
+                    digest: entry.digest.clone(),
+                })
+                .collect(),
+            None => vec![Member {
+                name: entity.name.clone(),
+                range: 0..entity.size as u64,
+                digest: entity.meta.digest.clone(),
+            }],
+        };
+
+        Self {
+            entity,
+            members,
+            receiver,
+            parts: Vec::new(),
+            buf: Vec::new(),
+            flushed: 0,
+            hasher: DigestHasher::new(),
+            digests: Vec::new(),
+            progressed: false,
+            reported_at: 0.0,
+            retries: 0,
+            finished: false,
+        }
+    }
+
+    pub fn owner_id(&self) -> PeerId {
+        self.entity.owner_id
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.receiver.is_complete()
+    }
+
+    /// Returns request to start or resume the transfer. Bytes received after the last
+    /// acknowledgement are discarded, acknowledged ones are never requested again.
+    pub fn request(&mut self) -> Frame {
+        let request = self.receiver.request();
+        self.buf
+            .truncate((self.receiver.offset() - self.flushed) as usize);
+        request
+    }
+
+    /// Handles received chunk. Returns acknowledgement that must be sent back, if any.
+    pub fn on_chunk(&mut self, offset: u64, data: Vec<u8>) -> Result<Option<Frame>, FrameError> {
+        let ack = self.receiver.on_chunk(offset, data.len() as u64)?;
+        self.buf.extend_from_slice(&data);
+        self.progressed = true;
+        self.retries = 0;
+
+        // Only acknowledged bytes are moved, the rest may be discarded on resume
+        if ack.is_some() && self.buf.len() >= PART_SIZE {
+            if let Err(err) = self.flush() {
+                tracing::warn!(?err, "Failed to move received bytes to blob");
+            }
+        }
+
+        Ok(ack)
+    }
+
+    /// Returns received bytes if it's time to report progress, the completed download
+    /// is reported with its transfer state instead.
+    pub fn progress_to_report(&mut self) -> Option<usize> {
+        let now = js_sys::Date::now();
+        if self.is_complete() || now - self.reported_at < PROGRESS_INTERVAL_MS {
+            return None;
+        }
+
+        self.reported_at = now;
+        Some(self.receiver.offset() as usize)
+    }
+
+    /// Returns number of retries if nothing is received since the previous check,
+    /// the transfer must be requested again then.
+    pub fn check_stalled(&mut self) -> Option<u32> {
+        if std::mem::take(&mut self.progressed) {
+            return None;
+        }
+
+        self.retries += 1;
+        Some(self.retries)
+    }
+
+    /// Marks the download failed, chunks aren't accepted anymore.
+    pub fn fail(&mut self) {
+        self.finished = true;
+        self.buf = Vec::new();
+        self.parts = Vec::new();
+    }
+
+    /// Checks digests of the received files and saves them to the downloads of the browser.
+    /// Files of directory are saved separately.
+    pub fn save(&mut self) -> Result<(), Error> {
+        self.finished = true;
+        self.flush().map_err(js_error)?;
+
+        for (member, actual) in self.members.iter().zip(&self.digests) {
+            if let Some(expected) = &member.digest {
+                if actual != expected {
+                    return Err(Error::Other(anyhow::anyhow!(
+                        "{} is corrupted: digest {actual} doesn't match {expected}",
+                        member.name
+                    )));
+                }
+            }
+        }
+
+        let parts = std::mem::take(&mut self.parts)
+            .into_iter()
+            .collect::<Array>();
+        let content = Blob::new_with_blob_sequence(&parts).map_err(js_error)?;
+        for member in &self.members {
+            let (start, end) = (member.range.start as f64, member.range.end as f64);
+            let file = match &self.entity.meta.mime_type {
+                Some(mime_type) if self.entity.manifest.is_none() => {
+                    content.slice_with_f64_and_f64_and_content_type(start, end, mime_type)
+                }
+                _ => content.slice_with_f64_and_f64(start, end),
+            }
+            .map_err(js_error)?;
+            save_file(&member.name, file).map_err(js_error)?;
+        }
+
+        Ok(())
+    }
+
+    /// Moves received bytes to a new blob and updates digests with them.
+    fn flush(&mut self) -> Result<(), JsValue> {
+        if self.buf.is_empty() {
+            // Digests of empty files are computed as well
+            self.hash(&[]);
+            return Ok(());
+        }
+
+        let part =
+            Blob::new_with_u8_array_sequence(&Array::of1(&Uint8Array::from(self.buf.as_slice())))?;
+        let buf = std::mem::take(&mut self.buf);
+        self.hash(&buf);
+        self.parts.push(part);
+        self.flushed += buf.len() as u64;
+
+        Ok(())
+    }
+
+    /// Updates digests of members with bytes following the flushed ones.
+    fn hash(&mut self, mut data: &[u8]) {
+        let mut offset = self.flushed;
+        while let Some(member) = self.members.get(self.digests.len()) {
+            let len = ((member.range.end - offset) as usize).min(data.len());
+            self.hasher.update(&data[..len]);
+            data = &data[len..];
+            offset += len as u64;
+            if offset < member.range.end {
+                break;
+            }
+
+            self.digests
+                .push(std::mem::take(&mut self.hasher).finalize());
+        }
+    }
+}
+
+/// Browsers save downloads into a single directory, so paths of directory files are
+/// joined into their names.
+fn directory_member_name(dir: &str, path: &str) -> String {
+    format!("{dir}_{}", path.replace(PATH_SEPARATOR, "_"))
+}
+
+/// Announces file selected by the user. The file is kept to be sent on requests.
+pub(super) async fn announce_file(shared: &SharedHandle, file: File) -> Result<EntityId, Error> {
+    let blob = Blob::from(gloo::file::Blob::from(file.clone()));
+    let digest = file_digest(&blob).await.map_err(js_error)?;
+    let mime_type = file.raw_mime_type();
+    let entity = AnnouncedEntity {
+        kind: EntityKind::File,
+        name: file.name(),
+        size: file.size() as usize,
+        content: None,
+        manifest: None,
+        meta: EntityMeta {
+            mime_type: (!mime_type.is_empty()).then_some(mime_type),
+            digest: Some(digest),
+            modified_at: Some(DateTime::<Utc>::from(file.last_modified_time())),
+            thumbnail: None,
+        },
+    };
+
+    let token = shared.borrow().token.clone();
+    let entity_id = rpc_client()?.announce_entity(token, entity).await?;
+    shared.borrow_mut().files.insert(entity_id, blob);
+
+    Ok(entity_id)
+}
+
+/// Computes digest of the file reading it by parts.
+async fn file_digest(blob: &Blob) -> Result<Digest, JsValue> {
+    let size = blob.size() as u64;
+    let mut hasher = DigestHasher::new();
+    let mut offset = 0;
+    while offset < size {
+        let end = (offset + DIGEST_READ_SIZE).min(size);
+        let part = blob.slice_with_f64_and_f64(offset as f64, end as f64)?;
+        let buf = JsFuture::from(part.array_buffer()).await?;
+        hasher.update(&Uint8Array::new(&buf).to_vec());
+        offset = end;
+    }
+
+    Ok(hasher.finalize())
+}
+
+/// Saves file with a temporary link, browsers allow downloads only this way.
+fn save_file(name: &str, file: Blob) -> Result<(), JsValue> {
+    let url = gloo::file::ObjectUrl::from(gloo::file::Blob::from(file));
+
+    let link = gloo::utils::document()
+        .create_element("a")?
+        .dyn_into::<HtmlElement>()?;
+    link.set_attribute("href", &url)?;
+    link.set_attribute("download", name)?;
+    link.click();
+
+    Timeout::new(OBJECT_URL_TTL_MS, move || drop(url)).forget();
+    Ok(())
+}
+
+fn js_error(err: JsValue) -> Error {
+    Error::Other(anyhow::anyhow!("{err:?}"))
+}
@@ -0,0 +1,233 @@
+//! End-to-end encryption of transferred entities.
+//!
+//! Peers exchange ephemeral X25519 public keys via room signaling and derive a session key
+//! with HKDF-SHA256. Each entity is sealed with ChaCha20-Poly1305 under its own key derived
+//! from the session key. Nonce is the chunk offset, so a resent chunk gets the same
+//! ciphertext and nonces are never reused for different data of the same entity.
+//!
+//! Public keys are relayed by the server unauthenticated, so the server can substitute
+//! them. Peers detect it by comparing [`SessionKey::fingerprint`] out of band: with
+//! substituted keys the fingerprints differ.
+
+use chacha20poly1305::{
+    aead::{Aead, KeyInit},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use hkdf::Hkdf;
+use rand_core::OsRng;
+use sha2::Sha256;
+use x25519_dalek::{EphemeralSecret, PublicKey};
+
+use crate::{EntityId, PeerId};
+
+/// Encoded X25519 public key.
+pub type PublicKeyBytes = [u8; 32];
+/// Size of authentication tag appended to every sealed chunk.
+pub const TAG_SIZE: usize = 16;
+
+const SESSION_INFO: &[u8] = b"drophub session";
+const ENTITY_INFO: &[u8] = b"drophub entity";
+const FINGERPRINT_INFO: &[u8] = b"drophub fingerprint";
+
+#[derive(Debug, thiserror::Error, Eq, PartialEq)]
+pub enum CryptoError {
+    #[error("Remote public key is not contributory")]
+    WeakPublicKey,
+    #[error("Failed to seal chunk")]
+    Seal,
+    #[error("Failed to open chunk: data is corrupted or key mismatch")]
+    Open,
+}
+
+/// Local side of the key exchange with a single remote peer.
+pub struct KeyExchange {
+    secret: EphemeralSecret,
+    public_key: PublicKey,
+}
+
+impl Default for KeyExchange {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl KeyExchange {
+    pub fn new() -> Self {
+        let secret = EphemeralSecret::random_from_rng(OsRng);
+        let public_key = PublicKey::from(&secret);
+        Self { secret, public_key }
+    }
+
+    /// Public key that must be sent to the remote peer.
+    pub fn public_key(&self) -> PublicKeyBytes {
+        self.public_key.to_bytes()
+    }
+
+    /// Derives session key shared with the remote peer. Both peers get the same key
+    /// regardless of who initiated the exchange.
+    pub fn derive(
+        self,
+        local_peer_id: PeerId,
+        remote_peer_id: PeerId,
+        remote_public_key: PublicKeyBytes,
+    ) -> Result<SessionKey, CryptoError> {
+        let local_public_key = self.public_key.to_bytes();
+        let shared = self
+            .secret
+            .diffie_hellman(&PublicKey::from(remote_public_key));
+        if !shared.was_contributory() {
+            return Err(CryptoError::WeakPublicKey);
+        }
+
+        let mut salt = [local_public_key, remote_public_key];
+        salt.sort();
+        let mut peer_ids = [local_peer_id, remote_peer_id];
+        peer_ids.sort();
+
+        let info = [
+            SESSION_INFO,
+            peer_ids[0].as_bytes().as_slice(),
+            peer_ids[1].as_bytes().as_slice(),
+        ]
+        .concat();
+
+        let mut key = [0; 32];
+        Hkdf::<Sha256>::new(Some(&salt.concat()), shared.as_bytes())
+            .expand(&info, &mut key)
+            .expect("32 bytes is a valid HKDF-SHA256 output length");
+
+        Ok(SessionKey(key))
+    }
+}
+
+/// Key shared by two peers.
+#[derive(Clone)]
+pub struct SessionKey([u8; 32]);
+
+impl SessionKey {
+    /// Returns cipher of the entity chunks.
+    pub fn entity_cipher(&self, entity_id: EntityId) -> EntityCipher {
+        let info = [ENTITY_INFO, entity_id.as_bytes().as_slice()].concat();
+
+        let mut key = [0; 32];
+        Hkdf::<Sha256>::from_prk(&self.0)
+            .expect("session key is a valid PRK")
+            .expand(&info, &mut key)
+            .expect("32 bytes is a valid HKDF-SHA256 output length");
+
+        EntityCipher {
+            cipher: ChaCha20Poly1305::new(Key::from_slice(&key)),
+        }
+    }
+
+    /// Returns short code both peers show to their users, e.g. `1f2e-3d4c-5b6a`.
+    /// Codes match only if the peers share the same key.
+    pub fn fingerprint(&self) -> String {
+        let mut code = [0; 6];
+        Hkdf::<Sha256>::from_prk(&self.0)
+            .expect("session key is a valid PRK")
+            .expand(FINGERPRINT_INFO, &mut code)
+            .expect("6 bytes is a valid HKDF-SHA256 output length");
+
+        code.chunks(2)
+            .map(|pair| format!("{:02x}{:02x}", pair[0], pair[1]))
+            .collect::<Vec<_>>()
+            .join("-")
+    }
+}
+
+/// Seals and opens chunks of a single entity.
+#[derive(Clone)]
+pub struct EntityCipher {
+    cipher: ChaCha20Poly1305,
+}
+
+impl EntityCipher {
+    /// Encrypts chunk that starts at the offset. Output is [`TAG_SIZE`] bytes longer.
+    pub fn seal(&self, offset: u64, chunk: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        self.cipher
+            .encrypt(&nonce(offset), chunk)
+            .map_err(|_| CryptoError::Seal)
+    }
+
+    /// Decrypts and authenticates chunk that starts at the offset.
+    pub fn open(&self, offset: u64, sealed: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        self.cipher
+            .decrypt(&nonce(offset), sealed)
+            .map_err(|_| CryptoError::Open)
+    }
+}
+
+fn nonce(offset: u64) -> Nonce {
+    let mut nonce = [0; 12];
+    nonce[4..].copy_from_slice(&offset.to_be_bytes());
+    *Nonce::from_slice(&nonce)
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+
+    #[test]
+    fn seal_open() {
+        let (alice_id, bob_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let alice = KeyExchange::new();
+        let bob = KeyExchange::new();
+        let (alice_public_key, bob_public_key) = (alice.public_key(), bob.public_key());
+
+        let alice_key = alice.derive(alice_id, bob_id, bob_public_key).unwrap();
+        let bob_key = bob.derive(bob_id, alice_id, alice_public_key).unwrap();
+
+        let entity_id = Uuid::new_v4();
+        let sealed = alice_key
+            .entity_cipher(entity_id)
+            .seal(16, b"chunk")
+            .unwrap();
+        assert_eq!(sealed.len(), b"chunk".len() + TAG_SIZE);
+        assert_eq!(
+            bob_key.entity_cipher(entity_id).open(16, &sealed).unwrap(),
+            b"chunk"
+        );
+
+        // Another offset or entity
+        assert_eq!(
+            bob_key.entity_cipher(entity_id).open(0, &sealed),
+            Err(CryptoError::Open)
+        );
+        assert_eq!(
+            bob_key.entity_cipher(Uuid::new_v4()).open(16, &sealed),
+            Err(CryptoError::Open)
+        );
+    }
+
+    #[test]
+    fn fingerprint() {
+        let (alice_id, bob_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let (alice, bob) = (KeyExchange::new(), KeyExchange::new());
+        let (alice_public_key, bob_public_key) = (alice.public_key(), bob.public_key());
+
+        let alice_key = alice.derive(alice_id, bob_id, bob_public_key).unwrap();
+        let bob_key = bob.derive(bob_id, alice_id, alice_public_key).unwrap();
+        assert_eq!(alice_key.fingerprint(), bob_key.fingerprint());
+        assert_eq!(alice_key.fingerprint().len(), "1f2e-3d4c-5b6a".len());
+
+        // Server substitutes public keys of both peers with its own
+        let (alice, bob) = (KeyExchange::new(), KeyExchange::new());
+        let (server_to_alice, server_to_bob) = (KeyExchange::new(), KeyExchange::new());
+        let alice_key = alice
+            .derive(alice_id, bob_id, server_to_alice.public_key())
+            .unwrap();
+        let bob_key = bob
+            .derive(bob_id, alice_id, server_to_bob.public_key())
+            .unwrap();
+        assert_ne!(alice_key.fingerprint(), bob_key.fingerprint());
+    }
+
+    #[test]
+    fn weak_public_key() {
+        let res = KeyExchange::new().derive(Uuid::new_v4(), Uuid::new_v4(), [0; 32]);
+        assert!(matches!(res, Err(CryptoError::WeakPublicKey)));
+    }
+}
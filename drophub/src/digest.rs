@@ -0,0 +1,97 @@
+//! Content digests of entities.
+//!
+//! The owner announces digest of the entity and the receiver compares it with the digest
+//! of the received content, so a corrupted or truncated transfer is detected.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest as _, Sha256};
+
+/// Hex encoded digest of entity content.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "algorithm", content = "hex")]
+pub enum Digest {
+    Sha256(String),
+}
+
+impl Digest {
+    pub fn sha256(data: &[u8]) -> Self {
+        let mut hasher = DigestHasher::new();
+        hasher.update(data);
+        hasher.finalize()
+    }
+
+    /// Returns `false` if the digest isn't lowercase hex of the algorithm output length.
+    pub fn is_valid(&self) -> bool {
+        match self {
+            Self::Sha256(hex) => {
+                hex.len() == 64
+                    && hex
+                        .bytes()
+                        .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+            }
+        }
+    }
+}
+
+impl fmt::Display for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Sha256(hex) => write!(f, "sha256:{hex}"),
+        }
+    }
+}
+
+/// Computes digest of content received in chunks.
+#[derive(Debug, Clone, Default)]
+pub struct DigestHasher(Sha256);
+
+impl DigestHasher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    pub fn finalize(self) -> Digest {
+        let hex = self
+            .0
+            .finalize()
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect();
+        Digest::Sha256(hex)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sha256() {
+        let digest = Digest::sha256(b"abc");
+        assert_eq!(
+            digest,
+            Digest::Sha256(
+                "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad".to_owned()
+            )
+        );
+        assert!(digest.is_valid());
+
+        let mut hasher = DigestHasher::new();
+        hasher.update(b"a");
+        hasher.update(b"bc");
+        assert_eq!(hasher.finalize(), digest);
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(!Digest::Sha256("abc".to_owned()).is_valid());
+        assert!(!Digest::Sha256("BA".repeat(32)).is_valid());
+        assert!(!Digest::Sha256("zz".repeat(32)).is_valid());
+    }
+}
@@ -1,11 +1,57 @@
 use jsonrpsee::types::{ErrorObject, ErrorObjectOwned};
 use serde::de::Error as _;
 
-use crate::{EntityId, InvitePassphrase, PeerId, RoomId};
+use crate::{EntityId, InvitePassphrase, PeerId, RoomId, TransferState, PROTOCOL_VERSION};
 
-pub const COMMON_CODE: i32 = -40000;
-pub const NOT_FOUND_CODE: i32 = -40001;
-pub const PERMISSION_DENIED_CODE: i32 = -40002;
+// Every variant has its own code. Codes are stable, hundreds of a code denote its category.
+
+// Internal errors
+pub const OTHER_CODE: i32 = -40000;
+pub const MONGODB_ERROR_CODE: i32 = -40001;
+
+// Not found
+pub const ROOM_NOT_FOUND_CODE: i32 = -40100;
+pub const PEER_NOT_FOUND_CODE: i32 = -40101;
+pub const ENTITY_NOT_FOUND_CODE: i32 = -40102;
+pub const INVITE_NOT_FOUND_CODE: i32 = -40103;
+pub const SESSION_NOT_FOUND_CODE: i32 = -40104;
+pub const TRANSFER_NOT_FOUND_CODE: i32 = -40105;
+
+// Permission denied
+pub const PERMISSION_DENIED_CODE: i32 = -40200;
+pub const ROOM_LOCKED_CODE: i32 = -40201;
+pub const RELAY_DISABLED_CODE: i32 = -40202;
+pub const INVALID_TOKEN_CODE: i32 = -40203;
+pub const TOKEN_EXPIRED_CODE: i32 = -40204;
+
+// Limit exceeded
+pub const ROOM_CAPACITY_LIMIT_EXCEEDED_CODE: i32 = -40300;
+pub const ROOM_INVITES_LIMIT_EXCEEDED_CODE: i32 = -40301;
+pub const ROOM_ENTITIES_LIMIT_EXCEEDED_CODE: i32 = -40302;
+pub const ROOM_ENTITIES_SIZE_LIMIT_EXCEEDED_CODE: i32 = -40303;
+pub const PEER_ENTITIES_LIMIT_EXCEEDED_CODE: i32 = -40304;
+pub const PEER_ENTITIES_SIZE_LIMIT_EXCEEDED_CODE: i32 = -40305;
+pub const RELAY_FRAME_SIZE_LIMIT_EXCEEDED_CODE: i32 = -40306;
+pub const ROOM_RELAY_BANDWIDTH_LIMIT_EXCEEDED_CODE: i32 = -40307;
+pub const ROOM_RELAY_SIZE_LIMIT_EXCEEDED_CODE: i32 = -40308;
+pub const TEXT_SIZE_LIMIT_EXCEEDED_CODE: i32 = -40309;
+pub const THUMBNAIL_SIZE_LIMIT_EXCEEDED_CODE: i32 = -40310;
+pub const MANIFEST_ENTRIES_LIMIT_EXCEEDED_CODE: i32 = -40311;
+
+// Rate limited
+pub const RATE_LIMITED_CODE: i32 = -40400;
+
+// Conflicting state or invalid request
+pub const ENTITY_ALREADY_EXISTS_CODE: i32 = -40500;
+pub const PEER_IS_BUSY_CODE: i32 = -40501;
+pub const SAME_PEER_CODE: i32 = -40502;
+pub const PEER_ALREADY_CONNECTED_CODE: i32 = -40503;
+pub const INVALID_ROOM_OPTIONS_CODE: i32 = -40504;
+pub const UNSUPPORTED_PROTOCOL_VERSION_CODE: i32 = -40505;
+pub const INVALID_ENTITY_CODE: i32 = -40506;
+pub const INVALID_TRANSFER_STATE_CODE: i32 = -40507;
+pub const INVALID_TRANSFER_PROGRESS_CODE: i32 = -40508;
+pub const INVITE_ALREADY_EXISTS_CODE: i32 = -40509;
 
 #[derive(Debug, thiserror::Error, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "snake_case", tag = "kind")]
@@ -30,6 +76,8 @@ pub enum Error {
         room_id: RoomId,
         entity_id: EntityId,
     },
+    #[error("Invite already exists")]
+    InviteAlreadyExists { invite_passphrase: InvitePassphrase },
     #[error("Peer is busy")]
     PeerIsBusy {
         peer_id: PeerId,
@@ -44,6 +92,69 @@ pub enum Error {
     PeerAlreadyConnected { peer_id: PeerId, room_id: RoomId },
     #[error("Invite not found")]
     InviteNotFound { invite_passphrase: InvitePassphrase },
+    #[error("Session not found")]
+    SessionNotFound { peer_id: PeerId },
+    #[error("Transfer not found")]
+    TransferNotFound {
+        entity_id: EntityId,
+        peer_id: PeerId,
+    },
+    #[error("Invalid transfer state")]
+    InvalidTransferState {
+        entity_id: EntityId,
+        peer_id: PeerId,
+        state: TransferState,
+    },
+    #[error("Transferred more than the entity size")]
+    InvalidTransferProgress { transferred: usize, size: usize },
+    #[error("Room is locked")]
+    RoomLocked { room_id: RoomId },
+    #[error("Invalid room options")]
+    InvalidRoomOptions {
+        room_id: RoomId,
+        details: Option<serde_json::Value>,
+    },
+    #[error("Room capacity limit exceeded")]
+    RoomCapacityLimitExceeded { room_id: RoomId, limit: usize },
+    #[error("Room invites limit exceeded")]
+    RoomInvitesLimitExceeded { room_id: RoomId, limit: usize },
+    #[error("Room entities limit exceeded")]
+    RoomEntitiesLimitExceeded { room_id: RoomId, limit: usize },
+    #[error("Room entities size limit exceeded")]
+    RoomEntitiesSizeLimitExceeded { room_id: RoomId, limit: usize },
+    #[error("Peer entities limit exceeded")]
+    PeerEntitiesLimitExceeded { peer_id: PeerId, limit: usize },
+    #[error("Peer entities size limit exceeded")]
+    PeerEntitiesSizeLimitExceeded { peer_id: PeerId, limit: usize },
+    #[error("Text size limit exceeded")]
+    TextSizeLimitExceeded { limit: usize },
+    #[error("Thumbnail size limit exceeded")]
+    ThumbnailSizeLimitExceeded { limit: usize },
+    #[error("Manifest entries limit exceeded")]
+    ManifestEntriesLimitExceeded { limit: usize },
+    #[error("Invalid entity")]
+    InvalidEntity { details: Option<serde_json::Value> },
+    #[error("Relay is disabled")]
+    RelayDisabled,
+    #[error("Relay frame size limit exceeded")]
+    RelayFrameSizeLimitExceeded { limit: usize },
+    #[error("Room relay bandwidth limit exceeded")]
+    RoomRelayBandwidthLimitExceeded { room_id: RoomId, limit: usize },
+    #[error("Room relay size limit exceeded")]
+    RoomRelaySizeLimitExceeded { room_id: RoomId, limit: usize },
+    #[error("Invalid token")]
+    InvalidToken { details: Option<serde_json::Value> },
+    #[error("Token expired")]
+    TokenExpired,
+    #[error("Rate limited, retry after {retry_after} seconds")]
+    RateLimited { retry_after: u64 },
+    #[error("Unsupported protocol version, supported versions are {min_version}..={max_version}")]
+    UnsupportedProtocolVersion {
+        /// Not set if the client didn't send hello.
+        version: Option<u32>,
+        min_version: u32,
+        max_version: u32,
+    },
     #[error("Mongodb error")]
     MongodbError {
         message: String,
@@ -56,13 +167,28 @@ pub enum Error {
 
 impl From<jsonwebtoken::errors::Error> for Error {
     fn from(f: jsonwebtoken::errors::Error) -> Self {
-        Error::Other(f.into())
+        use jsonwebtoken::errors::ErrorKind;
+
+        match f.kind() {
+            ErrorKind::ExpiredSignature => Error::TokenExpired,
+            ErrorKind::InvalidToken
+            | ErrorKind::InvalidSignature
+            | ErrorKind::InvalidAlgorithm
+            | ErrorKind::ImmatureSignature
+            | ErrorKind::MissingRequiredClaim(_)
+            | ErrorKind::Base64(_)
+            | ErrorKind::Json(_)
+            | ErrorKind::Utf8(_) => Error::InvalidToken {
+                details: Some(serde_json::json! { f.to_string() }),
+            },
+            _ => Error::Other(f.into()),
+        }
     }
 }
 
 impl From<Error> for ErrorObjectOwned {
     fn from(f: Error) -> Self {
-        let code = f.jrpc_error_code();
+        let code = f.code();
         let msg = f.jrpc_error_msg();
         let data = f.jrpc_error_data();
         ErrorObject::owned(code, msg, data)
@@ -80,20 +206,60 @@ impl TryFrom<ErrorObjectOwned> for Error {
     }
 }
 
+#[cfg(any(feature = "rpc-client-ws", feature = "rpc-client-wasm"))]
+impl Error {
+    /// Extracts error returned by the server from the client error. Returns `None` for
+    /// transport errors and errors not produced by the server methods.
+    pub fn from_client_error(err: &jsonrpsee::core::ClientError) -> Option<Self> {
+        match err {
+            jsonrpsee::core::ClientError::Call(err) => Self::try_from(err.clone()).ok(),
+            _ => None,
+        }
+    }
+}
+
 impl Error {
-    fn jrpc_error_code(&self) -> i32 {
+    /// Returns JSON-RPC error code of the variant.
+    pub fn code(&self) -> i32 {
         match self {
-            Error::RoomNotFound { .. } => NOT_FOUND_CODE,
-            Error::PeerNotFound { .. } => NOT_FOUND_CODE,
-            Error::EntityNotFound { .. } => NOT_FOUND_CODE,
+            Error::RoomNotFound { .. } => ROOM_NOT_FOUND_CODE,
+            Error::PeerNotFound { .. } => PEER_NOT_FOUND_CODE,
+            Error::EntityNotFound { .. } => ENTITY_NOT_FOUND_CODE,
             Error::PermissionDenied { .. } => PERMISSION_DENIED_CODE,
-            Error::EntityAlreadyExists { .. } => COMMON_CODE,
-            Error::PeerIsBusy { .. } => COMMON_CODE,
-            Error::SamePeer { .. } => COMMON_CODE,
-            Error::PeerAlreadyConnected { .. } => COMMON_CODE,
-            Error::InviteNotFound { .. } => NOT_FOUND_CODE,
-            Error::MongodbError { .. } => COMMON_CODE,
-            Error::Other(_) => COMMON_CODE,
+            Error::EntityAlreadyExists { .. } => ENTITY_ALREADY_EXISTS_CODE,
+            Error::PeerIsBusy { .. } => PEER_IS_BUSY_CODE,
+            Error::SamePeer { .. } => SAME_PEER_CODE,
+            Error::PeerAlreadyConnected { .. } => PEER_ALREADY_CONNECTED_CODE,
+            Error::InviteNotFound { .. } => INVITE_NOT_FOUND_CODE,
+            Error::SessionNotFound { .. } => SESSION_NOT_FOUND_CODE,
+            Error::TransferNotFound { .. } => TRANSFER_NOT_FOUND_CODE,
+            Error::InvalidTransferState { .. } => INVALID_TRANSFER_STATE_CODE,
+            Error::InvalidTransferProgress { .. } => INVALID_TRANSFER_PROGRESS_CODE,
+            Error::InviteAlreadyExists { .. } => INVITE_ALREADY_EXISTS_CODE,
+            Error::RoomLocked { .. } => ROOM_LOCKED_CODE,
+            Error::InvalidRoomOptions { .. } => INVALID_ROOM_OPTIONS_CODE,
+            Error::RoomCapacityLimitExceeded { .. } => ROOM_CAPACITY_LIMIT_EXCEEDED_CODE,
+            Error::RoomInvitesLimitExceeded { .. } => ROOM_INVITES_LIMIT_EXCEEDED_CODE,
+            Error::RoomEntitiesLimitExceeded { .. } => ROOM_ENTITIES_LIMIT_EXCEEDED_CODE,
+            Error::RoomEntitiesSizeLimitExceeded { .. } => ROOM_ENTITIES_SIZE_LIMIT_EXCEEDED_CODE,
+            Error::PeerEntitiesLimitExceeded { .. } => PEER_ENTITIES_LIMIT_EXCEEDED_CODE,
+            Error::PeerEntitiesSizeLimitExceeded { .. } => PEER_ENTITIES_SIZE_LIMIT_EXCEEDED_CODE,
+            Error::TextSizeLimitExceeded { .. } => TEXT_SIZE_LIMIT_EXCEEDED_CODE,
+            Error::ThumbnailSizeLimitExceeded { .. } => THUMBNAIL_SIZE_LIMIT_EXCEEDED_CODE,
+            Error::ManifestEntriesLimitExceeded { .. } => MANIFEST_ENTRIES_LIMIT_EXCEEDED_CODE,
+            Error::InvalidEntity { .. } => INVALID_ENTITY_CODE,
+            Error::RelayDisabled => RELAY_DISABLED_CODE,
+            Error::RelayFrameSizeLimitExceeded { .. } => RELAY_FRAME_SIZE_LIMIT_EXCEEDED_CODE,
+            Error::RoomRelayBandwidthLimitExceeded { .. } => {
+                ROOM_RELAY_BANDWIDTH_LIMIT_EXCEEDED_CODE
+            }
+            Error::RoomRelaySizeLimitExceeded { .. } => ROOM_RELAY_SIZE_LIMIT_EXCEEDED_CODE,
+            Error::InvalidToken { .. } => INVALID_TOKEN_CODE,
+            Error::TokenExpired => TOKEN_EXPIRED_CODE,
+            Error::RateLimited { .. } => RATE_LIMITED_CODE,
+            Error::UnsupportedProtocolVersion { .. } => UNSUPPORTED_PROTOCOL_VERSION_CODE,
+            Error::MongodbError { .. } => MONGODB_ERROR_CODE,
+            Error::Other(_) => OTHER_CODE,
         }
     }
 
@@ -101,8 +267,13 @@ impl Error {
         self.to_string()
     }
 
+    /// Error data is the serialized error with the protocol version of the server.
     fn jrpc_error_data(&self) -> Option<serde_json::Value> {
-        serde_json::to_value(self).ok()
+        let mut data = serde_json::to_value(self).ok()?;
+        if let serde_json::Value::Object(fields) = &mut data {
+            fields.insert("version".to_owned(), PROTOCOL_VERSION.into());
+        }
+        Some(data)
     }
 }
 
@@ -132,3 +303,29 @@ mod serde_other_error {
         Ok(anyhow!(deserialized.details))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+
+    #[test]
+    fn error_object_roundtrip() {
+        let room_id = Uuid::new_v4();
+        let obj = ErrorObjectOwned::from(Error::RoomLocked { room_id });
+        assert_eq!(obj.code(), ROOM_LOCKED_CODE);
+
+        let data: serde_json::Value = serde_json::from_str(obj.data().unwrap().get()).unwrap();
+        assert_eq!(data["kind"], "room_locked");
+        assert_eq!(data["version"], PROTOCOL_VERSION);
+        assert!(matches!(
+            Error::try_from(obj),
+            Ok(Error::RoomLocked { room_id: id }) if id == room_id
+        ));
+
+        let obj = ErrorObjectOwned::from(Error::TokenExpired);
+        assert_eq!(obj.code(), TOKEN_EXPIRED_CODE);
+        assert!(matches!(Error::try_from(obj), Ok(Error::TokenExpired)));
+    }
+}
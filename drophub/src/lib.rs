@@ -1,5 +1,10 @@
+pub mod crypto;
+pub mod digest;
 pub mod error;
+pub mod manifest;
+pub mod passphrase;
 pub mod rpc;
+pub mod transfer;
 pub mod types;
 
 pub use error::*;
@@ -0,0 +1,198 @@
+//! Manifests of directory entities.
+//!
+//! Directory is announced as a single entity listing its files. The files are transferred
+//! as one stream: their content is concatenated in manifest order, so the framed transfer
+//! protocol sends and resumes a directory like a single file.
+
+use std::{collections::HashSet, ops::Range};
+
+use serde::{Deserialize, Serialize};
+
+use crate::digest::Digest;
+
+/// Separates components of manifest paths on every platform.
+pub const PATH_SEPARATOR: char = '/';
+
+const MAX_PATH_LEN: usize = 4096;
+
+#[derive(Debug, thiserror::Error, Eq, PartialEq)]
+pub enum ManifestError {
+    #[error("Manifest is empty")]
+    Empty,
+    #[error("Invalid path: {path}")]
+    InvalidPath { path: String },
+    #[error("Duplicate path: {path}")]
+    DuplicatePath { path: String },
+    #[error("Path {path} is a file and a directory at the same time")]
+    ConflictingPath { path: String },
+    #[error("Total size overflow")]
+    SizeOverflow,
+}
+
+/// Files of directory entity. Empty directories are not listed.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub entries: Vec<ManifestEntry>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// Path relative to the directory, components are separated by [`PATH_SEPARATOR`].
+    pub path: String,
+    /// Size in bytes.
+    pub size: usize,
+    #[serde(default)]
+    pub digest: Option<Digest>,
+}
+
+impl Manifest {
+    /// Checks that every path stays inside the directory and the files don't overlap.
+    pub fn validate(&self) -> Result<(), ManifestError> {
+        if self.entries.is_empty() {
+            return Err(ManifestError::Empty);
+        }
+
+        let mut files = HashSet::new();
+        for entry in &self.entries {
+            if !is_safe_path(&entry.path) {
+                return Err(ManifestError::InvalidPath {
+                    path: entry.path.clone(),
+                });
+            }
+            if !files.insert(entry.path.as_str()) {
+                return Err(ManifestError::DuplicatePath {
+                    path: entry.path.clone(),
+                });
+            }
+        }
+
+        for path in &files {
+            let parents = path
+                .match_indices(PATH_SEPARATOR)
+                .map(|(idx, _)| &path[..idx]);
+            for parent in parents {
+                if files.contains(parent) {
+                    return Err(ManifestError::ConflictingPath {
+                        path: parent.to_owned(),
+                    });
+                }
+            }
+        }
+
+        self.entries
+            .iter()
+            .try_fold(0usize, |size, entry| size.checked_add(entry.size))
+            .ok_or(ManifestError::SizeOverflow)?;
+        Ok(())
+    }
+
+    /// Total size of the files.
+    pub fn size(&self) -> usize {
+        self.entries.iter().map(|entry| entry.size).sum()
+    }
+
+    /// Returns files with byte ranges of their content in the transfer stream.
+    pub fn ranges(&self) -> impl Iterator<Item = (&ManifestEntry, Range<u64>)> {
+        self.entries.iter().scan(0u64, |offset, entry| {
+            let start = *offset;
+            *offset += entry.size as u64;
+            Some((entry, start..*offset))
+        })
+    }
+}
+
+/// Returns `false` if the path is absolute, escapes the directory or can't be created
+/// on every platform.
+pub fn is_safe_path(path: &str) -> bool {
+    if path.is_empty() || path.len() > MAX_PATH_LEN {
+        return false;
+    }
+
+    path.split(PATH_SEPARATOR).all(|component| {
+        !component.is_empty()
+            && component != "."
+            && component != ".."
+            && !component
+                .chars()
+                .any(|ch| ch.is_control() || ch == '\\' || ch == ':')
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manifest(paths: &[&str]) -> Manifest {
+        Manifest {
+            entries: paths
+                .iter()
+                .map(|path| ManifestEntry {
+                    path: (*path).to_owned(),
+                    size: 1,
+                    digest: None,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn safe_path() {
+        for path in ["a", "a/b.txt", "a b/.c", "a/..b"] {
+            assert!(is_safe_path(path), "{path}");
+        }
+        for path in [
+            "",
+            "/a",
+            "a/",
+            "a//b",
+            ".",
+            "./a",
+            "..",
+            "a/../../b",
+            "a\\..\\b",
+            "c:/a",
+            "a\0",
+        ] {
+            assert!(!is_safe_path(path), "{path}");
+        }
+    }
+
+    #[test]
+    fn validate() {
+        assert_eq!(manifest(&["a/b", "a/c", "d"]).validate(), Ok(()));
+        assert_eq!(manifest(&[]).validate(), Err(ManifestError::Empty));
+        assert_eq!(
+            manifest(&["a", "../a"]).validate(),
+            Err(ManifestError::InvalidPath {
+                path: "../a".to_owned()
+            })
+        );
+        assert_eq!(
+            manifest(&["a", "a"]).validate(),
+            Err(ManifestError::DuplicatePath {
+                path: "a".to_owned()
+            })
+        );
+        assert_eq!(
+            manifest(&["a/b", "a/b/c"]).validate(),
+            Err(ManifestError::ConflictingPath {
+                path: "a/b".to_owned()
+            })
+        );
+
+        let mut overflow = manifest(&["a", "b"]);
+        overflow.entries[0].size = usize::MAX;
+        assert_eq!(overflow.validate(), Err(ManifestError::SizeOverflow));
+    }
+
+    #[test]
+    fn ranges() {
+        let mut manifest = manifest(&["a", "b", "c"]);
+        manifest.entries[0].size = 3;
+        manifest.entries[1].size = 0;
+
+        let ranges: Vec<_> = manifest.ranges().map(|(_, range)| range).collect();
+        assert_eq!(ranges, [0..3, 3..3, 3..4]);
+        assert_eq!(manifest.size(), 4);
+    }
+}
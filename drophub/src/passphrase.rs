@@ -0,0 +1,98 @@
+//! Invite passphrase normalization and check characters.
+//!
+//! Passphrase may end with a check character separated by [`SEPARATOR`], e.g. `k7m2xq-4` or
+//! `correct-horse-battery-f`. The check character is computed over the alphanumeric
+//! characters of the passphrase with ISO 7064 MOD 37,36, so clients can reject every single
+//! mistyped character and most swapped neighbours before sending the passphrase to the server.
+
+use crate::InvitePassphrase;
+
+/// Separates words of the passphrase and the check character.
+pub const SEPARATOR: char = '-';
+
+const CHECK_ALPHABET: &[u8; 36] = b"0123456789abcdefghijklmnopqrstuvwxyz";
+
+/// Trims and lowercases typed passphrase.
+pub fn normalize(passphrase: &str) -> InvitePassphrase {
+    passphrase.trim().to_lowercase()
+}
+
+/// Appends check character to the passphrase.
+pub fn with_check_char(passphrase: &str) -> InvitePassphrase {
+    format!("{passphrase}{SEPARATOR}{}", check_char(passphrase))
+}
+
+/// Returns `false` if the passphrase has a check character that doesn't match.
+/// Passphrases without check character are always valid.
+pub fn verify(passphrase: &str) -> bool {
+    match split_check_char(passphrase) {
+        Some((body, check)) => check_char(body) == check,
+        None => true,
+    }
+}
+
+/// Splits passphrase into body and check character, if the last segment is a single character.
+fn split_check_char(passphrase: &str) -> Option<(&str, char)> {
+    let (body, check) = passphrase.rsplit_once(SEPARATOR)?;
+    let mut chars = check.chars();
+    match (chars.next(), chars.next()) {
+        (Some(check), None) if !body.is_empty() => Some((body, check)),
+        _ => None,
+    }
+}
+
+fn check_char(body: &str) -> char {
+    let mut p = 36;
+    for ch in body.chars() {
+        let Some(value) = ch.to_digit(36) else {
+            // Separators and other symbols don't affect the check
+            continue;
+        };
+
+        let mut s = (p + value) % 36;
+        if s == 0 {
+            s = 36;
+        }
+        p = (s * 2) % 37;
+    }
+
+    CHECK_ALPHABET[((37 - p) % 36) as usize] as char
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_char_roundtrip() {
+        for body in ["k7m2xq", "correct-horse-battery", "a"] {
+            let passphrase = with_check_char(body);
+            assert!(verify(&passphrase), "{passphrase}");
+            assert!(verify(&normalize(&passphrase.to_uppercase())));
+        }
+
+        // Without check character
+        assert!(verify("k7m2xq"));
+        assert!(verify("correct-horse-battery"));
+    }
+
+    #[test]
+    fn mistyped_char() {
+        let passphrase = with_check_char("correct-horse-battery");
+        for (idx, original) in passphrase.char_indices() {
+            if original == SEPARATOR {
+                continue;
+            }
+
+            for typo in CHECK_ALPHABET.iter().map(|ch| *ch as char) {
+                if typo == original {
+                    continue;
+                }
+
+                let mut mistyped = passphrase.clone();
+                mistyped.replace_range(idx..idx + 1, &typo.to_string());
+                assert!(!verify(&mistyped), "{mistyped}");
+            }
+        }
+    }
+}
@@ -5,9 +5,15 @@ use jsonrpsee::proc_macros::rpc;
 #[cfg(feature = "rpc-server")]
 use crate::Error;
 use crate::{
-    AnnouncedEntity, EntityId, InvitePassphrase, PeerEvent, PeerId, PeerTokenEncoded, Room,
+    AnnouncedEntity, ClientHello, EntityId, Invite, InvitePassphrase, PeerEvent, PeerId,
+    PeerTokenEncoded, RelayData, RelayFrame, Room, RoomOptions, SignalPayload, TransferState,
 };
 
+/// Version of the RPC protocol. Bumped on breaking changes of methods, events and errors.
+pub const PROTOCOL_VERSION: u32 = 4;
+/// Oldest protocol version of clients the server can talk to.
+pub const MIN_PROTOCOL_VERSION: u32 = 3;
+
 #[cfg_attr(
     all(
         any(feature = "rpc-client-ws", feature = "rpc-client-wasm"),
@@ -30,9 +36,13 @@ use crate::{
     rpc(client, server, namespace = "rpc")
 )]
 pub trait Rpc {
-    /// Invite peer to room.
-    #[method(name = "invite")]
-    fn invite(
+    /// Issues new token with the same scope. The old token is revoked.
+    #[method(name = "refresh_token")]
+    async fn refresh_token(&self, token: PeerTokenEncoded) -> Result<PeerTokenEncoded, Error>;
+
+    /// Invite peer to room. Failed attempts are rate limited per peer and client address.
+    #[method(name = "invite", with_extensions)]
+    async fn invite(
         &self,
         token: PeerTokenEncoded,
         invite_passphrase: InvitePassphrase,
@@ -40,7 +50,7 @@ pub trait Rpc {
 
     /// Announces new entity.
     #[method(name = "announce_entity")]
-    fn announce_entity(
+    async fn announce_entity(
         &self,
         token: PeerTokenEncoded,
         entity: AnnouncedEntity,
@@ -48,13 +58,133 @@ pub trait Rpc {
 
     /// Removes file.
     #[method(name = "remove_entity")]
-    fn remove_entity(&self, token: PeerTokenEncoded, entity_id: EntityId) -> Result<(), Error>;
+    async fn remove_entity(
+        &self,
+        token: PeerTokenEncoded,
+        entity_id: EntityId,
+    ) -> Result<(), Error>;
+
+    /// Asks the owner to send the entity. The owner receives `EntityRequested` event,
+    /// state of the transfer is visible in the room.
+    #[method(name = "request_entity")]
+    async fn request_entity(
+        &self,
+        token: PeerTokenEncoded,
+        entity_id: EntityId,
+    ) -> Result<(), Error>;
+
+    /// Accepts or declines request of the peer for the entity. Only the owner can answer.
+    #[method(name = "answer_entity_request")]
+    async fn answer_entity_request(
+        &self,
+        token: PeerTokenEncoded,
+        entity_id: EntityId,
+        peer_id: PeerId,
+        accept: bool,
+    ) -> Result<(), Error>;
+
+    /// Reports that the accepted transfer is done or failed. Both the owner and
+    /// the receiver can report.
+    #[method(name = "report_transfer")]
+    async fn report_transfer(
+        &self,
+        token: PeerTokenEncoded,
+        entity_id: EntityId,
+        peer_id: PeerId,
+        state: TransferState,
+    ) -> Result<(), Error>;
+
+    /// Reports bytes of the accepted transfer received so far. Both the owner and
+    /// the receiver can report. Reports more frequent than the server publishes them
+    /// are dropped.
+    #[method(name = "report_progress")]
+    async fn report_progress(
+        &self,
+        token: PeerTokenEncoded,
+        entity_id: EntityId,
+        peer_id: PeerId,
+        transferred: usize,
+    ) -> Result<(), Error>;
 
     /// Get current room state.
     #[method(name = "get_room_state")]
-    fn get_room_state(&self, token: PeerTokenEncoded) -> Result<Room, Error>;
+    async fn get_room_state(&self, token: PeerTokenEncoded) -> Result<Room, Error>;
+
+    /// Relays WebRTC signaling message to another peer in the same room.
+    #[method(name = "send_signal")]
+    async fn send_signal(
+        &self,
+        token: PeerTokenEncoded,
+        to_peer_id: PeerId,
+        payload: SignalPayload,
+    ) -> Result<(), Error>;
+
+    /// Switches entities exchange with another peer to relaying through the server.
+    /// Both peers are notified about the transport change.
+    #[method(name = "request_relay")]
+    async fn request_relay(&self, token: PeerTokenEncoded, to_peer_id: PeerId)
+        -> Result<(), Error>;
+
+    /// Relays encoded transfer frame to another peer in the same room.
+    #[method(name = "relay_frame")]
+    async fn relay_frame(
+        &self,
+        token: PeerTokenEncoded,
+        to_peer_id: PeerId,
+        data: RelayData,
+    ) -> Result<(), Error>;
+
+    /// Disconnect peer from the room. Only the host can kick peers.
+    #[method(name = "kick_peer")]
+    async fn kick_peer(&self, token: PeerTokenEncoded, peer_id: PeerId) -> Result<(), Error>;
+
+    /// Disconnect every peer and remove the room. Only the host can close the room.
+    #[method(name = "close_room")]
+    async fn close_room(&self, token: PeerTokenEncoded) -> Result<(), Error>;
+
+    /// Forbid or allow new peers to join the room. Only the host can lock the room.
+    #[method(name = "lock_room")]
+    async fn lock_room(&self, token: PeerTokenEncoded, locked: bool) -> Result<(), Error>;
+
+    /// Change room options. Only the host can change options.
+    #[method(name = "set_room_options")]
+    async fn set_room_options(
+        &self,
+        token: PeerTokenEncoded,
+        options: RoomOptions,
+    ) -> Result<(), Error>;
+
+    /// Create single-use invite to the room. Only the host can create invites.
+    #[method(name = "create_invite")]
+    async fn create_invite(&self, token: PeerTokenEncoded) -> Result<Invite, Error>;
+
+    /// List outstanding invites created for the room.
+    #[method(name = "list_invites")]
+    async fn list_invites(&self, token: PeerTokenEncoded) -> Result<Vec<Invite>, Error>;
+
+    /// Revoke invite created for the room. Only the host can revoke invites.
+    #[method(name = "revoke_invite")]
+    async fn revoke_invite(
+        &self,
+        token: PeerTokenEncoded,
+        invite_passphrase: InvitePassphrase,
+    ) -> Result<(), Error>;
+
+    /// Subscribe to frames relayed from other peers of the room.
+    #[subscription(name = "sub_relay_frames", unsubscribe = "unsub_relay_frames", item = RelayFrame)]
+    async fn sub_relay_frames(&self, token: PeerTokenEncoded) -> SubscriptionResult;
 
-    /// Subscribe to invitation.
+    /// Subscribe to invitation. Subscription is rejected if the hello is missing or
+    /// the client protocol version is not supported.
     #[subscription(name = "sub_peer_events", unsubscribe = "unsub_peer_events", item = PeerEvent)]
-    async fn sub_peer_events(&self) -> SubscriptionResult;
+    async fn sub_peer_events(&self, hello: Option<ClientHello>) -> SubscriptionResult;
+
+    /// Resume peer events subscription closed within the grace period. Events sent to the peer
+    /// in between are replayed after the `Init` event with a refreshed token.
+    #[subscription(name = "resume_session", unsubscribe = "unsub_resume_session", item = PeerEvent)]
+    async fn resume_session(
+        &self,
+        hello: Option<ClientHello>,
+        token: PeerTokenEncoded,
+    ) -> SubscriptionResult;
 }
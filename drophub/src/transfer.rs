@@ -0,0 +1,331 @@
+//! Framed protocol for transferring entities between peers over a data channel.
+//!
+//! Sender splits entity into chunks and keeps at most [`MAX_IN_FLIGHT`] unacknowledged bytes.
+//! Receiver acknowledges received bytes every [`ACK_INTERVAL`] and on completion. After
+//! reconnect the receiver requests transfer from the last acknowledged offset.
+
+use std::ops::Range;
+
+use uuid::Uuid;
+
+use crate::EntityId;
+
+/// Size of chunk payload. Larger messages are not supported by every browser.
+pub const CHUNK_SIZE: u64 = 16 * 1024;
+/// Maximum number of sent but not acknowledged bytes.
+pub const MAX_IN_FLIGHT: u64 = 4 * 1024 * 1024;
+/// Number of received bytes after which receiver sends acknowledgement.
+pub const ACK_INTERVAL: u64 = 1024 * 1024;
+
+/// Size of frame header: kind, entity id, offset and payload length.
+const HEADER_SIZE: usize = 1 + 16 + 8 + 4;
+
+const REQUEST_KIND: u8 = 0;
+const CHUNK_KIND: u8 = 1;
+const ACK_KIND: u8 = 2;
+
+#[derive(Debug, thiserror::Error, Eq, PartialEq)]
+pub enum FrameError {
+    #[error("Frame is too short: {len} bytes")]
+    TooShort { len: usize },
+    #[error("Unknown frame kind: {kind}")]
+    UnknownKind { kind: u8 },
+    #[error("Payload length mismatch: expected {expected}, actual {actual}")]
+    LengthMismatch { expected: usize, actual: usize },
+    #[error("Unexpected offset: expected {expected}, actual {actual}")]
+    UnexpectedOffset { expected: u64, actual: u64 },
+    #[error("Offset {offset} is out of entity size {size}")]
+    OutOfBounds { offset: u64, size: u64 },
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Frame {
+    /// Receiver asks to send the entity starting from offset.
+    Request { entity_id: EntityId, offset: u64 },
+    /// Part of the entity starting from offset.
+    Chunk {
+        entity_id: EntityId,
+        offset: u64,
+        data: Vec<u8>,
+    },
+    /// Receiver got every byte before offset.
+    Ack { entity_id: EntityId, offset: u64 },
+}
+
+impl Frame {
+    pub fn entity_id(&self) -> EntityId {
+        match self {
+            Frame::Request { entity_id, .. } => *entity_id,
+            Frame::Chunk { entity_id, .. } => *entity_id,
+            Frame::Ack { entity_id, .. } => *entity_id,
+        }
+    }
+
+    /// Encodes frame to binary format: kind (1 byte), entity id (16 bytes),
+    /// offset (8 bytes, BE), payload length (4 bytes, BE) and payload.
+    pub fn encode(&self) -> Vec<u8> {
+        let (kind, entity_id, offset, data) = match self {
+            Frame::Request { entity_id, offset } => (REQUEST_KIND, entity_id, offset, &[][..]),
+            Frame::Chunk {
+                entity_id,
+                offset,
+                data,
+            } => (CHUNK_KIND, entity_id, offset, data.as_slice()),
+            Frame::Ack { entity_id, offset } => (ACK_KIND, entity_id, offset, &[][..]),
+        };
+
+        let mut buf = Vec::with_capacity(HEADER_SIZE + data.len());
+        buf.push(kind);
+        buf.extend_from_slice(entity_id.as_bytes());
+        buf.extend_from_slice(&offset.to_be_bytes());
+        buf.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        buf.extend_from_slice(data);
+        buf
+    }
+
+    /// Decodes frame from binary format.
+    pub fn decode(buf: &[u8]) -> Result<Self, FrameError> {
+        if buf.len() < HEADER_SIZE {
+            return Err(FrameError::TooShort { len: buf.len() });
+        }
+
+        let (header, data) = buf.split_at(HEADER_SIZE);
+        let kind = header[0];
+        let entity_id = Uuid::from_bytes(header[1..17].try_into().unwrap());
+        let offset = u64::from_be_bytes(header[17..25].try_into().unwrap());
+        let len = u32::from_be_bytes(header[25..29].try_into().unwrap()) as usize;
+        if len != data.len() {
+            return Err(FrameError::LengthMismatch {
+                expected: len,
+                actual: data.len(),
+            });
+        }
+
+        match kind {
+            REQUEST_KIND => Ok(Frame::Request { entity_id, offset }),
+            CHUNK_KIND => Ok(Frame::Chunk {
+                entity_id,
+                offset,
+                data: data.to_vec(),
+            }),
+            ACK_KIND => Ok(Frame::Ack { entity_id, offset }),
+            kind => Err(FrameError::UnknownKind { kind }),
+        }
+    }
+}
+
+/// Sending side of the entity transfer.
+#[derive(Debug, Clone)]
+pub struct ChunkSender {
+    entity_id: EntityId,
+    size: u64,
+    next_offset: u64,
+    acked_offset: u64,
+}
+
+impl ChunkSender {
+    pub fn new(entity_id: EntityId, size: u64) -> Self {
+        Self {
+            entity_id,
+            size,
+            next_offset: 0,
+            acked_offset: 0,
+        }
+    }
+
+    pub fn entity_id(&self) -> EntityId {
+        self.entity_id
+    }
+
+    pub fn acked_offset(&self) -> u64 {
+        self.acked_offset
+    }
+
+    /// Restarts transfer from the offset requested by receiver.
+    pub fn resume(&mut self, offset: u64) -> Result<(), FrameError> {
+        if offset > self.size {
+            return Err(FrameError::OutOfBounds {
+                offset,
+                size: self.size,
+            });
+        }
+
+        self.next_offset = offset;
+        self.acked_offset = offset;
+        Ok(())
+    }
+
+    /// Returns byte range of the next chunk. Returns `None` if everything is sent
+    /// or too many bytes are not acknowledged yet.
+    pub fn next_chunk(&mut self) -> Option<Range<u64>> {
+        if self.next_offset >= self.size || self.next_offset - self.acked_offset >= MAX_IN_FLIGHT {
+            return None;
+        }
+
+        let start = self.next_offset;
+        let end = (start + CHUNK_SIZE).min(self.size);
+        self.next_offset = end;
+        Some(start..end)
+    }
+
+    /// Handles acknowledgement from receiver. Stale acknowledgements are ignored.
+    pub fn on_ack(&mut self, offset: u64) -> Result<(), FrameError> {
+        if offset > self.next_offset {
+            return Err(FrameError::OutOfBounds {
+                offset,
+                size: self.next_offset,
+            });
+        }
+
+        self.acked_offset = self.acked_offset.max(offset);
+        Ok(())
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.acked_offset == self.size
+    }
+}
+
+/// Receiving side of the entity transfer.
+#[derive(Debug, Clone)]
+pub struct ChunkReceiver {
+    entity_id: EntityId,
+    size: u64,
+    offset: u64,
+    acked_offset: u64,
+}
+
+impl ChunkReceiver {
+    pub fn new(entity_id: EntityId, size: u64) -> Self {
+        Self {
+            entity_id,
+            size,
+            offset: 0,
+            acked_offset: 0,
+        }
+    }
+
+    pub fn entity_id(&self) -> EntityId {
+        self.entity_id
+    }
+
+    /// Number of received bytes.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Returns request to start or resume the transfer. Bytes received after the last
+    /// acknowledgement are requested again, caller must discard them.
+    pub fn request(&mut self) -> Frame {
+        self.offset = self.acked_offset;
+        Frame::Request {
+            entity_id: self.entity_id,
+            offset: self.acked_offset,
+        }
+    }
+
+    /// Handles received chunk. Returns acknowledgement that must be sent back, if any.
+    pub fn on_chunk(&mut self, offset: u64, len: u64) -> Result<Option<Frame>, FrameError> {
+        if offset != self.offset {
+            return Err(FrameError::UnexpectedOffset {
+                expected: self.offset,
+                actual: offset,
+            });
+        }
+        if offset + len > self.size {
+            return Err(FrameError::OutOfBounds {
+                offset: offset + len,
+                size: self.size,
+            });
+        }
+
+        self.offset += len;
+        if self.offset - self.acked_offset >= ACK_INTERVAL || self.is_complete() {
+            self.acked_offset = self.offset;
+            return Ok(Some(Frame::Ack {
+                entity_id: self.entity_id,
+                offset: self.offset,
+            }));
+        }
+
+        Ok(None)
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.offset == self.size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_roundtrip() {
+        let entity_id = Uuid::new_v4();
+        let frames = [
+            Frame::Request {
+                entity_id,
+                offset: 1,
+            },
+            Frame::Chunk {
+                entity_id,
+                offset: u64::MAX,
+                data: vec![1, 2, 3],
+            },
+            Frame::Ack {
+                entity_id,
+                offset: 123,
+            },
+        ];
+
+        for frame in frames {
+            assert_eq!(Frame::decode(&frame.encode()), Ok(frame));
+        }
+
+        assert_eq!(
+            Frame::decode(&[CHUNK_KIND]),
+            Err(FrameError::TooShort { len: 1 })
+        );
+    }
+
+    #[test]
+    fn transfer_resume() {
+        let entity_id = Uuid::new_v4();
+        let size = MAX_IN_FLIGHT * 2 + 1;
+        let mut sender = ChunkSender::new(entity_id, size);
+        let mut receiver = ChunkReceiver::new(entity_id, size);
+
+        // Window is full without acknowledgements
+        let mut sent = 0;
+        while let Some(range) = sender.next_chunk() {
+            sent += range.end - range.start;
+        }
+        assert_eq!(sent, MAX_IN_FLIGHT);
+
+        // Receiver got half of the window and connection is lost
+        let mut offset = 0;
+        while offset < MAX_IN_FLIGHT / 2 {
+            receiver.on_chunk(offset, CHUNK_SIZE).unwrap();
+            offset += CHUNK_SIZE;
+        }
+        receiver.on_chunk(offset, CHUNK_SIZE).unwrap();
+
+        let Frame::Request { offset, .. } = receiver.request() else {
+            unreachable!()
+        };
+        assert_eq!(offset, MAX_IN_FLIGHT / 2);
+        sender.resume(offset).unwrap();
+
+        while !sender.is_complete() {
+            let range = sender.next_chunk().unwrap();
+            if let Some(Frame::Ack { offset, .. }) = receiver
+                .on_chunk(range.start, range.end - range.start)
+                .unwrap()
+            {
+                sender.on_ack(offset).unwrap();
+            }
+        }
+        assert!(receiver.is_complete());
+    }
+}
@@ -1,13 +1,14 @@
 use std::collections::{HashMap, HashSet};
 
-use chrono::{DateTime, Utc};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chrono::{DateTime, SubsecRound, Utc};
 #[cfg(feature = "rpc-server")]
 use jsonrpsee::SubscriptionMessage;
-use jsonwebtoken::{DecodingKey, EncodingKey, Header, Validation};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use crate::Error;
+use crate::{crypto::PublicKeyBytes, digest::Digest, manifest::Manifest, Error, PROTOCOL_VERSION};
 
 pub type PeerId = Uuid;
 pub type RoomId = Uuid;
@@ -18,10 +19,51 @@ pub type PeerTokenEncoded = String;
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct Room {
     pub id: RoomId,
+    /// Peer that created the room and moderates it.
+    pub host: PeerId,
+    /// New peers can't join locked room.
+    pub locked: bool,
+    pub options: RoomOptions,
     pub entities: HashMap<EntityId, Entity>,
     pub peers: HashMap<PeerId, Peer>,
 }
 
+impl Room {
+    pub fn role(&self, peer_id: PeerId) -> ClientRole {
+        if peer_id == self.host {
+            ClientRole::Host
+        } else {
+            ClientRole::Guest
+        }
+    }
+}
+
+/// Room settings changed by the host.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct RoomOptions {
+    /// Maximum number of peers in the room.
+    pub capacity: usize,
+    /// Maximum number of outstanding invites created for the room.
+    pub max_invites: usize,
+    /// Lifetime of invites created for the room, in seconds. Server default is used if not set.
+    pub invite_ttl: Option<u64>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Invite {
+    pub passphrase: InvitePassphrase,
+    /// Room the invite is created for. Not set for invites of peers.
+    pub room_id: Option<RoomId>,
+    pub expire_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClientRole {
+    Host,
+    Guest,
+}
+
 #[cfg(feature = "rpc-server")]
 impl TryFrom<Room> for SubscriptionMessage {
     type Error = serde_json::Error;
@@ -45,22 +87,110 @@ impl TryFrom<&Room> for SubscriptionMessage {
 pub enum EntityKind {
     File,
     Text,
+    /// Files transferred as a single stream, listed in the manifest.
+    Directory,
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
-#[serde(tag = "kind")]
 pub struct Entity {
     pub kind: EntityKind,
     pub name: String,
     pub size: usize,
     pub owner_id: PeerId,
+    /// Content of text entity, delivered inline with the room.
+    #[serde(default)]
+    pub content: Option<String>,
+    /// Files of directory entity.
+    #[serde(default)]
+    pub manifest: Option<Manifest>,
+    #[serde(default)]
+    pub meta: EntityMeta,
+    /// Transfers to the peers that requested the entity.
+    #[serde(default)]
+    pub transfers: HashMap<PeerId, TransferState>,
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct AnnouncedEntity {
     pub kind: EntityKind,
     pub name: String,
+    /// Size in bytes. Size of text entity is the length of its content, size of directory
+    /// is the total size of its files.
     pub size: usize,
+    /// Content of text entity. Files are transferred between peers instead.
+    #[serde(default)]
+    pub content: Option<String>,
+    /// Files of directory entity.
+    #[serde(default)]
+    pub manifest: Option<Manifest>,
+    #[serde(default)]
+    pub meta: EntityMeta,
+}
+
+/// State of entity transfer to the peer that requested it.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransferState {
+    /// Waiting for the owner to accept the request.
+    Pending,
+    /// Accepted by the owner.
+    Transferring,
+    Done,
+    Failed,
+    /// Declined by the owner.
+    Declined,
+}
+
+impl TransferState {
+    /// Returns `true` if the transfer is over, so the entity can be requested again.
+    pub fn is_finished(self) -> bool {
+        matches!(self, Self::Done | Self::Failed | Self::Declined)
+    }
+}
+
+/// Progress of entity transfer to the peer that requested it.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct TransferProgress {
+    /// Bytes received by the peer.
+    pub transferred: usize,
+    /// Size of the entity.
+    pub size: usize,
+    /// Bytes per second, smoothed over recent reports.
+    pub throughput: u64,
+    /// Seconds left until the transfer is done. Unknown until throughput is measured.
+    pub eta: Option<u64>,
+}
+
+/// Optional metadata provided by the entity owner.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EntityMeta {
+    /// MIME type of the content, e.g. `image/png`.
+    pub mime_type: Option<String>,
+    /// Digest the receiver checks the transferred content against.
+    pub digest: Option<Digest>,
+    /// Last modification time of the file.
+    pub modified_at: Option<DateTime<Utc>>,
+    pub thumbnail: Option<Thumbnail>,
+}
+
+/// Small preview image of the entity, serialized as base64 string.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Thumbnail {
+    /// MIME type of the image, e.g. `image/png`.
+    pub mime_type: String,
+    pub data: RelayData,
+}
+
+impl Thumbnail {
+    /// Returns `data:` URL to display the image.
+    pub fn data_url(&self) -> String {
+        format!(
+            "data:{};base64,{}",
+            self.mime_type,
+            BASE64.encode(&self.data.0)
+        )
+    }
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
@@ -69,11 +199,51 @@ pub struct Peer {
     pub entities: HashSet<EntityId>,
 }
 
+/// Sent by the client when it subscribes to peer events.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ClientHello {
+    /// Protocol version the client is built with.
+    pub version: u32,
+    /// Optional features the client wants to use.
+    pub capabilities: Vec<Capability>,
+}
+
+impl ClientHello {
+    pub fn new(capabilities: Vec<Capability>) -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            capabilities,
+        }
+    }
+}
+
+impl Default for ClientHello {
+    fn default() -> Self {
+        Self::new(Vec::new())
+    }
+}
+
+/// Optional features of the protocol.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Capability {
+    /// Frames can be relayed through the server.
+    Relay,
+    /// Peers exchange public keys to encrypt entities end-to-end.
+    Encryption,
+    /// Peer receives progress of transfers in the room.
+    Progress,
+}
+
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub enum PeerEvent {
     Init {
         token: PeerTokenEncoded,
         invite_passphrase: InvitePassphrase,
+        /// Protocol version of the server.
+        version: u32,
+        /// Capabilities requested by the client and supported by the server.
+        capabilities: Vec<Capability>,
     },
     Invite {
         token: PeerTokenEncoded,
@@ -81,6 +251,119 @@ pub enum PeerEvent {
     UpdateRoom {
         room: Room,
     },
+    /// Signaling message sent by another peer in the room.
+    Signal {
+        from_peer_id: PeerId,
+        payload: SignalPayload,
+    },
+    /// Another peer asks the owner to send the entity.
+    EntityRequested {
+        by: PeerId,
+        entity_id: EntityId,
+    },
+    /// Progress of entity transfer reported by the owner or the receiver. Sent only
+    /// to peers with [`Capability::Progress`].
+    TransferProgress {
+        entity_id: EntityId,
+        peer_id: PeerId,
+        progress: TransferProgress,
+    },
+    /// Transport used to exchange entities with the peer is changed.
+    Transport {
+        peer_id: PeerId,
+        transport: Transport,
+    },
+    /// Peer was disconnected from the room by the server.
+    Disconnect {
+        room_id: Option<RoomId>,
+        reason: DisconnectReason,
+    },
+}
+
+/// WebRTC signaling message relayed by the server as is.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "kind")]
+pub enum SignalPayload {
+    Offer {
+        sdp: String,
+    },
+    Answer {
+        sdp: String,
+    },
+    IceCandidate {
+        candidate: String,
+        sdp_mid: Option<String>,
+        sdp_m_line_index: Option<u16>,
+    },
+    /// Ephemeral key for end-to-end encryption of entities.
+    PublicKey {
+        public_key: PublicKeyBytes,
+    },
+}
+
+/// Way entities are exchanged between two peers.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Transport {
+    /// Direct WebRTC data channel.
+    P2p,
+    /// Frames are relayed through the server.
+    Relay,
+}
+
+/// Binary data serialized as base64 string, e.g. encoded transfer frame.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct RelayData(pub Vec<u8>);
+
+impl Serialize for RelayData {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&BASE64.encode(&self.0))
+    }
+}
+
+impl<'de> Deserialize<'de> for RelayData {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let encoded = String::deserialize(deserializer)?;
+        BASE64
+            .decode(encoded)
+            .map(RelayData)
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// Frame relayed by the server from another peer.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct RelayFrame {
+    pub from_peer_id: PeerId,
+    pub data: RelayData,
+}
+
+#[cfg(feature = "rpc-server")]
+impl TryFrom<RelayFrame> for SubscriptionMessage {
+    type Error = serde_json::Error;
+
+    fn try_from(f: RelayFrame) -> Result<Self, Self::Error> {
+        SubscriptionMessage::from_json(&f)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DisconnectReason {
+    /// Room lifetime is over.
+    RoomExpired,
+    /// Peer lifetime is over, subscription is closed.
+    PeerExpired,
+    /// Peer is kicked by the host.
+    Kicked,
+    /// Room is closed by the host.
+    RoomClosed,
 }
 
 #[cfg(feature = "rpc-server")]
@@ -105,47 +388,96 @@ impl TryFrom<&PeerEvent> for SubscriptionMessage {
 pub struct PeerToken {
     pub peer_id: PeerId,
     pub room_id: Option<RoomId>,
-    #[serde(with = "chrono::serde::ts_milliseconds_option")]
-    pub exp: Option<DateTime<Utc>>,
+    /// Unique token id, used to revoke the token.
+    pub jti: Uuid,
+    #[serde(with = "chrono::serde::ts_seconds")]
+    pub iat: DateTime<Utc>,
+    #[serde(with = "chrono::serde::ts_seconds")]
+    pub nbf: DateTime<Utc>,
+    #[serde(with = "chrono::serde::ts_seconds")]
+    pub exp: DateTime<Utc>,
 }
 
 impl PeerToken {
-    /// Encodes token to JWT format.
+    /// Creates token that is valid from now for the specified time.
+    pub fn new(peer_id: PeerId, room_id: Option<RoomId>, ttl: chrono::Duration) -> Self {
+        // Claims are encoded in seconds
+        let now = Utc::now().trunc_subsecs(0);
+        Self {
+            peer_id,
+            room_id,
+            jti: Uuid::new_v4(),
+            iat: now,
+            nbf: now,
+            exp: now + ttl,
+        }
+    }
+
+    /// Returns time left until the token should be refreshed, when a fifth of its lifetime
+    /// remains. Zero if the time has come already.
+    pub fn refresh_delay(&self) -> std::time::Duration {
+        let refresh_at = self.exp - (self.exp - self.iat) / 5;
+        (refresh_at - Utc::now()).to_std().unwrap_or_default()
+    }
+
+    /// Encodes token to JWT format signed by HMAC secret.
     pub fn encode(&self, secret: &str) -> Result<String, Error> {
-        let tok = jsonwebtoken::encode(
+        self.encode_with_key(
             &Header::default(),
-            self,
             &EncodingKey::from_secret(secret.as_bytes()),
-        )?;
+        )
+    }
+
+    /// Encodes token to JWT format signed by the key. Header specifies algorithm and key id.
+    pub fn encode_with_key(&self, header: &Header, key: &EncodingKey) -> Result<String, Error> {
+        let tok = jsonwebtoken::encode(header, self, key)?;
 
         tracing::debug!("Encoded peer token: {:?}", tok);
         Ok(tok)
     }
 
-    /// Decodes token from JWT format and verify signature by secret key.
+    /// Decodes token from JWT format, verifies signature by HMAC secret and validity period.
     pub fn decode_and_verify(token: &str, secret: &str) -> Result<Self, Error> {
+        Self::decode_and_verify_with_key(
+            token,
+            Algorithm::HS256,
+            &DecodingKey::from_secret(secret.as_bytes()),
+        )
+    }
+
+    /// Decodes token from JWT format, verifies signature by the key and validity period.
+    pub fn decode_and_verify_with_key(
+        token: &str,
+        algorithm: Algorithm,
+        key: &DecodingKey,
+    ) -> Result<Self, Error> {
         let validation = {
-            let mut v = Validation::default();
-            v.set_required_spec_claims::<&str>(&[]);
+            let mut v = Validation::new(algorithm);
+            v.set_required_spec_claims(&["exp", "nbf"]);
+            v.validate_nbf = true;
+            // Tokens are issued by the server replicas, their clocks are expected to be in sync
+            v.leeway = 0;
             v
         };
 
-        let tok = jsonwebtoken::decode::<Self>(
-            token,
-            &DecodingKey::from_secret(secret.as_bytes()),
-            &validation,
-        )
-        .map(|token_data| token_data.claims)?;
+        let tok = jsonwebtoken::decode::<Self>(token, key, &validation)
+            .map(|token_data| token_data.claims)?;
 
         tracing::debug!("Decoded peer token: {:?}", tok);
         Ok(tok)
     }
 
+    /// Returns id of the key the token is signed with, without verifying.
+    pub fn key_id(token: &str) -> Result<Option<String>, Error> {
+        Ok(jsonwebtoken::decode_header(token)?.kid)
+    }
+
     /// Decodes token from JWT format without verifying.
     pub fn decode(token: &str) -> Result<Self, Error> {
         let validation = {
             let mut v = Validation::default();
             v.set_required_spec_claims::<&str>(&[]);
+            v.validate_exp = false;
             v.insecure_disable_signature_validation();
             v
         };